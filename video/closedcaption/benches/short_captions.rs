@@ -0,0 +1,107 @@
+// Copyright (C) 2020 Mathieu Duponchelle <mathieu@centricular.com>
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Library General Public
+// License as published by the Free Software Foundation; either
+// version 2 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Library General Public License for more details.
+//
+// You should have received a copy of the GNU Library General Public
+// License along with this library; if not, write to the
+// Free Software Foundation, Inc., 51 Franklin Street, Suite 500,
+// Boston, MA 02110-1335, USA.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/* Counts the allocations made on the Rust heap, where the Vec of byte
+ * pairs of each caption lives, growing it counts as one too */
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/* Half a second apart, leaving room for their byte pairs */
+const N_CAPTIONS: u64 = 1000;
+const CAPTION_INTERVAL: u64 = 500_000_000;
+
+fn init() {
+    use std::sync::Once;
+    static INIT: Once = Once::new();
+
+    INIT.call_once(|| {
+        gst::init().unwrap();
+        gstrsclosedcaption::plugin_register_static().unwrap();
+    });
+}
+
+/* Pushes N_CAPTIONS short captions starting from caption first */
+fn push_captions(h: &mut gst_check::Harness, first: u64) {
+    for i in first..first + N_CAPTIONS {
+        let mut buf = gst::Buffer::from_slice("Hi");
+        let buf_ref = buf.get_mut().unwrap();
+        buf_ref.set_pts(((i + 1) * CAPTION_INTERVAL).into());
+        buf_ref.set_duration((CAPTION_INTERVAL / 2).into());
+
+        assert_eq!(h.push(buf), Ok(gst::FlowSuccess::Ok));
+        while h.buffers_in_queue() > 0 {
+            let _ = h.pull().unwrap();
+        }
+    }
+}
+
+fn bench_short_captions(c: &mut Criterion) {
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=pop-on");
+    h.set_src_caps_str("text/x-raw");
+
+    /* Leave out the allocations only made once, such as the first
+     * growth of the Vec of byte pairs */
+    push_captions(&mut h, 0);
+
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    push_captions(&mut h, N_CAPTIONS);
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+
+    println!(
+        "{} short captions, {:.1} allocations per caption",
+        N_CAPTIONS,
+        allocations as f64 / N_CAPTIONS as f64
+    );
+
+    let mut first = 2 * N_CAPTIONS;
+
+    c.bench_function("tttocea608_short_captions", |b| {
+        b.iter(|| {
+            push_captions(&mut h, first);
+            first += N_CAPTIONS;
+        })
+    });
+}
+
+criterion_group!(benches, bench_short_captions);
+criterion_main!(benches);
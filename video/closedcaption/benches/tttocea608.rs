@@ -0,0 +1,101 @@
+// Copyright (C) 2020 Mathieu Duponchelle <mathieu@centricular.com>
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Library General Public
+// License as published by the Free Software Foundation; either
+// version 2 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Library General Public License for more details.
+//
+// You should have received a copy of the GNU Library General Public
+// License along with this library; if not, write to the
+// Free Software Foundation, Inc., 51 Franklin Street, Suite 500,
+// Boston, MA 02110-1335, USA.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use std::collections::HashSet;
+
+const N_CAPTIONS: u64 = 100;
+
+fn init() {
+    use std::sync::Once;
+    static INIT: Once = Once::new();
+
+    INIT.call_once(|| {
+        gst::init().unwrap();
+        gstrsclosedcaption::plugin_register_static().unwrap();
+    });
+}
+
+fn new_caption(pts: gst::ClockTime) -> gst::Buffer {
+    let mut buf = gst::Buffer::from_slice("Hello World\nHow are you?");
+    let buf_ref = buf.get_mut().unwrap();
+    buf_ref.set_pts(pts);
+    buf_ref.set_duration(gst::SECOND);
+    buf
+}
+
+/* The byte pairs are GstBuffers allocated by GLib, which a counting
+ * global allocator wouldn't see. Buffers from the cc_data pool are
+ * allocated once and then keep their address when reused, the others
+ * are allocated for each byte pair */
+fn bench_cc_data_allocations(c: &mut Criterion) {
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=pop-on");
+    h.set_src_caps_str("text/x-raw");
+
+    let mut pooled = HashSet::new();
+    let mut n_unpooled = 0;
+    let mut n_pairs = 0;
+
+    for i in 0..N_CAPTIONS {
+        assert_eq!(
+            h.push(new_caption((2 * i + 1) * gst::SECOND)),
+            Ok(gst::FlowSuccess::Ok)
+        );
+
+        while h.buffers_in_queue() > 0 {
+            let buffer = h.pull().unwrap();
+            n_pairs += 1;
+
+            if unsafe { (*buffer.as_ptr()).pool.is_null() } {
+                n_unpooled += 1;
+            } else {
+                pooled.insert(buffer.as_ptr() as usize);
+            }
+        }
+    }
+
+    println!(
+        "{} captions, {} byte pairs, {} byte pair allocations",
+        N_CAPTIONS,
+        n_pairs,
+        pooled.len() + n_unpooled
+    );
+
+    let mut pts = (2 * N_CAPTIONS + 1) * gst::SECOND;
+
+    c.bench_function("tttocea608_pop_on", |b| {
+        b.iter_batched(
+            || {
+                let buf = new_caption(pts);
+                pts += 2 * gst::SECOND;
+                buf
+            },
+            |buf| {
+                assert_eq!(h.push(buf), Ok(gst::FlowSuccess::Ok));
+                while h.buffers_in_queue() > 0 {
+                    let _ = h.pull().unwrap();
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+criterion_group!(benches, bench_cc_data_allocations);
+criterion_main!(benches);
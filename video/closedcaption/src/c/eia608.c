@@ -46,13 +46,18 @@ eia608_row_pramble (int row, int chan, int x, int underline)
 {
   row = eia608_reverse_row_map[row & 0x0F];
   return eia608_parity (0x1040 | (chan ? 0x0800 : 0x0000) | ((row << 7) &
-          0x0700) | ((row << 5) & 0x0020)) | ((x << 1) & 0x001E) | (underline ?
-      0x0001 : 0x0000);
+          0x0700) | ((row << 5) & 0x0020) | (x & 0x0010) | ((x << 1) & 0x000E) |
+      (underline ? 0x0001 : 0x0000));
 }
 
 uint16_t
 eia608_row_column_pramble (int row, int col, int chan, int underline)
 {
+  /* Indent 0 is equivalent to the white style preamble */
+  if (4 > col) {
+    return eia608_row_pramble (row, chan, eia608_style_white, underline);
+  }
+
   return eia608_row_pramble (row, chan, 0x10 | (col / 4), underline);
 }
 
@@ -0,0 +1,109 @@
+// Copyright (C) 2020 Mathieu Duponchelle <mathieu@centricular.com>
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Library General Public
+// License as published by the Free Software Foundation; either
+// version 2 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Library General Public License for more details.
+//
+// You should have received a copy of the GNU Library General Public
+// License along with this library; if not, write to the
+// Free Software Foundation, Inc., 51 Franklin Street, Suite 500,
+// Boston, MA 02110-1335, USA.
+
+/* Frame rates a CDP can signal, with their cdp_frame_rate code and the
+ * number of cc_data triples each packet carries at that rate */
+pub const CDP_FRAMERATES: [(i32, i32, u8, usize); 8] = [
+    (24000, 1001, 0x1, 25),
+    (24, 1, 0x2, 25),
+    (25, 1, 0x3, 24),
+    (30000, 1001, 0x4, 20),
+    (30, 1, 0x5, 20),
+    (50, 1, 0x6, 12),
+    (60000, 1001, 0x7, 10),
+    (60, 1, 0x8, 10),
+];
+
+/* cdp_frame_rate code and cc_count for a frame rate, if a CDP can
+ * signal it */
+pub fn framerate_code(framerate: gst::Fraction) -> Option<(u8, usize)> {
+    CDP_FRAMERATES
+        .iter()
+        .find(|(n, d, _, _)| *n == *framerate.numer() && *d == *framerate.denom())
+        .map(|(_, _, code, cc_count)| (*code, *cc_count))
+}
+
+/* BCD encoded time_code section payload of the CDP for a frame, using
+ * drop-frame counting at 29.97 and 59.94 fps */
+pub fn time_code(mut frame_no: u64, fps_n: u64, fps_d: u64) -> [u8; 4] {
+    let nominal_fps = (fps_n + fps_d / 2) / fps_d;
+    let drop_frame = fps_d == 1001 && nominal_fps % 30 == 0;
+
+    if drop_frame {
+        let dropped = nominal_fps / 15;
+        let frames_per_minute = nominal_fps * 60 - dropped;
+        let frames_per_ten_minutes = nominal_fps * 600 - dropped * 9;
+        let ten_minutes = frame_no / frames_per_ten_minutes;
+        let remainder = frame_no % frames_per_ten_minutes;
+
+        frame_no += dropped * 9 * ten_minutes;
+        if remainder > dropped {
+            frame_no += dropped * ((remainder - dropped) / frames_per_minute);
+        }
+    }
+
+    let frames = frame_no % nominal_fps;
+    let seconds = frame_no / nominal_fps % 60;
+    let minutes = frame_no / nominal_fps / 60 % 60;
+    let hours = frame_no / nominal_fps / 3600 % 24;
+    let bcd = |value: u64| ((value / 10) << 4 | value % 10) as u8;
+
+    [
+        0xc0 | bcd(hours),
+        0x80 | bcd(minutes),
+        bcd(seconds),
+        if drop_frame { 0x80 } else { 0x00 } | bcd(frames),
+    ]
+}
+
+/* CEA-708 Caption Distribution Packet carrying a frame's cc_data
+ * triples, padded to the number of triples the frame rate requires */
+pub fn packet(
+    triples: &[[u8; 3]],
+    framerate_code: u8,
+    cc_count: usize,
+    time_code: [u8; 4],
+    sequence_counter: u16,
+) -> Vec<u8> {
+    assert!(triples.len() <= cc_count);
+
+    let len = 7 + 5 + 2 + 3 * cc_count + 4;
+    let mut packet = Vec::with_capacity(len);
+
+    packet.extend_from_slice(&[0x96, 0x69, len as u8, framerate_code << 4 | 0x0f]);
+    /* time code and cc_data present, caption service active */
+    packet.push(0xc3);
+    packet.extend_from_slice(&sequence_counter.to_be_bytes());
+
+    packet.push(0x71);
+    packet.extend_from_slice(&time_code);
+
+    packet.extend_from_slice(&[0x72, 0xe0 | cc_count as u8]);
+    for triple in triples {
+        packet.extend_from_slice(triple);
+    }
+    for _ in triples.len()..cc_count {
+        packet.extend_from_slice(&[0xfa, 0x00, 0x00]);
+    }
+
+    packet.push(0x74);
+    packet.extend_from_slice(&sequence_counter.to_be_bytes());
+    let sum = packet.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    packet.push(sum.wrapping_neg());
+
+    packet
+}
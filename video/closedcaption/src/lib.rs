@@ -35,6 +35,7 @@ extern crate pretty_assertions;
 
 mod caption_frame;
 mod ccdetect;
+mod cdp;
 mod cea608overlay;
 mod cea608tott;
 #[allow(non_camel_case_types, non_upper_case_globals)]
@@ -49,6 +50,7 @@ mod scc_enc;
 mod scc_parse;
 mod scc_parser;
 mod tttocea608;
+mod tttocea708;
 
 fn plugin_init(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
     mcc_parse::register(plugin)?;
@@ -57,6 +59,7 @@ fn plugin_init(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
     scc_enc::register(plugin)?;
     cea608tott::register(plugin)?;
     tttocea608::register(plugin)?;
+    tttocea708::register(plugin)?;
     cea608overlay::register(plugin)?;
     ccdetect::register(plugin)?;
     Ok(())
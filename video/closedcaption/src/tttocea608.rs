@@ -22,7 +22,10 @@ use glib::GEnum;
 use gst::prelude::*;
 use gst::subclass::prelude::*;
 
+use super::caption_frame::{CaptionFrame, Status};
+use super::cdp;
 use super::cea608tott_ffi as ffi;
+use std::collections::VecDeque;
 use std::sync::Mutex;
 
 fn decrement_pts(
@@ -82,31 +85,112 @@ fn is_specialna(cc_data: u16) -> bool {
     0x1130 == (0x7770 & cc_data)
 }
 
+/* Basic North American characters are encoded as their ASCII code,
+ * without parity, except for the few ASCII codes the basic set assigns
+ * to accented characters: those are taken from the extended tables */
+fn basicna_from_utf8(c: &[u8; 5]) -> Option<u16> {
+    if c[1] != 0 {
+        return None;
+    }
+
+    match c[0] {
+        b'\'' | b'*' | b'\\' | b'^' | b'_' | b'`' | b'{' | b'|' | b'}' | b'~' => None,
+        0x20..=0x7e => Some((c[0] as u16) << 8),
+        _ => None,
+    }
+}
+
+/* Channels are numbered from 0 (CC1) to 3 (CC4): bit 0 selects the data
+ * channel, and bit 1 the field, which only control codes carry */
+
 #[allow(clippy::trivially_copy_pass_by_ref)]
-fn eia608_from_utf8_1(c: &[u8; 5]) -> u16 {
+fn eia608_from_utf8_1(c: &[u8; 5], channel: i32) -> u16 {
     assert!(c[4] == 0);
-    unsafe { ffi::eia608_from_utf8_1(c.as_ptr() as *const _, 0) }
+
+    /* Basic characters don't carry the channel */
+    if let Some(cc_data) = basicna_from_utf8(c) {
+        return eia608_parity(cc_data);
+    }
+
+    unsafe { ffi::eia608_from_utf8_1(c.as_ptr() as *const _, channel & 1) }
 }
 
-fn eia608_row_column_preamble(row: i32, col: i32) -> u16 {
-    unsafe {
-        /* Hardcoded chan and underline */
-        ffi::eia608_row_column_pramble(row, col, 0, 0)
+#[allow(clippy::trivially_copy_pass_by_ref)]
+fn eia608_from_utf8_2(c1: &[u8; 5], c2: &[u8; 5]) -> u16 {
+    assert!(c1[4] == 0 && c2[4] == 0);
+
+    if let (Some(bna1), Some(bna2)) = (basicna_from_utf8(c1), basicna_from_utf8(c2)) {
+        return eia608_from_basicna(bna1, bna2);
     }
+
+    unsafe { ffi::eia608_from_utf8_2(c1.as_ptr() as *const _, c2.as_ptr() as *const _) }
+}
+
+fn eia608_row_column_preamble(row: i32, col: i32, channel: i32, underline: bool) -> u16 {
+    unsafe { ffi::eia608_row_column_pramble(row, col, channel & 1, underline as i32) }
+}
+
+fn eia608_row_style_preamble(
+    row: i32,
+    style: ffi::eia608_style_t,
+    channel: i32,
+    underline: bool,
+) -> u16 {
+    unsafe { ffi::eia608_row_style_pramble(row, channel & 1, style, underline as i32) }
 }
 
-fn eia608_control_command(cmd: ffi::eia608_control_t) -> u16 {
-    unsafe { ffi::eia608_control_command(cmd, 0) }
+fn eia608_midrow_change(style: ffi::eia608_style_t, channel: i32, underline: bool) -> u16 {
+    unsafe { ffi::eia608_midrow_change(channel & 1, style, underline as i32) }
+}
+
+/* Bit 8 of control codes selects the field, except for tab offsets
+ * which use the same codes on both fields */
+fn eia608_control_command(cmd: ffi::eia608_control_t, channel: i32) -> u16 {
+    let mut cc_data = cmd as u16 | if channel & 1 != 0 { 0x0800 } else { 0 };
+
+    if cmd & 0xfffc != ffi::eia608_control_t_eia608_tab_offset_0 {
+        cc_data |= if channel & 2 != 0 { 0x0100 } else { 0 };
+    }
+
+    eia608_parity(cc_data)
 }
 
 fn eia608_from_basicna(bna1: u16, bna2: u16) -> u16 {
-    unsafe { ffi::eia608_from_basicna(bna1, bna2) }
+    if !is_basicna(bna1) || !is_basicna(bna2) {
+        return 0;
+    }
+
+    eia608_parity((bna1 & 0xff00) | (bna2 & 0xff00) >> 8)
+}
+
+/* A caption is made of dozens of byte pairs, allocate them from a pool
+ * instead of one by one */
+fn new_cc_data_pool(element: &gst::Element) -> Option<gst::BufferPool> {
+    let pool = gst::BufferPool::new();
+    let mut config = pool.get_config();
+    config.set_params(None, 2, 0, 0);
+
+    match pool.set_config(config).and_then(|_| pool.set_active(true)) {
+        Ok(_) => Some(pool),
+        Err(err) => {
+            gst_warning!(
+                CAT,
+                obj: element,
+                "Failed to set up the cc_data buffer pool, allocating byte pairs one by one: {}",
+                err
+            );
+            None
+        }
+    }
 }
 
-fn buffer_from_cc_data(cc_data: u16) -> gst::buffer::Buffer {
-    let mut ret = gst::Buffer::with_size(2).unwrap();
+fn buffer_from_cc_data(pool: Option<&gst::BufferPool>, cc_data: u16) -> gst::buffer::Buffer {
+    /* Fall back to plain allocation without a pool */
+    let mut ret = pool
+        .and_then(|pool| pool.acquire_buffer(None).ok())
+        .unwrap_or_else(|| gst::Buffer::with_size(2).unwrap());
     {
-        let buf_mut = ret.get_mut().unwrap();
+        let buf_mut = ret.make_mut();
 
         let cc_data = cc_data.to_be_bytes();
 
@@ -118,678 +202,4688 @@ fn buffer_from_cc_data(cc_data: u16) -> gst::buffer::Buffer {
     ret
 }
 
-fn control_command_buffer(buffers: &mut Vec<gst::Buffer>, cmd: ffi::eia608_control_t) {
-    let cc_data = eia608_control_command(cmd);
-    buffers.push(buffer_from_cc_data(cc_data));
-    buffers.push(buffer_from_cc_data(cc_data));
+/* Copies the buffer first if it's shared */
+fn set_timestamps(buffer: &mut gst::Buffer, pts: gst::ClockTime, duration: gst::ClockTime) {
+    let buf_mut = buffer.make_mut();
+    buf_mut.set_pts(pts);
+    buf_mut.set_duration(duration);
+}
+
+/* Control codes, preambles and mid-row codes are sent copies times,
+ * twice unless double-control-codes is disabled */
+fn control_code_buffer(
+    buffers: &mut Vec<gst::Buffer>,
+    pool: Option<&gst::BufferPool>,
+    pool: Option<&gst::BufferPool>,
+    cc_data: u16,
+    copies: u64,
+) {
+    for _ in 0..copies {
+        buffers.push(buffer_from_cc_data(pool, cc_data));
+    }
+}
+
+fn control_command_buffer(
+    buffers: &mut Vec<gst::Buffer>,
+    pool: Option<&gst::BufferPool>,
+    cmd: ffi::eia608_control_t,
+    channel: i32,
+    copies: u64,
+) {
+    control_code_buffer(buffers, pool, eia608_control_command(cmd, channel), copies);
 }
 
-fn erase_non_displayed_memory(buffers: &mut Vec<gst::Buffer>) {
+fn erase_non_displayed_memory(
+    buffers: &mut Vec<gst::Buffer>,
+    pool: Option<&gst::BufferPool>,
+    channel: i32,
+    copies: u64,
+) {
     control_command_buffer(
         buffers,
+        pool,
         ffi::eia608_control_t_eia608_control_erase_non_displayed_memory,
+        channel,
+        copies,
     );
 }
 
-fn erase_display_memory(buffers: &mut Vec<gst::Buffer>) {
+fn erase_display_memory(
+    buffers: &mut Vec<gst::Buffer>,
+    pool: Option<&gst::BufferPool>,
+    channel: i32,
+    copies: u64,
+) {
     control_command_buffer(
         buffers,
+        pool,
         ffi::eia608_control_t_eia608_control_erase_display_memory,
+        channel,
+        copies,
     );
 }
 
 fn erase_display_memory_with_pts(
     bufferlist: &mut gst::BufferListRef,
+    pool: Option<&gst::BufferPool>,
     pts: gst::ClockTime,
     duration: gst::ClockTime,
+    channel: i32,
 ) {
-    let cc_data = eia608_control_command(ffi::eia608_control_t_eia608_control_erase_display_memory);
+    let cc_data = eia608_control_command(
+        ffi::eia608_control_t_eia608_control_erase_display_memory,
+        channel,
+    );
 
-    let mut buffer = buffer_from_cc_data(cc_data);
-    {
-        let buf_mut = buffer.get_mut().unwrap();
-        buf_mut.set_pts(pts);
-        buf_mut.set_duration(duration);
-    }
+    let mut buffer = buffer_from_cc_data(pool, cc_data);
+    set_timestamps(&mut buffer, pts, duration);
     bufferlist.insert(0, buffer);
 }
 
-fn resume_caption_loading(buffers: &mut Vec<gst::Buffer>) {
+fn resume_caption_loading(
+    buffers: &mut Vec<gst::Buffer>,
+    pool: Option<&gst::BufferPool>,
+    channel: i32,
+    copies: u64,
+) {
     control_command_buffer(
         buffers,
+        pool,
         ffi::eia608_control_t_eia608_control_resume_caption_loading,
+        channel,
+        copies,
     );
 }
 
-fn roll_up_2(buffers: &mut Vec<gst::Buffer>) {
-    control_command_buffer(buffers, ffi::eia608_control_t_eia608_control_roll_up_2);
+fn resume_direct_captioning(
+    buffers: &mut Vec<gst::Buffer>,
+    pool: Option<&gst::BufferPool>,
+    channel: i32,
+    copies: u64,
+) {
+    control_command_buffer(
+        buffers,
+        pool,
+        ffi::eia608_control_t_eia608_control_resume_direct_captioning,
+        channel,
+        copies,
+    );
 }
 
-fn roll_up_3(buffers: &mut Vec<gst::Buffer>) {
-    control_command_buffer(buffers, ffi::eia608_control_t_eia608_control_roll_up_3);
+fn roll_up_2(
+    buffers: &mut Vec<gst::Buffer>,
+    pool: Option<&gst::BufferPool>,
+    channel: i32,
+    copies: u64,
+) {
+    control_command_buffer(
+        buffers,
+        pool,
+        ffi::eia608_control_t_eia608_control_roll_up_2,
+        channel,
+        copies,
+    );
 }
 
-fn roll_up_4(buffers: &mut Vec<gst::Buffer>) {
-    control_command_buffer(buffers, ffi::eia608_control_t_eia608_control_roll_up_4);
+fn roll_up_3(
+    buffers: &mut Vec<gst::Buffer>,
+    pool: Option<&gst::BufferPool>,
+    channel: i32,
+    copies: u64,
+) {
+    control_command_buffer(
+        buffers,
+        pool,
+        ffi::eia608_control_t_eia608_control_roll_up_3,
+        channel,
+        copies,
+    );
 }
 
-fn carriage_return(buffers: &mut Vec<gst::Buffer>) {
+fn roll_up_4(
+    buffers: &mut Vec<gst::Buffer>,
+    pool: Option<&gst::BufferPool>,
+    channel: i32,
+    copies: u64,
+) {
     control_command_buffer(
         buffers,
-        ffi::eia608_control_t_eia608_control_carriage_return,
+        pool,
+        ffi::eia608_control_t_eia608_control_roll_up_4,
+        channel,
+        copies,
     );
 }
 
-fn end_of_caption(buffers: &mut Vec<gst::Buffer>) {
-    control_command_buffer(buffers, ffi::eia608_control_t_eia608_control_end_of_caption);
+fn roll_up(
+    buffers: &mut Vec<gst::Buffer>,
+    pool: Option<&gst::BufferPool>,
+    rows: u32,
+    channel: i32,
+    copies: u64,
+) {
+    match rows {
+        2 => roll_up_2(buffers, pool, channel, copies),
+        3 => roll_up_3(buffers, pool, channel, copies),
+        _ => roll_up_4(buffers, pool, channel, copies),
+    }
 }
 
-fn preamble_buffer(buffers: &mut Vec<gst::Buffer>, row: i32, col: i32) {
-    let cc_data = eia608_row_column_preamble(row, col);
-    buffers.push(buffer_from_cc_data(cc_data));
-    buffers.push(buffer_from_cc_data(cc_data));
+fn carriage_return(
+    buffers: &mut Vec<gst::Buffer>,
+    pool: Option<&gst::BufferPool>,
+    channel: i32,
+    copies: u64,
+) {
+    control_command_buffer(
+        buffers,
+        pool,
+        ffi::eia608_control_t_eia608_control_carriage_return,
+        channel,
+        copies,
+    );
 }
 
-fn bna_buffer(buffers: &mut Vec<gst::Buffer>, bna1: u16, bna2: u16) {
-    let cc_data = eia608_from_basicna(bna1, bna2);
-
-    buffers.push(buffer_from_cc_data(cc_data));
+fn delete_to_end_of_row(
+    buffers: &mut Vec<gst::Buffer>,
+    pool: Option<&gst::BufferPool>,
+    channel: i32,
+    copies: u64,
+) {
+    control_command_buffer(
+        buffers,
+        pool,
+        ffi::eia608_control_t_eia608_control_delete_to_end_of_row,
+        channel,
+        copies,
+    );
 }
 
-const DEFAULT_FPS_N: i32 = 30;
-const DEFAULT_FPS_D: i32 = 1;
-
-/* 74 is quite the magic number:
- * 2 byte pairs for resume_caption_loading
- * 2 byte pairs for erase_non_displayed_memory
- * At most 4 byte pairs for the preambles (one per line, at most 2 lines)
- * At most 64 byte pairs for the text if it's made up of 64 westeu characters
- * At most 2 byte pairs if we need to splice in an erase_display_memory
- */
-const LATENCY_BUFFERS: u64 = 74;
-
-#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, GEnum)]
-#[repr(u32)]
-#[genum(type_name = "GstTtToCea608Mode")]
-enum Mode {
-    PopOn,
-    RollUp2,
-    RollUp3,
-    RollUp4,
+/* Deletes what's left on a row we overwrite past the text we painted */
+fn delete_overwritten(
+    buffers: &mut Vec<gst::Buffer>,
+    pool: Option<&gst::BufferPool>,
+    painted_rows: &[(i32, u32, u32)],
+    row: i32,
+    col: u32,
+    channel: i32,
+    copies: u64,
+) {
+    if painted_rows
+        .iter()
+        .any(|(painted_row, _, end)| *painted_row == row && *end > col)
+    {
+        delete_to_end_of_row(buffers, pool, channel, copies);
+    }
 }
 
-const DEFAULT_MODE: Mode = Mode::RollUp2;
-
-static PROPERTIES: [subclass::Property; 1] = [subclass::Property("mode", |name| {
-    glib::ParamSpec::enum_(
-        name,
-        "Mode",
-        "Which mode to operate in, roll-up modes introduce no latency",
-        Mode::static_type(),
-        DEFAULT_MODE as i32,
-        glib::ParamFlags::READWRITE,
-    )
-})];
-
-#[derive(Debug, Clone)]
-struct Settings {
-    mode: Mode,
+fn end_of_caption(
+    buffers: &mut Vec<gst::Buffer>,
+    pool: Option<&gst::BufferPool>,
+    channel: i32,
+    copies: u64,
+) {
+    control_command_buffer(
+        buffers,
+        pool,
+        ffi::eia608_control_t_eia608_control_end_of_caption,
+        channel,
+        copies,
+    );
 }
 
-impl Default for Settings {
-    fn default() -> Self {
-        Settings { mode: DEFAULT_MODE }
+/* Italics and colors can only be set by a preamble at the start of the
+ * row, and italic preambles are always white, other preambles reset to
+ * plain text. Indent preambles can only address every fourth column,
+ * a tab offset moves the cursor to the others without drawing blanks.
+ * Returns the style that was set */
+fn preamble_buffer(
+    buffers: &mut Vec<gst::Buffer>,
+    pool: Option<&gst::BufferPool>,
+    row: i32,
+    col: i32,
+    channel: i32,
+    style: Style,
+    copies: u64,
+) -> Style {
+    /* Other rows have no preamble code, and would address another row */
+    let row = if !(0..=14).contains(&row) {
+        let clamped_row = std::cmp::max(0, std::cmp::min(row, 14));
+        gst_warning!(CAT, "Invalid row {}, using row {}", row, clamped_row);
+        clamped_row
+    } else {
+        row
+    };
+
+    let cc_data = if style.italics && col < 4 {
+        eia608_row_style_preamble(
+            row,
+            ffi::eia608_style_t_eia608_style_italics,
+            channel,
+            style.underline,
+        )
+    } else if col < 4 {
+        eia608_row_style_preamble(row, style.color.eia608_style(), channel, style.underline)
+    } else {
+        eia608_row_column_preamble(row, col, channel, style.underline)
+    };
+
+    control_code_buffer(buffers, pool, cc_data, copies);
+
+    match col % 4 {
+        1 => control_command_buffer(
+            buffers,
+            pool,
+            ffi::eia608_control_t_eia608_tab_offset_1,
+            channel,
+            copies,
+        ),
+        2 => control_command_buffer(
+            buffers,
+            pool,
+            ffi::eia608_control_t_eia608_tab_offset_2,
+            channel,
+            copies,
+        ),
+        3 => control_command_buffer(
+            buffers,
+            pool,
+            ffi::eia608_control_t_eia608_tab_offset_3,
+            channel,
+            copies,
+        ),
+        _ => (),
     }
-}
 
-struct State {
-    settings: Settings,
-    framerate: gst::Fraction,
-    erase_display_frame_no: Option<u64>,
-    last_frame_no: u64,
-    roll_up_column: u32,
-    send_roll_up: bool,
+    preamble_style(col, style)
 }
 
-impl Default for State {
-    fn default() -> Self {
-        Self {
-            settings: Settings::default(),
-            framerate: gst::Fraction::new(DEFAULT_FPS_N, DEFAULT_FPS_D),
-            erase_display_frame_no: None,
-            last_frame_no: 0,
-            roll_up_column: 0,
-            send_roll_up: false,
+/* Style set by the preamble preamble_buffer sends for a column */
+fn preamble_style(col: i32, style: Style) -> Style {
+    if style.italics && col < 4 {
+        Style {
+            color: Color::White,
+            ..style
+        }
+    } else if col < 4 {
+        Style {
+            italics: false,
+            ..style
+        }
+    } else {
+        Style {
+            italics: false,
+            color: Color::White,
+            ..style
         }
     }
 }
 
-struct TtToCea608 {
-    srcpad: gst::Pad,
-    sinkpad: gst::Pad,
+/* Mid-row codes are displayed as a space, and thus occupy a column.
+ * The italics code leaves the color untouched, while color codes end
+ * italics. Returns the style that was set and the number of columns
+ * the codes occupy */
+fn midrow_change_buffer(
+    buffers: &mut Vec<gst::Buffer>,
+    pool: Option<&gst::BufferPool>,
+    mut emitted_style: Style,
+    style: Style,
+    channel: i32,
+    copies: u64,
+) -> (Style, u32) {
+    let mut n_cols = 0;
+
+    if style.color != emitted_style.color
+        || (emitted_style.italics && !style.italics)
+        || (!style.italics && style.underline != emitted_style.underline)
+    {
+        let cc_data = eia608_midrow_change(style.color.eia608_style(), channel, style.underline);
+        control_code_buffer(buffers, pool, cc_data, copies);
+        emitted_style = Style {
+            italics: false,
+            ..style
+        };
+        n_cols += 1;
+    }
 
-    state: Mutex<State>,
-    settings: Mutex<Settings>,
+    if style != emitted_style {
+        let cc_data = eia608_midrow_change(
+            ffi::eia608_style_t_eia608_style_italics,
+            channel,
+            style.underline,
+        );
+        control_code_buffer(buffers, pool, cc_data, copies);
+        emitted_style = style;
+        n_cols += 1;
+    }
+
+    (emitted_style, n_cols)
 }
 
-lazy_static! {
-    static ref CAT: gst::DebugCategory = gst::DebugCategory::new(
-        "tttocea608",
-        gst::DebugColorFlags::empty(),
-        Some("TT CEA 608 Element"),
-    );
-    static ref SPACE: u16 = eia608_from_utf8_1(&[0x20, 0, 0, 0, 0]);
+fn bna_buffer(
+    buffers: &mut Vec<gst::Buffer>,
+    pool: Option<&gst::BufferPool>,
+    bna1: u16,
+    bna2: u16,
+) {
+    let cc_data = eia608_from_basicna(bna1, bna2);
+
+    buffers.push(buffer_from_cc_data(pool, cc_data));
 }
 
-impl TtToCea608 {
-    fn push_gap(&self, last_frame_no: u64, new_frame_no: u64) {
-        if last_frame_no < new_frame_no {
-            let state = self.state.lock().unwrap();
-            let (fps_n, fps_d) = (
-                *state.framerate.numer() as u64,
-                *state.framerate.denom() as u64,
-            );
-            let start = (last_frame_no * gst::SECOND)
-                .mul_div_round(fps_d, fps_n)
-                .unwrap();
-            let end = (new_frame_no * gst::SECOND)
-                .mul_div_round(fps_d, fps_n)
-                .unwrap();
+/* Sets the odd parity bit of both bytes */
+fn eia608_parity(cc_data: u16) -> u16 {
+    let parity = |byte: u16| {
+        let byte = byte & 0x7f;
+        if byte.count_ones() % 2 == 0 {
+            byte | 0x80
+        } else {
+            byte
+        }
+    };
 
-            let event = gst::event::Gap::new(start, end - start);
+    parity(cc_data >> 8) << 8 | parity(cc_data & 0xff)
+}
 
-            drop(state);
+/* Not part of libcaption's eia608_control_t */
+const FLASH_ON: ffi::eia608_control_t = 0x1428;
+
+/* The decoder certification pattern, on the given channel, in this order:
+ * - every control command, doubled, in ascending order of their codes,
+ *   followed by the three tab offsets
+ * - a preamble at column 0 for each row, from 0 to 14, doubled
+ * - on row 14, a preamble for each style then each indent, without then
+ *   with underline, doubled
+ * - a mid-row code for each style, without then with underline, doubled
+ * - every basic North American character from 0x20 to 0x7f, two per pair
+ * - every special North American character
+ * - every extended Western European character, Spanish / French then
+ *   Portuguese / German / Danish
+ * - erase_display_memory, doubled
+ */
+fn cert_pattern(channel: i32) -> Vec<u16> {
+    let mut pattern = vec![];
 
-            let _ = self.srcpad.push_event(event);
-        }
+    let controls = [
+        ffi::eia608_control_t_eia608_control_resume_caption_loading,
+        ffi::eia608_control_t_eia608_control_backspace,
+        ffi::eia608_control_t_eia608_control_alarm_off,
+        ffi::eia608_control_t_eia608_control_alarm_on,
+        ffi::eia608_control_t_eia608_control_delete_to_end_of_row,
+        ffi::eia608_control_t_eia608_control_roll_up_2,
+        ffi::eia608_control_t_eia608_control_roll_up_3,
+        ffi::eia608_control_t_eia608_control_roll_up_4,
+        FLASH_ON,
+        ffi::eia608_control_t_eia608_control_resume_direct_captioning,
+        ffi::eia608_control_t_eia608_control_text_restart,
+        ffi::eia608_control_t_eia608_control_text_resume_text_display,
+        ffi::eia608_control_t_eia608_control_erase_display_memory,
+        ffi::eia608_control_t_eia608_control_carriage_return,
+        ffi::eia608_control_t_eia608_control_erase_non_displayed_memory,
+        ffi::eia608_control_t_eia608_control_end_of_caption,
+        ffi::eia608_control_t_eia608_tab_offset_1,
+        ffi::eia608_control_t_eia608_tab_offset_2,
+        ffi::eia608_control_t_eia608_tab_offset_3,
+    ];
+
+    for cmd in controls.iter() {
+        let cc_data = eia608_control_command(*cmd, channel);
+        pattern.push(cc_data);
+        pattern.push(cc_data);
     }
 
-    fn push_list(
-        &self,
-        bufferlist: gst::BufferList,
-        last_frame_no: u64,
-        new_frame_no: u64,
-    ) -> Result<gst::FlowSuccess, gst::FlowError> {
-        self.push_gap(last_frame_no, new_frame_no);
-        self.srcpad.push_list(bufferlist)
+    for row in 0..15 {
+        let cc_data = eia608_row_column_preamble(row, 0, channel, false);
+        pattern.push(cc_data);
+        pattern.push(cc_data);
     }
 
-    fn do_erase_display(
-        &self,
-        min_frame_no: u64,
-        mut erase_display_frame_no: u64,
-    ) -> Result<gst::FlowSuccess, gst::FlowError> {
-        let mut state = self.state.lock().unwrap();
+    for underline in &[false, true] {
+        for style in 0..8 {
+            let cc_data = eia608_row_style_preamble(14, style, channel, *underline);
+            pattern.push(cc_data);
+            pattern.push(cc_data);
+        }
 
-        let (fps_n, fps_d) = (
-            *state.framerate.numer() as u64,
-            *state.framerate.denom() as u64,
-        );
+        for indent in 0..8 {
+            let cc_data = eia608_row_column_preamble(14, indent * 4, channel, *underline);
+            pattern.push(cc_data);
+            pattern.push(cc_data);
+        }
+    }
 
-        let mut bufferlist = gst::BufferList::new();
+    for underline in &[false, true] {
+        for style in 0..8 {
+            let cc_data = eia608_midrow_change(style, channel, *underline);
+            pattern.push(cc_data);
+            pattern.push(cc_data);
+        }
+    }
 
-        state.last_frame_no = erase_display_frame_no;
+    for c in (0x20..0x80).step_by(2) {
+        pattern.push(eia608_parity(c << 8 | (c + 1)));
+    }
 
-        let (pts, duration) =
-            decrement_pts(min_frame_no, &mut erase_display_frame_no, fps_n, fps_d);
-        erase_display_memory_with_pts(bufferlist.get_mut().unwrap(), pts, duration);
-        let (pts, duration) =
-            decrement_pts(min_frame_no, &mut erase_display_frame_no, fps_n, fps_d);
-        erase_display_memory_with_pts(bufferlist.get_mut().unwrap(), pts, duration);
+    for cc_data in (0x1130..0x1140).chain(0x1220..0x1240).chain(0x1320..0x1340) {
+        pattern.push(eia608_parity(cc_data | (channel as u16 & 1) << 11));
+    }
 
-        drop(state);
+    let cc_data = eia608_control_command(
+        ffi::eia608_control_t_eia608_control_erase_display_memory,
+        channel,
+    );
+    pattern.push(cc_data);
+    pattern.push(cc_data);
+
+    pattern
+}
 
-        self.push_list(bufferlist, min_frame_no, erase_display_frame_no)
+const STYLE_NAMES: [&str; 8] = [
+    "white", "green", "blue", "cyan", "red", "yellow", "magenta", "italics",
+];
+
+fn control_name(cmd: ffi::eia608_control_t) -> &'static str {
+    match cmd {
+        ffi::eia608_control_t_eia608_tab_offset_0 => "tab_offset_0",
+        ffi::eia608_control_t_eia608_tab_offset_1 => "tab_offset_1",
+        ffi::eia608_control_t_eia608_tab_offset_2 => "tab_offset_2",
+        ffi::eia608_control_t_eia608_tab_offset_3 => "tab_offset_3",
+        ffi::eia608_control_t_eia608_control_resume_caption_loading => "resume_caption_loading",
+        ffi::eia608_control_t_eia608_control_backspace => "backspace",
+        ffi::eia608_control_t_eia608_control_alarm_off => "alarm_off",
+        ffi::eia608_control_t_eia608_control_alarm_on => "alarm_on",
+        ffi::eia608_control_t_eia608_control_delete_to_end_of_row => "delete_to_end_of_row",
+        ffi::eia608_control_t_eia608_control_roll_up_2 => "roll_up_2",
+        ffi::eia608_control_t_eia608_control_roll_up_3 => "roll_up_3",
+        ffi::eia608_control_t_eia608_control_roll_up_4 => "roll_up_4",
+        FLASH_ON => "flash_on",
+        ffi::eia608_control_t_eia608_control_resume_direct_captioning => "resume_direct_captioning",
+        ffi::eia608_control_t_eia608_control_text_restart => "text_restart",
+        ffi::eia608_control_t_eia608_control_text_resume_text_display => "text_resume_text_display",
+        ffi::eia608_control_t_eia608_control_erase_display_memory => "erase_display_memory",
+        ffi::eia608_control_t_eia608_control_carriage_return => "carriage_return",
+        ffi::eia608_control_t_eia608_control_erase_non_displayed_memory => {
+            "erase_non_displayed_memory"
+        }
+        ffi::eia608_control_t_eia608_control_end_of_caption => "end_of_caption",
+        _ => "unknown_control",
     }
+}
 
-    #[allow(clippy::cognitive_complexity)]
-    fn sink_chain(
-        &self,
-        pad: &gst::Pad,
-        element: &gst::Element,
-        buffer: gst::Buffer,
-    ) -> Result<gst::FlowSuccess, gst::FlowError> {
-        let pts = match buffer.get_pts() {
-            gst::CLOCK_TIME_NONE => {
-                gst_element_error!(
-                    element,
-                    gst::StreamError::Format,
-                    ["Stream with timestamped buffers required"]
-                );
-                Err(gst::FlowError::Error)
-            }
-            pts => Ok(pts),
-        }?;
+/* Human readable description of a byte pair, for debug-output */
+fn describe_cc_data(cc_data: u16) -> String {
+    let mut chan = 0;
+
+    if cc_data & 0x7f7f == 0 {
+        "padding".to_string()
+    } else if is_basicna(cc_data) || is_specialna(cc_data) || is_westeu(cc_data) {
+        let mut char1 = [0; 5];
+        let mut char2 = [0; 5];
+
+        let (char1, char2) = unsafe {
+            ffi::eia608_to_utf8(cc_data, &mut chan, char1.as_mut_ptr(), char2.as_mut_ptr());
+            (
+                std::ffi::CStr::from_ptr(char1.as_ptr()).to_string_lossy(),
+                std::ffi::CStr::from_ptr(char2.as_ptr()).to_string_lossy(),
+            )
+        };
 
-        let duration = match buffer.get_duration() {
-            gst::CLOCK_TIME_NONE => {
-                gst_element_error!(
-                    element,
-                    gst::StreamError::Format,
-                    ["Buffers of stream need to have a duration"]
-                );
-                Err(gst::FlowError::Error)
-            }
-            duration => Ok(duration),
-        }?;
+        format!("text '{}{}'", char1, char2)
+    } else if 0x1120 == (0x7770 & cc_data) {
+        let mut style = 0;
+        let mut underline = 0;
 
-        let mut state = self.state.lock().unwrap();
-        let mut buffers = vec![];
+        unsafe {
+            ffi::eia608_parse_midrowchange(cc_data, &mut chan, &mut style, &mut underline);
+        }
 
-        if state.send_roll_up {
-            erase_display_memory(&mut buffers);
-            match state.settings.mode {
-                Mode::RollUp2 => roll_up_2(&mut buffers),
-                Mode::RollUp3 => roll_up_3(&mut buffers),
-                Mode::RollUp4 => roll_up_4(&mut buffers),
-                _ => (),
-            }
-            preamble_buffer(&mut buffers, 14, 0);
-            state.send_roll_up = false;
-            state.roll_up_column = 0;
+        format!(
+            "mid-row {}{}",
+            STYLE_NAMES[style as usize],
+            if underline != 0 { " underline" } else { "" }
+        )
+    } else if 0x1040 == (0x7040 & cc_data) {
+        let mut row = 0;
+        let mut col = 0;
+        let mut style = 0;
+        let mut underline = 0;
+
+        unsafe {
+            ffi::eia608_parse_preamble(
+                cc_data,
+                &mut row,
+                &mut col,
+                &mut style,
+                &mut chan,
+                &mut underline,
+            );
         }
 
-        let mut row = 13;
-        let mut col = if state.settings.mode == Mode::PopOn {
-            0
-        } else {
-            state.roll_up_column
-        };
+        format!(
+            "preamble row {} column {} {}{}",
+            row,
+            col,
+            STYLE_NAMES[style as usize],
+            if underline != 0 { " underline" } else { "" }
+        )
+    } else if 0x1420 == (0x7670 & cc_data) || 0x1720 == (0x7770 & cc_data) {
+        let cmd = unsafe { ffi::eia608_parse_control(cc_data, &mut chan) };
 
-        if state.settings.mode == Mode::PopOn {
-            resume_caption_loading(&mut buffers);
-            erase_non_displayed_memory(&mut buffers);
-            preamble_buffer(&mut buffers, row, 0);
+        control_name(cmd).to_string()
+    } else {
+        format!("unknown {:04x}", cc_data)
+    }
+}
+
+/* Replaces each byte pair buffer with the output of convert, keeping its timestamps */
+fn convert_bufferlist<F>(
+    bufferlist: &gst::BufferList,
+    mut convert: F,
+) -> Result<gst::BufferList, gst::FlowError>
+where
+    F: FnMut(gst::ClockTime, &[u8]) -> Vec<u8>,
+{
+    let mut converted = gst::BufferList::new();
+    {
+        let converted = converted.make_mut();
+
+        for buffer in bufferlist.iter() {
+            let data = buffer.map_readable().map_err(|_| gst::FlowError::Error)?;
+            let pts = buffer.get_pts();
+
+            let mut new_buffer = gst::Buffer::from_mut_slice(convert(pts, &data));
+            set_timestamps(&mut new_buffer, pts, buffer.get_duration());
+            converted.insert(-1, new_buffer);
         }
+    }
 
-        let data = buffer.map_readable().map_err(|_| {
-            gst_error!(CAT, obj: pad, "Can't map buffer readable");
+    Ok(converted)
+}
 
-            gst::FlowError::Error
-        })?;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token {
+    Char(char),
+    Underline(bool),
+    Italics(bool),
+    Color(Color),
+    /* Offset in milliseconds from the start of the caption */
+    Timing(u64),
+}
 
-        let data = std::str::from_utf8(&data).map_err(|err| {
-            gst_error!(CAT, obj: pad, "Can't decode utf8: {}", err);
+/* Whether a caption has nothing to display, once its markup is removed */
+fn is_blank(data: &str, markup: bool) -> bool {
+    parse_markup(data, markup).iter().all(|token| match token {
+        Token::Char(c) => c.is_whitespace(),
+        _ => true,
+    })
+}
 
-            gst::FlowError::Error
-        })?;
+/* Splits text into characters and styling changes, the latter
+ * expressed with Pango-style markup. CEA-608 has no bold, <b> tags are
+ * dropped. Word timings for karaoke-style captions are expressed as
+ * <t ms="500">word</t> */
+fn parse_markup(data: &str, enable_markup: bool) -> Vec<Token> {
+    if !enable_markup {
+        return data.chars().map(Token::Char).collect();
+    }
 
-        let mut prev_char: u16 = if state.settings.mode == Mode::PopOn || col == 0 {
-            0
-        } else if col >= 31 {
-            match state.settings.mode {
-                Mode::RollUp2 => roll_up_2(&mut buffers),
-                Mode::RollUp3 => roll_up_3(&mut buffers),
-                Mode::RollUp4 => roll_up_4(&mut buffers),
-                _ => (),
-            }
-            carriage_return(&mut buffers);
-            preamble_buffer(&mut buffers, 14, 0);
-            col = 0;
-            0
+    let mut tokens = vec![];
+    let mut rest = data;
+
+    while let Some(c) = rest.chars().next() {
+        if rest.starts_with("<u>") {
+            tokens.push(Token::Underline(true));
+            rest = &rest[3..];
+        } else if rest.starts_with("</u>") {
+            tokens.push(Token::Underline(false));
+            rest = &rest[4..];
+        } else if rest.starts_with("<i>") {
+            tokens.push(Token::Italics(true));
+            rest = &rest[3..];
+        } else if rest.starts_with("</i>") {
+            tokens.push(Token::Italics(false));
+            rest = &rest[4..];
+        } else if rest.starts_with("<b>") {
+            rest = &rest[3..];
+        } else if rest.starts_with("</b>") {
+            rest = &rest[4..];
+        } else if rest.starts_with("</span>") {
+            tokens.push(Token::Color(Color::White));
+            rest = &rest[7..];
+        } else if let Some((color, len)) = parse_span(rest) {
+            tokens.push(Token::Color(color));
+            rest = &rest[len..];
+        } else if rest.starts_with("</t>") {
+            rest = &rest[4..];
+        } else if let Some((ms, len)) = parse_timing(rest) {
+            tokens.push(Token::Timing(ms));
+            rest = &rest[len..];
         } else {
-            // In roll-up mode, the typical input will not have surrounding
-            // whitespaces. This could be improved by detecting whether the
-            // last character that was output was some sort of whitespace,
-            // and we could avoid the white space before punctuation, but
-            // this is complicated by the fact that in some languages,
-            // some punctuation must be preceded by a white space, eg in
-            // French that is the case for '?' and '!', but not for '.' or
-            // ';'. Let's not go down that rabbit hole.
-            col += 1;
-            *SPACE
-        };
+            tokens.push(Token::Char(c));
+            rest = &rest[c.len_utf8()..];
+        }
+    }
 
-        for mut c in data.chars() {
-            if c == '\n' && state.settings.mode == Mode::PopOn {
-                if prev_char != 0 {
-                    buffers.push(buffer_from_cc_data(prev_char));
-                    prev_char = 0;
-                }
+    tokens
+}
 
-                row += 1;
+/* Returns the color and the length of a leading <span foreground="..."> tag */
+fn parse_span(data: &str) -> Option<(Color, usize)> {
+    const PREFIX: &str = "<span foreground=\"";
 
-                if row > 14 {
-                    break;
-                }
+    if !data.starts_with(PREFIX) {
+        return None;
+    }
 
-                preamble_buffer(&mut buffers, row, 0);
+    let end = data.find("\">")?;
+    let value = data.get(PREFIX.len()..end)?;
+    let color = Color::parse(value).unwrap_or_else(|| {
+        gst_warning!(CAT, "Unsupported color {}, using white", value);
+        Color::White
+    });
 
-                col = 0;
-                continue;
-            } else if c == '\n' {
-                c = ' ';
-            } else if c == '\r' {
-                continue;
-            }
+    Some((color, end + 2))
+}
 
-            let mut encoded = [0; 5];
-            c.encode_utf8(&mut encoded);
-            let mut cc_data = eia608_from_utf8_1(&encoded);
+/* Returns the offset and the length of a leading <t ms="..."> tag */
+fn parse_timing(data: &str) -> Option<(u64, usize)> {
+    const PREFIX: &str = "<t ms=\"";
 
-            if cc_data == 0 {
-                gst_warning!(CAT, obj: element, "Not translating UTF8: {}", c);
-                cc_data = *SPACE;
-            }
+    if !data.starts_with(PREFIX) {
+        return None;
+    }
 
-            if is_basicna(prev_char) {
-                if is_basicna(cc_data) {
-                    bna_buffer(&mut buffers, prev_char, cc_data);
-                } else if is_westeu(cc_data) {
-                    // extended characters overwrite the previous character,
-                    // so insert a dummy char then write the extended char
-                    bna_buffer(&mut buffers, prev_char, *SPACE);
-                    buffers.push(buffer_from_cc_data(cc_data));
-                } else {
-                    buffers.push(buffer_from_cc_data(prev_char));
-                    buffers.push(buffer_from_cc_data(cc_data));
-                }
-                prev_char = 0;
-            } else if is_westeu(cc_data) {
-                // extended characters overwrite the previous character,
-                // so insert a dummy char then write the extended char
-                buffers.push(buffer_from_cc_data(*SPACE));
-                buffers.push(buffer_from_cc_data(cc_data));
-            } else if is_basicna(cc_data) {
-                prev_char = cc_data;
-            } else {
-                buffers.push(buffer_from_cc_data(cc_data));
-            }
+    let end = data.find("\">")?;
+    let ms = data.get(PREFIX.len()..end)?.parse::<u64>().ok()?;
 
-            if is_specialna(cc_data) {
-                resume_caption_loading(&mut buffers);
-            }
+    Some((ms, end + 2))
+}
 
-            col += 1;
+/* Running times past this can't be represented as a GstClockTimeDiff */
+const MAX_RUNNING_TIME: u64 = std::i64::MAX as u64;
 
-            if col > 32 && state.settings.mode == Mode::PopOn {
-                gst_warning!(
-                    CAT,
-                    obj: element,
-                    "Dropping character after 32nd column: {}",
-                    c
-                );
+/* Computes the display window of a caption, applying in order ts-offset,
+ * lead-time, min-duration then pre-clear-gap, without ever letting the
+ * window start before 0, end before it starts or past MAX_RUNNING_TIME */
+fn schedule(
+    settings: &Settings,
+    pts: gst::ClockTime,
+    duration: gst::ClockTime,
+) -> (gst::ClockTime, gst::ClockTime) {
+    let offset = |time: u64| {
+        let time = if settings.ts_offset < 0 {
+            /* wrapping_neg() leaves i64::MIN as is, which is 2^63 as u64 */
+            time.saturating_sub(settings.ts_offset.wrapping_neg() as u64)
+        } else {
+            time.saturating_add(settings.ts_offset as u64)
+        };
+
+        std::cmp::min(time, MAX_RUNNING_TIME)
+    };
+
+    let start = offset(pts.unwrap());
+    let end = offset(pts.unwrap().saturating_add(duration.unwrap()));
+
+    let start = start.saturating_sub(settings.lead_time);
+
+    let end = std::cmp::max(
+        end,
+        std::cmp::min(
+            start.saturating_add(settings.min_duration),
+            MAX_RUNNING_TIME,
+        ),
+    );
+
+    let end = std::cmp::max(end.saturating_sub(settings.pre_clear_gap), start);
+
+    (start.into(), (end - start).into())
+}
+
+/* Breaks lines longer than width at their last space that fits, or
+ * within words longer than width */
+fn wrap_tokens(tokens: Vec<Token>, width: usize) -> Vec<Token> {
+    let mut wrapped = vec![];
+    let mut col = 0;
+    let mut last_space = None;
+
+    for token in tokens {
+        let c = match token {
+            Token::Char(c) if c != '\r' => c,
+            _ => {
+                wrapped.push(token);
                 continue;
-            } else if col == 32 && state.settings.mode != Mode::PopOn {
-                if prev_char != 0 {
-                    buffers.push(buffer_from_cc_data(prev_char));
-                    prev_char = 0;
-                }
+            }
+        };
 
-                match state.settings.mode {
-                    Mode::RollUp2 => roll_up_2(&mut buffers),
-                    Mode::RollUp3 => roll_up_3(&mut buffers),
-                    Mode::RollUp4 => roll_up_4(&mut buffers),
-                    _ => (),
-                }
+        if c == '\n' {
+            col = 0;
+            last_space = None;
+            wrapped.push(token);
+            continue;
+        }
 
-                carriage_return(&mut buffers);
-                preamble_buffer(&mut buffers, 14, 0);
+        if col >= width {
+            /* The break replaces the space */
+            if c == ' ' {
+                wrapped.push(Token::Char('\n'));
                 col = 0;
+                last_space = None;
+                continue;
             }
-        }
 
-        if prev_char != 0 {
-            buffers.push(buffer_from_cc_data(prev_char));
+            match last_space.take() {
+                Some(idx) => {
+                    wrapped[idx] = Token::Char('\n');
+                    col = wrapped[idx + 1..]
+                        .iter()
+                        .filter(|token| match token {
+                            Token::Char(c) => *c != '\r',
+                            _ => false,
+                        })
+                        .count();
+                }
+                None => {
+                    wrapped.push(Token::Char('\n'));
+                    col = 0;
+                }
+            }
         }
 
-        if state.settings.mode == Mode::PopOn {
-            end_of_caption(&mut buffers);
-        } else {
-            state.roll_up_column = col;
+        if c == ' ' {
+            last_space = Some(wrapped.len());
         }
 
-        let mut bufferlist = gst::BufferList::new();
+        wrapped.push(token);
+        col += 1;
+    }
 
-        let (fps_n, fps_d) = (
-            *state.framerate.numer() as u64,
-            *state.framerate.denom() as u64,
-        );
+    wrapped
+}
 
-        /* Calculate the frame for which we want the first of our
-         * (doubled) end_of_caption control codes to be output
-         */
-        let mut frame_no = (pts.mul_div_round(fps_n, fps_d).unwrap() / gst::SECOND).unwrap();
+/* Spellings of characters CEA-608 has no glyph for with two basic
+ * North American characters */
+fn two_char_fallback(c: char) -> Option<(char, char)> {
+    match c {
+        'æ' => Some(('a', 'e')),
+        'Æ' => Some(('A', 'E')),
+        'œ' => Some(('o', 'e')),
+        'Œ' => Some(('O', 'E')),
+        'ĳ' => Some(('i', 'j')),
+        'Ĳ' => Some(('I', 'J')),
+        'ﬀ' => Some(('f', 'f')),
+        'ﬁ' => Some(('f', 'i')),
+        'ﬂ' => Some(('f', 'l')),
+        _ => None,
+    }
+}
 
-        if state.settings.mode == Mode::PopOn {
-            /* Add 2: One for our second end_of_caption control
-             * code, another to calculate its duration */
-            frame_no += 2;
+/* Latin letter a character with diacritics is based on, used to
+ * approximate letters CEA-608 has no glyph for, and as the character
+ * extended characters overwrite for decoders that don't support them */
+fn strip_diacritics(c: char) -> Option<char> {
+    let base = match c {
+        'À'..='Å' | 'à'..='å' | 'Ā'..='ą' => 'a',
+        'Ç' | 'ç' | 'Ć'..='č' => 'c',
+        'Ð' | 'ð' | 'Ď'..='đ' => 'd',
+        'È'..='Ë' | 'è'..='ë' | 'Ē'..='ě' => 'e',
+        'Ĝ'..='ģ' => 'g',
+        'Ĥ'..='ħ' => 'h',
+        'Ì'..='Ï' | 'ì'..='ï' | 'Ĩ'..='ı' => 'i',
+        'Ĵ' | 'ĵ' => 'j',
+        'Ķ' | 'ķ' => 'k',
+        'Ĺ'..='ł' => 'l',
+        'Ñ' | 'ñ' | 'Ń'..='ň' | 'Ŋ' | 'ŋ' => 'n',
+        'Ò'..='Ö' | 'Ø' | 'ò'..='ö' | 'ø' | 'Ō'..='ő' => 'o',
+        'Ŕ'..='ř' => 'r',
+        'Ś'..='š' | 'ſ' => 's',
+        'Ţ'..='ŧ' => 't',
+        'Ù'..='Ü' | 'ù'..='ü' | 'Ũ'..='ų' => 'u',
+        'Ŵ' | 'ŵ' => 'w',
+        'Ý' | 'ý' | 'ÿ' | 'Ŷ'..='Ÿ' => 'y',
+        'Ź'..='ž' => 'z',
+        _ => return None,
+    };
+
+    if c.is_uppercase() {
+        Some(base.to_ascii_uppercase())
+    } else {
+        Some(base)
+    }
+}
 
-            /* Store that frame number, so we can make sure not to output
-             * overlapped timestamps, outputting multiple buffers with
-             * a 0 duration will break strict line-21 encoding, but
-             * we should be fine with 608 over 708, as we can encode
-             * multiple byte pairs into a single frame */
-            let mut min_frame_no = state.last_frame_no;
-            state.last_frame_no = frame_no;
+/* Replaces the characters that can't be encoded as one symbol with
+ * their two symbol spelling, if any */
+fn expand_two_char_fallbacks(tokens: Vec<Token>) -> Vec<Token> {
+    let mut expanded = vec![];
 
-            let mut erase_display_frame_no = {
-                if state.erase_display_frame_no < Some(frame_no) {
-                    state.erase_display_frame_no
-                } else {
-                    None
+    for token in tokens {
+        if let Token::Char(c) = token {
+            let mut encoded = [0; 5];
+            c.encode_utf8(&mut encoded);
+
+            if eia608_from_utf8_1(&encoded, 0) == 0 {
+                if let Some((c1, c2)) = two_char_fallback(c) {
+                    let mut encoded1 = [0; 5];
+                    let mut encoded2 = [0; 5];
+                    c1.encode_utf8(&mut encoded1);
+                    c2.encode_utf8(&mut encoded2);
+
+                    if eia608_from_utf8_2(&encoded1, &encoded2) != 0 {
+                        expanded.push(Token::Char(c1));
+                        expanded.push(Token::Char(c2));
+                        continue;
+                    }
                 }
-            };
+            }
+        }
 
-            state.erase_display_frame_no = Some(
-                ((pts + duration).mul_div_round(fps_n, fps_d).unwrap() / gst::SECOND).unwrap() + 2,
-            );
+        expanded.push(token);
+    }
 
-            for mut buffer in buffers.drain(..).rev() {
-                /* Insert display erasure at the correct moment */
-                if erase_display_frame_no == Some(frame_no) {
-                    let (pts, duration) = decrement_pts(min_frame_no, &mut frame_no, fps_n, fps_d);
-                    erase_display_memory_with_pts(bufferlist.get_mut().unwrap(), pts, duration);
-                    let (pts, duration) = decrement_pts(min_frame_no, &mut frame_no, fps_n, fps_d);
-                    erase_display_memory_with_pts(bufferlist.get_mut().unwrap(), pts, duration);
+    expanded
+}
 
-                    erase_display_frame_no = None;
-                }
+/* ASCII approximations of punctuation CEA-608 has no glyph for */
+static TRANSLITERATIONS: &[(char, &str)] = &[
+    ('‐', "-"),
+    ('‑', "-"),
+    ('‒', "-"),
+    ('–', "-"),
+    ('―', "-"),
+    ('−', "-"),
+    ('‚', ","),
+    ('‛', "'"),
+    ('„', "\""),
+    ('‟', "\""),
+    ('′', "'"),
+    ('″', "\""),
+    ('‹', "<"),
+    ('›', ">"),
+    ('…', "..."),
+    ('€', "EUR"),
+];
+
+fn encode_char(c: char, channel: i32) -> u16 {
+    let mut encoded = [0; 5];
+    c.encode_utf8(&mut encoded);
+    eia608_from_utf8_1(&encoded, channel)
+}
 
-                let (pts, duration) = decrement_pts(min_frame_no, &mut frame_no, fps_n, fps_d);
+/* Whether a character can be encoded, possibly approximated with its
+ * base letter */
+fn can_encode(c: char) -> bool {
+    encode_char(c, 0) != 0
+        || strip_diacritics(c).map_or(false, |base| is_basicna(encode_char(base, 0)))
+}
 
-                let buf_mut = buffer.get_mut().unwrap();
-                buf_mut.set_pts(pts);
-                buf_mut.set_duration(duration);
-                bufferlist.get_mut().unwrap().insert(0, buffer);
-            }
-            drop(state);
+/* Applies the fallback to the characters that can't be encoded, those
+ * left are replaced with a space when encoding */
+fn apply_fallback(tokens: Vec<Token>, fallback: Fallback) -> Vec<Token> {
+    if fallback == Fallback::Space {
+        return tokens;
+    }
 
-            if let Some(erase_display_frame_no) = erase_display_frame_no {
-                self.do_erase_display(min_frame_no, erase_display_frame_no)?;
-                min_frame_no = erase_display_frame_no;
-            }
-            self.push_list(bufferlist, min_frame_no, frame_no)
-                .map_err(|err| {
-                    gst_error!(CAT, obj: &self.srcpad, "Pushing buffer returned {:?}", err);
-                    err
-                })
-        } else {
-            // Make sure our first buffer doesn't overlap with the last
-            // gap / buffer we pushed
-            frame_no = std::cmp::max(frame_no, state.last_frame_no);
-            let start_frame_no = frame_no;
-            let max_frame_no =
-                ((pts + duration).mul_div_round(fps_n, fps_d).unwrap() / gst::SECOND).unwrap();
-            for mut buffer in buffers.drain(..) {
-                let (pts, duration) = increment_pts(&mut frame_no, max_frame_no, fps_n, fps_d);
-                let buf_mut = buffer.get_mut().unwrap();
-                buf_mut.set_pts(pts);
-                buf_mut.set_duration(duration);
-                bufferlist.get_mut().unwrap().insert(-1, buffer);
-            }
-            let last_frame_no = state.last_frame_no;
-            state.last_frame_no = max_frame_no;
-            drop(state);
-            let ret = self.push_list(bufferlist, last_frame_no, start_frame_no);
-            self.push_gap(frame_no, max_frame_no);
-            ret
+    let mut replaced = vec![];
+
+    for token in tokens {
+        match token {
+            Token::Char(c) if !can_encode(c) => match fallback {
+                Fallback::Drop => {
+                    gst_warning!(CAT, "Dropping UTF8: {}", c);
+                }
+                Fallback::Transliterate => {
+                    match TRANSLITERATIONS.iter().find(|(from, _)| *from == c) {
+                        Some((_, to)) => replaced.extend(to.chars().map(Token::Char)),
+                        None => replaced.push(token),
+                    }
+                }
+                Fallback::Space => unreachable!(),
+            },
+            _ => replaced.push(token),
         }
     }
 
-    fn src_query(&self, pad: &gst::Pad, element: &gst::Element, query: &mut gst::QueryRef) -> bool {
-        use gst::QueryView;
-
-        gst_log!(CAT, obj: pad, "Handling query {:?}", query);
+    replaced
+}
 
-        match query.view_mut() {
-            QueryView::Latency(ref mut q) => {
-                let mut peer_query = gst::query::Latency::new();
+/* Characters of a caption that will be replaced or dropped, along
+ * with their position in the text */
+fn untranslatable_chars(data: &str, fallback: Fallback) -> Vec<(usize, char)> {
+    data.chars()
+        .enumerate()
+        .filter(|(_, c)| {
+            !c.is_control()
+                && !can_encode(*c)
+                && two_char_fallback(*c).is_none()
+                && (fallback != Fallback::Transliterate
+                    || !TRANSLITERATIONS.iter().any(|(from, _)| from == c))
+        })
+        .collect()
+}
 
-                let ret = self.sinkpad.peer_query(&mut peer_query);
+/* Number of columns a line occupies once displayed, mid-row codes
+ * included, when its preamble is sent for the given column. Returns
+ * the style at the end of the line along with it */
+fn line_length(tokens: &[Token], col: u32, mut style: Style) -> (u32, Style) {
+    let mut length = 0;
+    let mut emitted_style = None;
+
+    for token in tokens {
+        match token {
+            Token::Char('\r') => (),
+            Token::Char(c) => match emitted_style {
+                None => {
+                    emitted_style = Some(preamble_style(col as i32, style));
+                    length += 1;
+                }
+                Some(emitted) if emitted != style => {
+                    /* Only the number of columns matters here */
+                    let (emitted, n_cols) =
+                        midrow_change_buffer(&mut vec![], None, emitted, style, 0, 1);
+                    emitted_style = Some(emitted);
+                    length += n_cols;
+
+                    /* The mid-row code is displayed as a space already */
+                    if *c != ' ' {
+                        length += 1;
+                    }
+                }
+                Some(_) => length += 1,
+            },
+            Token::Underline(underline) => style.underline = *underline,
+            Token::Italics(italics) => style.italics = *italics,
+            Token::Color(color) => style.color = *color,
+            Token::Timing(_) => (),
+        }
+    }
 
-                if ret {
-                    let state = self.state.lock().unwrap();
-                    let (live, mut min, mut max) = peer_query.get_result();
-                    let (fps_n, fps_d) = (
-                        *state.framerate.numer() as u64,
-                        *state.framerate.denom() as u64,
-                    );
+    (length, style)
+}
 
-                    if state.settings.mode == Mode::PopOn {
-                        let our_latency: gst::ClockTime = (LATENCY_BUFFERS * gst::SECOND)
-                            .mul_div_round(fps_d, fps_n)
-                            .unwrap();
+/* Removes whitespace at the end of each line, then the empty lines
+ * at the end of the text, leading whitespace is kept for alignment */
+fn trim_trailing(data: &str) -> String {
+    data.split('\n')
+        .map(|line| line.trim_end_matches(|c: char| c.is_ascii_whitespace()))
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim_end_matches('\n')
+        .to_string()
+}
 
-                        min += our_latency;
-                        max += our_latency;
-                    } else {
-                        /* We introduce at most a one-frame latency due to rounding */
-                        let our_latency: gst::ClockTime =
-                            gst::SECOND.mul_div_round(fps_d, fps_n).unwrap();
+/* Replaces runs of whitespace other than line breaks with a single space */
+fn collapse_spaces(tokens: Vec<Token>) -> Vec<Token> {
+    let mut collapsed = vec![];
+    let mut in_run = false;
+
+    for token in tokens {
+        match token {
+            Token::Char(c) if c != '\n' && c.is_whitespace() => {
+                if !in_run {
+                    collapsed.push(Token::Char(' '));
+                    in_run = true;
+                }
+            }
+            Token::Char(_) => {
+                in_run = false;
+                collapsed.push(token);
+            }
+            _ => collapsed.push(token),
+        }
+    }
 
-                        min += our_latency;
-                        max += our_latency;
-                    }
+    collapsed
+}
 
-                    q.set(live, min, max);
-                }
-                ret
+/* Removes the spaces at the start of each line, decoders would draw
+ * them with a background. Returns the number removed from each line,
+ * to be turned into an indent */
+fn strip_leading_spaces(tokens: Vec<Token>) -> (Vec<Token>, Vec<u32>) {
+    let mut stripped = vec![];
+    let mut indents = vec![0];
+    let mut line_start = true;
+
+    for token in tokens {
+        match token {
+            Token::Char('\n') => {
+                indents.push(0);
+                line_start = true;
+                stripped.push(token);
             }
-            _ => pad.query_default(Some(element), query),
+            Token::Char(' ') if line_start => *indents.last_mut().unwrap() += 1,
+            Token::Char(_) => {
+                line_start = false;
+                stripped.push(token);
+            }
+            _ => stripped.push(token),
         }
     }
 
-    fn sink_event(&self, pad: &gst::Pad, element: &gst::Element, event: gst::Event) -> bool {
-        gst_log!(CAT, obj: pad, "Handling event {:?}", event);
+    (stripped, indents)
+}
 
-        use gst::EventView;
+/* Column at which each line starts, lines being aligned between
+ * origin_column and the last column, starting with the given style.
+ * Lines are moved right by their indent, which counts in their length.
+ * Centered lines get the extra column on their right */
+fn line_columns(
+    tokens: &[Token],
+    origin_column: u32,
+    alignment: Alignment,
+    mut style: Style,
+    indents: &[u32],
+) -> Vec<u32> {
+    let column = |length: u32| {
+        let padding = (32 - origin_column).saturating_sub(length);
+
+        origin_column
+            + match alignment {
+                Alignment::Left => 0,
+                Alignment::Center => padding / 2,
+                Alignment::Right => padding,
+            }
+    };
 
-        match event.view() {
-            EventView::Caps(..) => {
-                let mut downstream_caps = match self.srcpad.get_allowed_caps() {
-                    None => self.srcpad.get_pad_template_caps().unwrap(),
-                    Some(caps) => caps,
-                };
+    let mut columns = vec![];
+    for (i, line) in tokens
+        .split(|token| *token == Token::Char('\n'))
+        .enumerate()
+    {
+        let indent = indents.get(i).copied().unwrap_or(0);
+
+        /* The preamble, and thus the style the line starts with, depends
+         * on the column, assume an indent preamble first */
+        let (length, _) = line_length(line, std::cmp::max(origin_column + indent, 4), style);
+        let mut col = column(length + indent) + indent;
+        if col < 4 {
+            col = column(line_length(line, col, style).0 + indent) + indent;
+        }
 
-                if downstream_caps.is_empty() {
-                    gst_error!(CAT, obj: pad, "Empty downstream caps");
-                    return false;
-                }
+        columns.push(col);
+        style = line_length(line, col, style).1;
+    }
 
-                let caps = downstream_caps.make_mut();
-                let s = caps.get_mut_structure(0).unwrap();
+    columns
+}
 
-                s.fixate_field_nearest_fraction(
-                    "framerate",
-                    gst::Fraction::new(DEFAULT_FPS_N, DEFAULT_FPS_D),
-                );
-                s.fixate();
+/* Splits a caption into pages of at most max_rows lines */
+fn split_pages(data: &str, max_rows: usize) -> Vec<String> {
+    let lines: Vec<&str> = data.split('\n').collect();
 
-                let mut state = self.state.lock().unwrap();
-                state.framerate = s.get_some::<gst::Fraction>("framerate").unwrap();
+    lines.chunks(max_rows).map(|page| page.join("\n")).collect()
+}
 
-                gst_debug!(CAT, obj: pad, "Pushing caps {}", caps);
+const CHANNEL_MARKERS: [&str; 4] = ["[CC1]", "[CC2]", "[CC3]", "[CC4]"];
+
+/* Routes the lines following a [CC1] to [CC4] marker to that channel,
+ * and those preceding any marker to the default channel. Returns the
+ * text for each channel, in order of appearance */
+fn split_channels(data: &str, default_channel: i32) -> Vec<(i32, String)> {
+    let mut captions: Vec<(i32, String)> = vec![];
+    let mut channel = default_channel;
+
+    for line in data.split('\n') {
+        let line = match CHANNEL_MARKERS
+            .iter()
+            .position(|marker| line.starts_with(marker))
+        {
+            Some(idx) => {
+                channel = idx as i32;
+                &line[CHANNEL_MARKERS[idx].len()..]
+            }
+            None => line,
+        };
 
-                let new_event = gst::event::Caps::new(&downstream_caps);
+        match captions.iter_mut().find(|(c, _)| *c == channel) {
+            Some((_, text)) => {
+                text.push('\n');
+                text.push_str(line);
+            }
+            None => captions.push((channel, line.to_string())),
+        }
+    }
 
-                drop(state);
+    captions
+}
 
-                self.srcpad.push_event(new_event)
+/* Windows-1252 assigns printable characters to most of the C1 control
+ * codes of ISO-8859-1, the 5 it leaves undefined map to those */
+const CP1252_C1: [char; 32] = [
+    '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8d}', 'Ž', '\u{8f}',
+    '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9d}', 'ž', 'Ÿ',
+];
+
+/* ISO-8859-1 bytes are the first 256 Unicode code points */
+fn decode_8bit(data: &[u8], charset: Charset) -> String {
+    data.iter()
+        .map(|&byte| match charset {
+            Charset::Cp1252 if (0x80..0xa0).contains(&byte) => CP1252_C1[(byte - 0x80) as usize],
+            _ => byte as char,
+        })
+        .collect()
+}
+
+/* Distributes the words of a caption over at most n_screens screens
+ * of similar length. Line breaks are kept, and the styles in effect
+ * where a screen ends are closed there and reopened on the next one */
+fn split_screens(data: &str, markup: bool, n_screens: usize) -> Vec<String> {
+    let is_word_char = |token: &Token| match token {
+        Token::Char(c) => !c.is_whitespace(),
+        _ => false,
+    };
+    let tokens = parse_markup(data, markup);
+    let total = tokens.iter().filter(|token| is_word_char(*token)).count();
+    let mut screens = vec![];
+    let mut screen = vec![];
+    /* Whitespace and styling changes since the end of the last word */
+    let mut pending = vec![];
+    let mut style = Style::default();
+    let mut count = 0;
+    let mut in_word = false;
+
+    for (i, token) in tokens.iter().enumerate() {
+        if !is_word_char(token) {
+            if let Token::Char(_) = token {
+                in_word = false;
             }
-            EventView::Gap(e) => {
-                let mut state = self.state.lock().unwrap();
-                let (fps_n, fps_d) = (
-                    *state.framerate.numer() as u64,
-                    *state.framerate.denom() as u64,
-                );
 
-                let (timestamp, duration) = e.get();
-                let mut frame_no = ((timestamp + duration).mul_div_round(fps_n, fps_d).unwrap()
-                    / gst::SECOND)
-                    .unwrap();
+            if in_word {
+                screen.push(*token);
+            } else {
+                pending.push(*token);
+            }
+            continue;
+        }
 
-                if state.settings.mode == Mode::PopOn {
-                    if frame_no < LATENCY_BUFFERS {
-                        return true;
-                    }
+        if !in_word {
+            let len = tokens[i..]
+                .iter()
+                .take_while(|token| match token {
+                    Token::Char(c) => !c.is_whitespace(),
+                    _ => true,
+                })
+                .filter(|token| is_word_char(*token))
+                .count();
+
+            if count > 0
+                && screens.len() + 1 < n_screens
+                && (count + len) * n_screens > total * (screens.len() + 1)
+            {
+                screens.push(screen_markup(&screen, &mut style));
+                screen.clear();
+
+                /* The whitespace separating the screens is dropped */
+                pending.retain(|token| match token {
+                    Token::Char(_) => false,
+                    _ => true,
+                });
+            }
 
-                    frame_no -= LATENCY_BUFFERS;
+            screen.append(&mut pending);
+            in_word = true;
+        }
 
-                    if let Some(erase_display_frame_no) = state.erase_display_frame_no {
-                        if erase_display_frame_no <= frame_no {
-                            let min_frame_no = state.last_frame_no;
-                            state.erase_display_frame_no = None;
+        screen.push(*token);
+        count += 1;
+    }
 
-                            drop(state);
+    screen.append(&mut pending);
+    if !screen.is_empty() {
+        screens.push(screen_markup(&screen, &mut style));
+    }
 
-                            /* Ignore return value, we may be flushing here and can't
-                             * communicate that through a boolean
-                             */
-                            let _ = self.do_erase_display(min_frame_no, erase_display_frame_no);
-                        }
-                    } else {
-                        let last_frame_no = state.last_frame_no;
-                        state.last_frame_no = frame_no;
-                        drop(state);
-                        self.push_gap(last_frame_no, frame_no);
-                    }
-                } else {
-                    let last_frame_no = state.last_frame_no;
-                    state.last_frame_no = frame_no;
-                    drop(state);
-                    self.push_gap(last_frame_no, frame_no);
-                }
+    screens
+}
 
-                true
-            }
-            EventView::Eos(_) => {
-                let mut state = self.state.lock().unwrap();
-                if let Some(erase_display_frame_no) = state.erase_display_frame_no {
-                    let min_frame_no = state.last_frame_no;
-                    state.erase_display_frame_no = None;
+/* Turns tokens back into markup, opening the tags of style first and
+ * closing the ones still open at the end, after updating style */
+fn screen_markup(tokens: &[Token], style: &mut Style) -> String {
+    let span = |color: Color| format!("<span foreground=\"{}\">", STYLE_NAMES[color as usize]);
+    let mut markup = String::new();
 
-                    drop(state);
+    if style.underline {
+        markup.push_str("<u>");
+    }
+    if style.italics {
+        markup.push_str("<i>");
+    }
+    if style.color != Color::White {
+        markup.push_str(&span(style.color));
+    }
 
-                    /* Ignore return value, we may be flushing here and can't
-                     * communicate that through a boolean
-                     */
-                    let _ = self.do_erase_display(min_frame_no, erase_display_frame_no);
-                }
-                pad.event_default(Some(element), event)
+    for token in tokens {
+        match *token {
+            Token::Char(c) => markup.push(c),
+            Token::Underline(underline) => {
+                style.underline = underline;
+                markup.push_str(if underline { "<u>" } else { "</u>" });
             }
-            EventView::FlushStop(_) => {
-                let mut state = self.state.lock().unwrap();
-
-                if state.settings.mode != Mode::PopOn {
-                    state.send_roll_up = true;
+            Token::Italics(italics) => {
+                style.italics = italics;
+                markup.push_str(if italics { "<i>" } else { "</i>" });
+            }
+            Token::Color(color) => {
+                if style.color != Color::White {
+                    markup.push_str("</span>");
                 }
-
-                pad.event_default(Some(element), event)
+                if color != Color::White {
+                    markup.push_str(&span(color));
+                }
+                style.color = color;
             }
-            _ => pad.event_default(Some(element), event),
+            Token::Timing(ms) => markup.push_str(&format!("<t ms=\"{}\">", ms)),
         }
     }
+
+    if style.color != Color::White {
+        markup.push_str("</span>");
+    }
+    if style.italics {
+        markup.push_str("</i>");
+    }
+    if style.underline {
+        markup.push_str("</u>");
+    }
+
+    markup
 }
 
-impl ObjectSubclass for TtToCea608 {
-    const NAME: &'static str = "TtToCea608";
-    type ParentType = gst::Element;
-    type Instance = gst::subclass::ElementInstanceStruct<Self>;
-    type Class = subclass::simple::ClassStruct<Self>;
+const DEFAULT_FPS_N: i32 = 30;
+const DEFAULT_FPS_D: i32 = 1;
 
-    glib_object_subclass!();
+/* 74 is quite the magic number:
+ * 2 byte pairs for resume_caption_loading
+ * 2 byte pairs for erase_non_displayed_memory, unless disabled
+ * At most 4 byte pairs for the preambles (one per line, at most 2 lines)
+ * At most 64 byte pairs for the text if it's made up of 64 westeu characters
+ * At most 2 byte pairs if we need to splice in an erase_display_memory
+ *
+ * Control codes, preambles and mid-row codes are all doubled together,
+ * when they aren't only the text is left as is and the others halve:
+ * 69 byte pairs, or 68 without erase_non_displayed_memory.
+ *
+ * Each row allowed by max-rows past the second adds 2 byte pairs for its
+ * preamble and 32 for its text, see Settings::default_latency().
+ *
+ * This is the default of the latency property, which can lower it for
+ * captions known not to need that many byte pairs
+ */
+const LATENCY_BUFFERS: u64 = 74;
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, GEnum)]
+#[repr(u32)]
+#[genum(type_name = "GstTtToCea608Mode")]
+enum Mode {
+    PopOn,
+    RollUp2,
+    RollUp3,
+    RollUp4,
+    PaintOn,
+    /* Ignores the text, and outputs the decoder certification pattern
+     * for each input buffer */
+    CertPattern,
+}
+
+impl Mode {
+    /* Height of the roll-up window, in rows */
+    fn roll_up_rows(self) -> Option<u32> {
+        match self {
+            Mode::PopOn | Mode::PaintOn | Mode::CertPattern => None,
+            Mode::RollUp2 => Some(2),
+            Mode::RollUp3 => Some(3),
+            Mode::RollUp4 => Some(4),
+        }
+    }
+
+    fn is_roll_up(self) -> bool {
+        self.roll_up_rows().is_some()
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, GEnum)]
+#[repr(u32)]
+#[genum(type_name = "GstTtToCea608ReadingSpeedAction")]
+enum ReadingSpeedAction {
+    Extend,
+    Split,
+    WarnOnly,
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, GEnum)]
+#[repr(u32)]
+#[genum(type_name = "GstTtToCea608Alignment")]
+enum Alignment {
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, GEnum)]
+#[repr(u32)]
+#[genum(type_name = "GstTtToCea608Fallback")]
+enum Fallback {
+    Space,
+    Drop,
+    Transliterate,
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, GEnum)]
+#[repr(u32)]
+#[genum(type_name = "GstTtToCea608OutOfOrder")]
+enum OutOfOrder {
+    /* Encoded right away, timestamps can't go back in time */
+    Clamp,
+    Drop,
+    Reorder,
+}
+
+/* U.S. TV Parental Guidelines rating, in the order of their XDS codes */
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, GEnum)]
+#[repr(u32)]
+#[genum(type_name = "GstTtToCea608XdsTvRating")]
+enum XdsTvRating {
+    None,
+    TvY,
+    TvY7,
+    TvG,
+    TvPg,
+    Tv14,
+    TvMa,
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, GEnum)]
+#[repr(u32)]
+#[genum(type_name = "GstTtToCea608Charset")]
+enum Charset {
+    Utf8,
+    Latin1,
+    Cp1252,
+}
+
+/* Number of captions held back to reorder the input */
+const REORDER_QUEUE_SIZE: usize = 2;
+
+const DEFAULT_MODE: Mode = Mode::RollUp2;
+const DEFAULT_BASE_ROW: u32 = 14;
+const DEFAULT_ORIGIN_ROW: u32 = 13;
+const DEFAULT_ORIGIN_COLUMN: u32 = 0;
+const DEFAULT_ENABLE_MARKUP: bool = true;
+const DEFAULT_ITALICS: bool = false;
+const DEFAULT_UNDERLINE: bool = false;
+const DEFAULT_COLOR: Color = Color::White;
+const DEFAULT_TS_OFFSET: i64 = 0;
+const DEFAULT_LEAD_TIME: u64 = 0;
+const DEFAULT_MIN_DURATION: u64 = 0;
+const DEFAULT_PRE_CLEAR_GAP: u64 = 0;
+const DEFAULT_WINDOW_START: u64 = 0;
+const DEFAULT_WINDOW_STOP: u64 = std::u64::MAX;
+const DEFAULT_MERGE_SHORT: u64 = 0;
+const DEFAULT_CHANNEL: u32 = 1;
+const DEFAULT_FIELD: u32 = 1;
+const DEFAULT_FLIP_OFFSET: u32 = 0;
+const DEFAULT_WRAP: bool = false;
+const DEFAULT_MAX_ROWS: u32 = 2;
+const DEFAULT_PAGINATE: bool = false;
+const DEFAULT_DEBUG_OUTPUT: bool = false;
+const DEFAULT_ALIGNMENT: Alignment = Alignment::Left;
+const DEFAULT_FALLBACK: Fallback = Fallback::Space;
+const DEFAULT_LATENCY: Option<u32> = None;
+const DEFAULT_REPLACEMENT_CHAR: char = ' ';
+const DEFAULT_DEFAULT_DURATION: u64 = 4_000_000_000;
+const DEFAULT_CLEAR_ON_EMPTY: bool = false;
+const DEFAULT_DYNAMIC_LATENCY: bool = false;
+const DEFAULT_SYNTHESIZE_PTS: bool = false;
+const DEFAULT_TRIM_TRAILING: bool = true;
+const DEFAULT_ROLL_UP_ROWS: u32 = 0;
+const DEFAULT_COLLAPSE_SPACES: bool = false;
+const DEFAULT_ERASE_NON_DISPLAYED: bool = true;
+const DEFAULT_OUT_OF_ORDER: OutOfOrder = OutOfOrder::Clamp;
+const DEFAULT_LOSSY_DECODE: bool = true;
+const DEFAULT_DOUBLE_CONTROL_CODES: bool = true;
+const DEFAULT_CLEAR_ON_EOS: bool = false;
+const DEFAULT_XDS_TV_RATING: XdsTvRating = XdsTvRating::None;
+const DEFAULT_MAX_ON_SCREEN: u64 = 0;
+const DEFAULT_CHARSET: Charset = Charset::Utf8;
+const DEFAULT_READING_SPEED: u32 = 0;
+const DEFAULT_READING_SPEED_ACTION: ReadingSpeedAction = ReadingSpeedAction::Extend;
+
+static PROPERTIES: [subclass::Property; 45] = [
+    subclass::Property("mode", |name| {
+        glib::ParamSpec::enum_(
+            name,
+            "Mode",
+            "Which mode to operate in, roll-up and paint-on modes introduce no latency, \
+             cert-pattern outputs a decoder certification pattern instead of the text",
+            Mode::static_type(),
+            DEFAULT_MODE as i32,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("base-row", |name| {
+        glib::ParamSpec::uint(
+            name,
+            "Base Row",
+            "Bottom row of the roll-up window, the window extends upwards from it",
+            0,
+            14,
+            DEFAULT_BASE_ROW,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("roll-up-window", |name| {
+        glib::ParamSpec::uint(
+            name,
+            "Roll-up Window",
+            "Number of rows displayed in roll-up modes (0 in other modes)",
+            0,
+            4,
+            2,
+            glib::ParamFlags::READABLE,
+        )
+    }),
+    subclass::Property("origin-row", |name| {
+        glib::ParamSpec::uint(
+            name,
+            "Origin Row",
+            "Row of the first line of text in pop-on and paint-on modes",
+            0,
+            14,
+            DEFAULT_ORIGIN_ROW,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("origin-column", |name| {
+        glib::ParamSpec::uint(
+            name,
+            "Origin Column",
+            "Column at which lines of text start, this reduces the usable width of each line, \
+             and is realized with indent codes in multiples of 4 followed by a tab offset",
+            0,
+            31,
+            DEFAULT_ORIGIN_COLUMN,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("reading-speed", |name| {
+        glib::ParamSpec::uint(
+            name,
+            "Reading Speed",
+            "Maximum number of characters per second viewers are expected to read (0 = unlimited)",
+            0,
+            std::u32::MAX,
+            DEFAULT_READING_SPEED,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("reading-speed-action", |name| {
+        glib::ParamSpec::enum_(
+            name,
+            "Reading Speed Action",
+            "What to do with captions that can't be read in time at the reading speed, \
+             splitting is not possible in roll-up modes",
+            ReadingSpeedAction::static_type(),
+            DEFAULT_READING_SPEED_ACTION as i32,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("enable-markup", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Enable Markup",
+            "Interpret <i>, <u>, <span foreground> and <t> tags in the input text, \
+             and drop <b> tags, instead of encoding them as is, as well as [CC1] to [CC4] line markers \
+             in pop-on mode, when the input caps don't say whether the text is \
+             markup",
+            DEFAULT_ENABLE_MARKUP,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("italics", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Italics",
+            "Display whole captions in italics, </i> can still be used to end italics",
+            DEFAULT_ITALICS,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("underline", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Underline",
+            "Underline whole captions, </u> can still be used to end underlining",
+            DEFAULT_UNDERLINE,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("color", |name| {
+        glib::ParamSpec::enum_(
+            name,
+            "Color",
+            "Color of whole captions, </span> can still be used to go back to white",
+            Color::static_type(),
+            DEFAULT_COLOR as i32,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("ts-offset", |name| {
+        glib::ParamSpec::int64(
+            name,
+            "Timestamp Offset",
+            "Offset (in ns) applied to the timestamps of the input text",
+            std::i64::MIN,
+            std::i64::MAX,
+            DEFAULT_TS_OFFSET,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("lead-time", |name| {
+        glib::ParamSpec::uint64(
+            name,
+            "Lead Time",
+            "Time (in ns) by which captions are displayed ahead of their timestamp",
+            0,
+            std::u64::MAX,
+            DEFAULT_LEAD_TIME,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("min-duration", |name| {
+        glib::ParamSpec::uint64(
+            name,
+            "Minimum Duration",
+            "Minimum time (in ns) captions are displayed for",
+            0,
+            std::u64::MAX,
+            DEFAULT_MIN_DURATION,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("pre-clear-gap", |name| {
+        glib::ParamSpec::uint64(
+            name,
+            "Pre-clear Gap",
+            "Time (in ns) by which captions are erased ahead of their end",
+            0,
+            std::u64::MAX,
+            DEFAULT_PRE_CLEAR_GAP,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("window-start", |name| {
+        glib::ParamSpec::uint64(
+            name,
+            "Window Start",
+            "Captions with a timestamp (in ns) before this are dropped",
+            0,
+            std::u64::MAX,
+            DEFAULT_WINDOW_START,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("window-stop", |name| {
+        glib::ParamSpec::uint64(
+            name,
+            "Window Stop",
+            "Captions with a timestamp (in ns) after this are dropped, and the display \
+             is erased at this time",
+            0,
+            std::u64::MAX,
+            DEFAULT_WINDOW_STOP,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("merge-short", |name| {
+        glib::ParamSpec::uint64(
+            name,
+            "Merge Short",
+            "Captions shorter than this (in ns) are displayed along with the next one \
+             in pop-on and paint-on modes (0 = disabled)",
+            0,
+            std::u64::MAX,
+            DEFAULT_MERGE_SHORT,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("channel", |name| {
+        glib::ParamSpec::uint(
+            name,
+            "Channel",
+            "Caption channel to output on, from CC1 to CC4, CC3 and CC4 being carried in the \
+             second field",
+            1,
+            4,
+            DEFAULT_CHANNEL,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("field", |name| {
+        glib::ParamSpec::uint(
+            name,
+            "Field",
+            "Field to output on, CC1 and CC2 become CC3 and CC4 on the second field, \
+             which CC3 and CC4 always use",
+            1,
+            2,
+            DEFAULT_FIELD,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("flip-offset", |name| {
+        glib::ParamSpec::uint(
+            name,
+            "Flip Offset",
+            "Number of frames after the timestamp of pop-on captions at which the \
+             end_of_caption control code displaying them is output",
+            0,
+            std::u32::MAX,
+            DEFAULT_FLIP_OFFSET,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("wrap", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Wrap",
+            "Wrap lines that don't fit in a row at word boundaries in pop-on and \
+             paint-on modes, instead of dropping the characters past the 32nd column",
+            DEFAULT_WRAP,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("max-rows", |name| {
+        glib::ParamSpec::uint(
+            name,
+            "Max Rows",
+            "Maximum number of rows of pop-on and paint-on captions, the rows after it \
+             are dropped. The default is what caption style guides usually recommend, \
+             raising it past 2 raises the default latency",
+            1,
+            15,
+            DEFAULT_MAX_ROWS,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("paginate", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Paginate",
+            "Split pop-on captions with more lines than max-rows into successive \
+             captions sharing their duration, instead of dropping the extra rows",
+            DEFAULT_PAGINATE,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("debug-output", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Debug Output",
+            "Output a text description of each byte pair along with its frame number \
+             instead of the byte pairs, for debugging and testing purposes",
+            DEFAULT_DEBUG_OUTPUT,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("alignment", |name| {
+        glib::ParamSpec::enum_(
+            name,
+            "Alignment",
+            "Alignment of the lines of pop-on and paint-on captions, between origin-column \
+             and the last column",
+            Alignment::static_type(),
+            DEFAULT_ALIGNMENT as i32,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("fallback", |name| {
+        glib::ParamSpec::enum_(
+            name,
+            "Fallback",
+            "What to do with characters CEA-608 can't represent: replace them with the \
+             replacement-char, drop them, or transliterate common punctuation to ASCII",
+            Fallback::static_type(),
+            DEFAULT_FALLBACK as i32,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("latency", |name| {
+        glib::ParamSpec::int(
+            name,
+            "Latency",
+            "Latency in frames introduced in pop-on mode to output the byte pairs of a \
+             caption ahead of its PTS, -1 to follow max-rows, double-control-codes and \
+             erase-non-displayed, 74 frames with their defaults. Captions that need more \
+             byte pairs than this, for instance made up of extended characters, will be \
+             output late",
+            -1,
+            std::i32::MAX,
+            -1,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("replacement-char", |name| {
+        glib::ParamSpec::string(
+            name,
+            "Replacement Char",
+            "Character replacing those CEA-608 can't represent, a space if it can't be \
+             represented itself",
+            Some(" "),
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("default-duration", |name| {
+        glib::ParamSpec::uint64(
+            name,
+            "Default Duration",
+            "Duration (in ns) given at EOS to a last caption without a duration, \
+             other captions without a duration lasting until the next one",
+            0,
+            std::u64::MAX,
+            DEFAULT_DEFAULT_DURATION,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("clear-on-empty", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Clear On Empty",
+            "Erase the display on empty or whitespace-only buffers instead of ignoring them",
+            DEFAULT_CLEAR_ON_EMPTY,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("dynamic-latency", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Dynamic Latency",
+            "Report the largest number of byte pairs output for a pop-on caption so far \
+             as the latency instead of the latency property, increasing it as needed",
+            DEFAULT_DYNAMIC_LATENCY,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("synthesize-pts", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Synthesize PTS",
+            "Timestamp buffers without a PTS with the end of the previous buffer, \
+             or the segment position, instead of erroring out",
+            DEFAULT_SYNTHESIZE_PTS,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("trim-trailing", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Trim Trailing",
+            "Remove whitespace at the end of each line, and empty lines at the end \
+             of the text, so that they don't take up columns or rows",
+            DEFAULT_TRIM_TRAILING,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("roll-up-rows", |name| {
+        glib::ParamSpec::uint(
+            name,
+            "Roll-up Rows",
+            "Number of rows of the roll-up window, from 2 to 4, overriding the one of the \
+             roll-up mode (0 = follow the mode). Changes while playing take effect on the \
+             next carriage return",
+            0,
+            4,
+            DEFAULT_ROLL_UP_ROWS,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("collapse-spaces", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Collapse Spaces",
+            "Collapse runs of whitespace within lines into a single space, instead of \
+             encoding each of them",
+            DEFAULT_COLLAPSE_SPACES,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("stats", |name| {
+        glib::ParamSpec::boxed(
+            name,
+            "Statistics",
+            "Byte pairs output, erase_display_memory sequences output, characters \
+             dropped for lack of room and characters replaced with replacement-char",
+            gst::Structure::static_type(),
+            glib::ParamFlags::READABLE,
+        )
+    }),
+    subclass::Property("erase-non-displayed", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Erase Non-displayed",
+            "Erase the non-displayed memory before loading each pop-on caption. Disable \
+             when each caption overwrites all the cells of the one before it, to save two \
+             byte pairs, and two frames of latency with the default latency",
+            DEFAULT_ERASE_NON_DISPLAYED,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("out-of-order", |name| {
+        glib::ParamSpec::enum_(
+            name,
+            "Out Of Order",
+            "What to do with captions with a PTS before that of the previous one. \
+             Reordering holds back the last 2 captions until later ones arrive, and \
+             drops those that arrive too late",
+            OutOfOrder::static_type(),
+            DEFAULT_OUT_OF_ORDER as i32,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("lossy-decode", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Lossy Decode",
+            "Replace invalid UTF-8 sequences in the input with U+FFFD, which is then \
+             handled according to the fallback, instead of erroring out",
+            DEFAULT_LOSSY_DECODE,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("charset", |name| {
+        glib::ParamSpec::enum_(
+            name,
+            "Charset",
+            "Character encoding of the input text, lossy-decode only applies to UTF-8",
+            Charset::static_type(),
+            DEFAULT_CHARSET as i32,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("double-control-codes", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Double Control Codes",
+            "Transmit control codes, preambles and mid-row codes twice, as line-21 \
+             requires for redundancy. Disable for 608 over 708 to save byte pairs and \
+             latency",
+            DEFAULT_DOUBLE_CONTROL_CODES,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("clear-on-eos", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Clear On EOS",
+            "Erase the display at the end of the stream even if no erasure is pending, \
+             such as after roll-up captions",
+            DEFAULT_CLEAR_ON_EOS,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("xds-program-name", |name| {
+        glib::ParamSpec::string(
+            name,
+            "XDS Program Name",
+            "Program name sent repeatedly in XDS packets on the second field, alongside the \
+             frames carrying captions. Only the cc_data, cdp and s334-1a formats can carry \
+             them, with captions on the first field",
+            None,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("xds-tv-rating", |name| {
+        glib::ParamSpec::enum_(
+            name,
+            "XDS TV Rating",
+            "U.S. TV Parental Guidelines rating sent in XDS content advisory packets, like \
+             xds-program-name",
+            XdsTvRating::static_type(),
+            DEFAULT_XDS_TV_RATING as i32,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("max-on-screen", |name| {
+        glib::ParamSpec::uint64(
+            name,
+            "Max On Screen",
+            "Longest time (in ns) a caption stays displayed, roll-up ones included, 0 for \
+             no limit. The erasure is output along with the next buffer, gap event or EOS, \
+             so live sources need to send gap events for it to be output in time",
+            0,
+            std::u64::MAX,
+            DEFAULT_MAX_ON_SCREEN,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+];
+
+#[derive(Debug, Clone)]
+struct Settings {
+    mode: Mode,
+    base_row: u32,
+    origin_row: u32,
+    origin_column: u32,
+    reading_speed: u32,
+    reading_speed_action: ReadingSpeedAction,
+    enable_markup: bool,
+    italics: bool,
+    underline: bool,
+    color: Color,
+    ts_offset: i64,
+    lead_time: u64,
+    min_duration: u64,
+    pre_clear_gap: u64,
+    window_start: u64,
+    window_stop: u64,
+    merge_short: u64,
+    channel: u32,
+    field: u32,
+    flip_offset: u32,
+    wrap: bool,
+    max_rows: u32,
+    paginate: bool,
+    debug_output: bool,
+    alignment: Alignment,
+    fallback: Fallback,
+    latency: Option<u32>,
+    replacement_char: char,
+    default_duration: u64,
+    clear_on_empty: bool,
+    dynamic_latency: bool,
+    synthesize_pts: bool,
+    trim_trailing: bool,
+    roll_up_rows: u32,
+    collapse_spaces: bool,
+    erase_non_displayed: bool,
+    out_of_order: OutOfOrder,
+    lossy_decode: bool,
+    charset: Charset,
+    double_control_codes: bool,
+    clear_on_eos: bool,
+    xds_program_name: Option<String>,
+    xds_tv_rating: XdsTvRating,
+    max_on_screen: u64,
+}
+
+impl Settings {
+    /* Height of the roll-up window, in rows, in roll-up modes */
+    fn roll_up_rows(&self) -> Option<u32> {
+        self.mode.roll_up_rows().map(|rows| {
+            if self.roll_up_rows != 0 {
+                self.roll_up_rows
+            } else {
+                rows
+            }
+        })
+    }
+
+    /* Latency in frames when the latency property is left unset, see
+     * LATENCY_BUFFERS. Never lower than for 2 rows */
+    fn default_latency(&self) -> u64 {
+        let copies = if self.double_control_codes { 2 } else { 1 };
+        let rows = std::cmp::max(self.max_rows, 2) as u64;
+
+        /* resume_caption_loading, the spliced erase_display_memory and
+         * a preamble per row */
+        let mut control_codes = 2 + rows;
+        if self.erase_non_displayed {
+            control_codes += 1;
+        }
+
+        control_codes * copies + 32 * rows
+    }
+
+    /* libcaption channel number, see eia608_control_command() */
+    fn channel_index(&self) -> i32 {
+        let field = if self.channel > 2 { 2 } else { self.field };
+
+        ((self.channel - 1) & 1 | (field - 1) << 1) as i32
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            mode: DEFAULT_MODE,
+            base_row: DEFAULT_BASE_ROW,
+            origin_row: DEFAULT_ORIGIN_ROW,
+            origin_column: DEFAULT_ORIGIN_COLUMN,
+            reading_speed: DEFAULT_READING_SPEED,
+            reading_speed_action: DEFAULT_READING_SPEED_ACTION,
+            enable_markup: DEFAULT_ENABLE_MARKUP,
+            italics: DEFAULT_ITALICS,
+            underline: DEFAULT_UNDERLINE,
+            color: DEFAULT_COLOR,
+            ts_offset: DEFAULT_TS_OFFSET,
+            lead_time: DEFAULT_LEAD_TIME,
+            min_duration: DEFAULT_MIN_DURATION,
+            pre_clear_gap: DEFAULT_PRE_CLEAR_GAP,
+            window_start: DEFAULT_WINDOW_START,
+            window_stop: DEFAULT_WINDOW_STOP,
+            merge_short: DEFAULT_MERGE_SHORT,
+            channel: DEFAULT_CHANNEL,
+            field: DEFAULT_FIELD,
+            flip_offset: DEFAULT_FLIP_OFFSET,
+            wrap: DEFAULT_WRAP,
+            max_rows: DEFAULT_MAX_ROWS,
+            paginate: DEFAULT_PAGINATE,
+            debug_output: DEFAULT_DEBUG_OUTPUT,
+            alignment: DEFAULT_ALIGNMENT,
+            fallback: DEFAULT_FALLBACK,
+            latency: DEFAULT_LATENCY,
+            replacement_char: DEFAULT_REPLACEMENT_CHAR,
+            default_duration: DEFAULT_DEFAULT_DURATION,
+            clear_on_empty: DEFAULT_CLEAR_ON_EMPTY,
+            dynamic_latency: DEFAULT_DYNAMIC_LATENCY,
+            synthesize_pts: DEFAULT_SYNTHESIZE_PTS,
+            trim_trailing: DEFAULT_TRIM_TRAILING,
+            roll_up_rows: DEFAULT_ROLL_UP_ROWS,
+            collapse_spaces: DEFAULT_COLLAPSE_SPACES,
+            erase_non_displayed: DEFAULT_ERASE_NON_DISPLAYED,
+            out_of_order: DEFAULT_OUT_OF_ORDER,
+            lossy_decode: DEFAULT_LOSSY_DECODE,
+            charset: DEFAULT_CHARSET,
+            double_control_codes: DEFAULT_DOUBLE_CONTROL_CODES,
+            clear_on_eos: DEFAULT_CLEAR_ON_EOS,
+            xds_program_name: None,
+            xds_tv_rating: DEFAULT_XDS_TV_RATING,
+            max_on_screen: DEFAULT_MAX_ON_SCREEN,
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, GEnum)]
+#[repr(u32)]
+#[genum(type_name = "GstTtToCea608Color")]
+enum Color {
+    White,
+    Green,
+    Blue,
+    Cyan,
+    Red,
+    Yellow,
+    Magenta,
+}
+
+const COLORS: [Color; 7] = [
+    Color::White,
+    Color::Green,
+    Color::Blue,
+    Color::Cyan,
+    Color::Red,
+    Color::Yellow,
+    Color::Magenta,
+];
+
+impl Default for Color {
+    fn default() -> Self {
+        Color::White
+    }
+}
+
+impl Color {
+    fn rgb(self) -> (i32, i32, i32) {
+        match self {
+            Color::White => (0xff, 0xff, 0xff),
+            Color::Green => (0x00, 0xff, 0x00),
+            Color::Blue => (0x00, 0x00, 0xff),
+            Color::Cyan => (0x00, 0xff, 0xff),
+            Color::Red => (0xff, 0x00, 0x00),
+            Color::Yellow => (0xff, 0xff, 0x00),
+            Color::Magenta => (0xff, 0x00, 0xff),
+        }
+    }
+
+    fn eia608_style(self) -> ffi::eia608_style_t {
+        match self {
+            Color::White => ffi::eia608_style_t_eia608_style_white,
+            Color::Green => ffi::eia608_style_t_eia608_style_green,
+            Color::Blue => ffi::eia608_style_t_eia608_style_blue,
+            Color::Cyan => ffi::eia608_style_t_eia608_style_cyan,
+            Color::Red => ffi::eia608_style_t_eia608_style_red,
+            Color::Yellow => ffi::eia608_style_t_eia608_style_yellow,
+            Color::Magenta => ffi::eia608_style_t_eia608_style_magenta,
+        }
+    }
+
+    /* Parses a color name or #rrggbb value, and snaps it to the closest
+     * color of the CEA-608 palette */
+    fn parse(value: &str) -> Option<Color> {
+        let rgb = match value {
+            "black" => (0x00, 0x00, 0x00),
+            "white" => (0xff, 0xff, 0xff),
+            "green" | "lime" => (0x00, 0xff, 0x00),
+            "blue" => (0x00, 0x00, 0xff),
+            "cyan" => (0x00, 0xff, 0xff),
+            "red" => (0xff, 0x00, 0x00),
+            "yellow" => (0xff, 0xff, 0x00),
+            "magenta" => (0xff, 0x00, 0xff),
+            _ if value.len() == 7 && value.starts_with('#') => {
+                let rgb = u32::from_str_radix(&value[1..], 16).ok()?;
+                (
+                    (rgb >> 16) as i32 & 0xff,
+                    (rgb >> 8) as i32 & 0xff,
+                    rgb as i32 & 0xff,
+                )
+            }
+            _ => return None,
+        };
+
+        let distance = |color: &Color| {
+            let (r, g, b) = color.rgb();
+            (r - rgb.0).pow(2) + (g - rgb.1).pow(2) + (b - rgb.2).pow(2)
+        };
+        let color = *COLORS.iter().min_by_key(|color| distance(color))?;
+
+        if color.rgb() != rgb {
+            gst_warning!(
+                CAT,
+                "Color {} is not part of the CEA-608 palette, using {:?}",
+                value,
+                color
+            );
+        }
+
+        Some(color)
+    }
+}
+
+/* XDS class codes and types of the packets we send */
+const XDS_CURRENT_START: u8 = 0x01;
+const XDS_PROGRAM_NAME: u8 = 0x03;
+const XDS_CONTENT_ADVISORY: u8 = 0x05;
+const XDS_END: u8 = 0x0f;
+
+/* Byte pairs of an XDS packet: the start code of its class and its type,
+ * its informational characters padded to a whole pair, then the end
+ * code and the checksum bringing the sum of all the bytes to 0, modulo
+ * 128 */
+fn xds_packet(class: u8, type_: u8, data: &[u8]) -> Vec<u16> {
+    let mut bytes = vec![class, type_];
+    bytes.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        bytes.push(0x00);
+    }
+    bytes.push(XDS_END);
+
+    let sum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    bytes.push(sum.wrapping_neg() & 0x7f);
+
+    bytes
+        .chunks(2)
+        .map(|pair| eia608_parity((pair[0] as u16) << 8 | pair[1] as u16))
+        .collect()
+}
+
+/* The packets for the XDS properties that are set. Program names are
+ * at most 32 printable ASCII characters, others are replaced with '?' */
+fn xds_packets(settings: &Settings) -> Vec<u16> {
+    let mut packets = vec![];
+
+    if let Some(ref program_name) = settings.xds_program_name {
+        let name = program_name
+            .chars()
+            .take(32)
+            .map(|c| match c {
+                ' '..='~' => c as u8,
+                _ => b'?',
+            })
+            .collect::<Vec<_>>();
+
+        if !name.is_empty() {
+            packets.extend(xds_packet(XDS_CURRENT_START, XDS_PROGRAM_NAME, &name));
+        }
+    }
+
+    if settings.xds_tv_rating != XdsTvRating::None {
+        /* U.S. TV Parental Guidelines system, without content flags */
+        packets.extend(xds_packet(
+            XDS_CURRENT_START,
+            XDS_CONTENT_ADVISORY,
+            &[0x48, 0x40 | settings.xds_tv_rating as u8],
+        ));
+    }
+
+    packets
+}
+
+/* Format negotiated with downstream */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    /* closedcaption/x-cea-608, format=raw: one byte pair per buffer */
+    Raw,
+    /* closedcaption/x-cea-608, format=s334-1a: one byte pair per buffer,
+     * prefixed with a byte identifying the field */
+    S3341a,
+    /* closedcaption/x-cea-708, format=cc_data: one cc_data triple per buffer */
+    CcData,
+    /* closedcaption/x-cea-708, format=cdp: one CDP per buffer */
+    Cdp,
+    /* text/x-raw: a description of each byte pair, see debug-output */
+    Debug,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct Style {
+    underline: bool,
+    italics: bool,
+    color: Color,
+}
+
+/* Counters reported through the stats property, and per caption
+ * through caption-stats */
+#[derive(Debug, Default, Clone)]
+struct Stats {
+    num_pairs: u64,
+    /* Characters written to the display */
+    num_chars: u64,
+    /* Rows at least one character was written on */
+    num_rows: u64,
+    num_erase_display: u64,
+    /* Characters past the 32nd column or after the last row */
+    num_dropped: u64,
+    /* Characters encoded as the replacement character */
+    num_replaced: u64,
+}
+
+struct State {
+    settings: Settings,
+    /* Input segment, we work with running times */
+    segment: gst::FormattedSegment<gst::ClockTime>,
+    framerate: gst::Fraction,
+    output_format: OutputFormat,
+    cdp_sequence_counter: u16,
+    /* Byte pairs of the XDS packets left to send on the second field */
+    xds_queue: VecDeque<u16>,
+    erase_display_frame_no: Option<u64>,
+    /* Channels the displayed caption is on */
+    displayed_channels: Vec<i32>,
+    last_frame_no: u64,
+    roll_up_column: u32,
+    send_roll_up: bool,
+    send_direct_captioning: bool,
+    preview_frame: CaptionFrame,
+    /* Style requested through markup */
+    style: Style,
+    /* Style last transmitted through a preamble or mid-row code */
+    emitted_style: Style,
+    /* Text, PTS and duration of a short caption waiting for the next one */
+    pending_short: Option<(String, gst::ClockTime, gst::ClockTime)>,
+    /* Text and PTS of a caption without a duration waiting for the next one */
+    pending_untimed: Option<(String, gst::ClockTime)>,
+    /* PTS of the caption we last posted an untranslatable message for */
+    last_untranslatable_pts: Option<gst::ClockTime>,
+    /* Largest number of byte pairs output for a pop-on caption so far */
+    max_buffers: u64,
+    /* Whether the input caps say the text is pango markup, if they do */
+    caps_markup: Option<bool>,
+    /* End of the last input buffer, to timestamp one without a PTS */
+    next_pts: gst::ClockTime,
+    /* Running time and data of the byte pairs to attach to video buffers */
+    meta_queue: VecDeque<(gst::ClockTime, [u8; 2])>,
+    video_segment: gst::FormattedSegment<gst::ClockTime>,
+    /* Row, first and last column + 1 of the displayed paint-on caption */
+    painted_rows: Vec<(i32, u32, u32)>,
+    stats: Stats,
+    /* First row of the caption being encoded, for caption-rendered */
+    caption_row: Option<i32>,
+    /* Running time of the last caption handled */
+    last_pts: gst::ClockTime,
+    /* Text, running time and duration of the captions held back to be
+     * reordered, sorted by running time */
+    reorder_queue: Vec<(String, gst::ClockTime, gst::ClockTime)>,
+    /* Emptied after each caption, only kept to reuse its allocation. It
+     * retains the capacity of the largest caption so far, which is
+     * bounded by what a full screen of text needs, so it's never shrunk */
+    scratch_buffers: Vec<gst::Buffer>,
+    /* Active from ReadyToPaused to PausedToReady, unless it couldn't be
+     * set up, see new_cc_data_pool() */
+    cc_data_pool: Option<gst::BufferPool>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            settings: Settings::default(),
+            segment: gst::FormattedSegment::new(),
+            framerate: gst::Fraction::new(DEFAULT_FPS_N, DEFAULT_FPS_D),
+            output_format: OutputFormat::Raw,
+            cdp_sequence_counter: 0,
+            xds_queue: VecDeque::new(),
+            erase_display_frame_no: None,
+            displayed_channels: vec![],
+            last_frame_no: 0,
+            roll_up_column: 0,
+            send_roll_up: false,
+            send_direct_captioning: false,
+            preview_frame: CaptionFrame::default(),
+            style: Style::default(),
+            emitted_style: Style::default(),
+            pending_short: None,
+            pending_untimed: None,
+            last_untranslatable_pts: None,
+            max_buffers: 0,
+            caps_markup: None,
+            next_pts: gst::CLOCK_TIME_NONE,
+            meta_queue: VecDeque::new(),
+            video_segment: gst::FormattedSegment::new(),
+            painted_rows: vec![],
+            stats: Stats::default(),
+            caption_row: None,
+            last_pts: gst::CLOCK_TIME_NONE,
+            reorder_queue: vec![],
+            scratch_buffers: Vec::with_capacity(LATENCY_BUFFERS as usize),
+            cc_data_pool: None,
+        }
+    }
+}
+
+impl State {
+    /* Latency in frames in pop-on mode */
+    fn latency(&self) -> u64 {
+        if self.settings.dynamic_latency {
+            self.max_buffers
+        } else {
+            self.settings
+                .latency
+                .map(u64::from)
+                .unwrap_or_else(|| self.settings.default_latency())
+        }
+    }
+
+    /* Next byte pair of the XDS packets, which are repeated for as long
+     * as they're set, unless our captions take the second field */
+    fn next_xds_pair(&mut self) -> Option<[u8; 2]> {
+        if self.settings.channel_index() >> 1 != 0 {
+            return None;
+        }
+
+        if self.xds_queue.is_empty() {
+            self.xds_queue.extend(xds_packets(&self.settings));
+        }
+
+        self.xds_queue
+            .pop_front()
+            .map(|cc_data| [(cc_data >> 8) as u8, cc_data as u8])
+    }
+
+    /* Number of times each control code is output */
+    fn control_code_copies(&self) -> u64 {
+        if self.settings.double_control_codes {
+            2
+        } else {
+            1
+        }
+    }
+
+    /* Converts the frame numbers we keep track of to a new framerate,
+     * rounding up so that timestamps keep increasing */
+    fn rescale_frame_numbers(&mut self, framerate: gst::Fraction) {
+        let num = *self.framerate.denom() as u64 * *framerate.numer() as u64;
+        let denom = *self.framerate.numer() as u64 * *framerate.denom() as u64;
+
+        self.last_frame_no = self.last_frame_no.mul_div_ceil(num, denom).unwrap();
+        self.erase_display_frame_no = self
+            .erase_display_frame_no
+            .map(|frame_no| frame_no.mul_div_ceil(num, denom).unwrap());
+    }
+
+    /* Whether markup in the input text is interpreted */
+    fn markup(&self) -> bool {
+        self.caps_markup.unwrap_or(self.settings.enable_markup)
+    }
+
+    /* Gap event covering the frames from last_frame_no to new_frame_no */
+    fn gap_event(&self, last_frame_no: u64, new_frame_no: u64) -> Option<gst::Event> {
+        if last_frame_no >= new_frame_no {
+            return None;
+        }
+
+        let (fps_n, fps_d) = (
+            *self.framerate.numer() as u64,
+            *self.framerate.denom() as u64,
+        );
+        let start = (last_frame_no * gst::SECOND)
+            .mul_div_round(fps_d, fps_n)
+            .unwrap();
+        let end = (new_frame_no * gst::SECOND)
+            .mul_div_round(fps_d, fps_n)
+            .unwrap();
+
+        Some(gst::event::Gap::new(start, end - start))
+    }
+}
+
+/* Everything to push, post or emit, prepared while holding the state
+ * lock and done in order after releasing it, see push_outputs() */
+enum Output {
+    List {
+        /* Covers the frames between the previous list and this one */
+        gap: Option<gst::Event>,
+        bufferlist: gst::BufferList,
+        preview: Option<(gst::Pad, gst::Buffer)>,
+    },
+    Gap(gst::Event),
+    /* Posted and emitted without holding the state lock, so that
+     * handlers can read our properties */
+    Message(gst::Message),
+    CaptionStats(u64, gst::Structure),
+    CaptionRendered(u64, u64, u32, String),
+}
+
+struct TtToCea608 {
+    srcpad: gst::Pad,
+    sinkpad: gst::Pad,
+    preview_srcpad: Mutex<Option<gst::Pad>>,
+    /* Video sink and source pads, see video_sink_chain() */
+    video_pads: Mutex<Option<(gst::Pad, gst::Pad)>>,
+
+    state: Mutex<State>,
+    settings: Mutex<Settings>,
+}
+
+lazy_static! {
+    static ref CAT: gst::DebugCategory = gst::DebugCategory::new(
+        "tttocea608",
+        gst::DebugColorFlags::empty(),
+        Some("TT CEA 608 Element"),
+    );
+    static ref SPACE: u16 = eia608_from_utf8_1(&[0x20, 0, 0, 0, 0], 0);
+}
+
+impl TtToCea608 {
+    /* Run the cc_data we are about to output through the decoder, and
+     * return the resulting text if the displayed memory was updated */
+    fn decode_preview(
+        &self,
+        state: &mut State,
+        bufferlist: &gst::BufferList,
+    ) -> Option<(gst::Pad, gst::Buffer)> {
+        let pad = self.preview_srcpad.lock().unwrap().clone()?;
+        let mut preview_pts = None;
+
+        for buffer in bufferlist.iter() {
+            let data = buffer.map_readable().ok()?;
+            let cc_data = (data[0] as u16) << 8 | data[1] as u16;
+            let pts = buffer.get_pts();
+
+            if let Ok(Status::Ready) = state
+                .preview_frame
+                .decode(cc_data, (pts.unwrap() as f64) / 1_000_000_000.0)
+            {
+                preview_pts = Some(pts);
+            }
+        }
+
+        /* In roll-up mode, text is written to the displayed memory directly */
+        if state.settings.mode.is_roll_up() && preview_pts.is_none() {
+            preview_pts = bufferlist.get(0).map(|buffer| buffer.get_pts());
+        }
+
+        let pts = preview_pts?;
+        let text = state.preview_frame.to_text(false).ok()?;
+
+        let mut buffer = gst::Buffer::from_mut_slice(text.into_bytes());
+        buffer.make_mut().set_pts(pts);
+
+        Some((pad, buffer))
+    }
+
+    /* Converts the byte pairs to the output format and updates the state
+     * accordingly, without pushing anything */
+    fn prepare_list(
+        &self,
+        state: &mut State,
+        bufferlist: gst::BufferList,
+        last_frame_no: u64,
+        new_frame_no: u64,
+    ) -> Result<Output, gst::FlowError> {
+        let preview = self.decode_preview(state, &bufferlist);
+        let attach_meta = self.video_pads.lock().unwrap().is_some();
+
+        state.stats.num_pairs += bufferlist.len() as u64;
+
+        if attach_meta {
+            for buffer in bufferlist.iter() {
+                let data = buffer.map_readable().map_err(|_| gst::FlowError::Error)?;
+                state
+                    .meta_queue
+                    .push_back((buffer.get_pts(), [data[0], data[1]]));
+            }
+        }
+
+        let output_format = state.output_format;
+        let bufferlist = match output_format {
+            OutputFormat::Raw => bufferlist,
+            OutputFormat::CcData => {
+                /* cc_valid and cc_type 0 or 1 for field 1 or 2 data */
+                let cc_type = 0xfc | (state.settings.channel_index() >> 1) as u8;
+
+                convert_bufferlist(&bufferlist, |_pts, data| {
+                    let mut triples = vec![cc_type, data[0], data[1]];
+                    if let Some(xds) = state.next_xds_pair() {
+                        triples.extend_from_slice(&[0xfd, xds[0], xds[1]]);
+                    }
+                    triples
+                })?
+            }
+            OutputFormat::S3341a => {
+                /* Field 1 flag, line offset 0 */
+                let flags = if state.settings.channel_index() >> 1 == 0 {
+                    0x80
+                } else {
+                    0x00
+                };
+
+                convert_bufferlist(&bufferlist, |_pts, data| {
+                    let mut triples = vec![flags, data[0], data[1]];
+                    if let Some(xds) = state.next_xds_pair() {
+                        triples.extend_from_slice(&[0x00, xds[0], xds[1]]);
+                    }
+                    triples
+                })?
+            }
+            OutputFormat::Cdp => {
+                let cc_type = 0xfc | (state.settings.channel_index() >> 1) as u8;
+                let (fps_n, fps_d) = (
+                    *state.framerate.numer() as u64,
+                    *state.framerate.denom() as u64,
+                );
+                let (framerate_code, cc_count) =
+                    cdp::framerate_code(state.framerate).ok_or(gst::FlowError::NotNegotiated)?;
+
+                convert_bufferlist(&bufferlist, |pts, data| {
+                    let frame_no =
+                        (pts.mul_div_round(fps_n, fps_d).unwrap() / gst::SECOND).unwrap();
+                    let sequence_counter = state.cdp_sequence_counter;
+                    state.cdp_sequence_counter = sequence_counter.wrapping_add(1);
+
+                    let mut triples = vec![[cc_type, data[0], data[1]]];
+                    if let Some(xds) = state.next_xds_pair() {
+                        triples.push([0xfd, xds[0], xds[1]]);
+                    }
+
+                    cdp::packet(
+                        &triples,
+                        framerate_code,
+                        cc_count,
+                        cdp::time_code(frame_no, fps_n, fps_d),
+                        sequence_counter,
+                    )
+                })?
+            }
+            OutputFormat::Debug => {
+                let (fps_n, fps_d) = (
+                    *state.framerate.numer() as u64,
+                    *state.framerate.denom() as u64,
+                );
+
+                convert_bufferlist(&bufferlist, |pts, data| {
+                    let cc_data = (data[0] as u16) << 8 | data[1] as u16;
+                    let frame_no =
+                        (pts.mul_div_round(fps_n, fps_d).unwrap() / gst::SECOND).unwrap();
+
+                    format!("frame {}: {}", frame_no, describe_cc_data(cc_data)).into_bytes()
+                })?
+            }
+        };
+
+        Ok(Output::List {
+            gap: state.gap_event(last_frame_no, new_frame_no),
+            bufferlist,
+            preview,
+        })
+    }
+
+    /* Must be called without holding the state lock */
+    fn push_outputs(
+        &self,
+        element: &gst::Element,
+        outputs: Vec<Output>,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        for output in outputs {
+            match output {
+                Output::List {
+                    gap,
+                    bufferlist,
+                    preview,
+                } => {
+                    if let Some(gap) = gap {
+                        let _ = self.srcpad.push_event(gap);
+                    }
+
+                    let ret = self.srcpad.push_list(bufferlist);
+
+                    if let Some((pad, buffer)) = preview {
+                        gst_log!(CAT, obj: &pad, "Pushing preview {:?}", buffer);
+                        let _ = pad.push(buffer);
+                    }
+
+                    ret.map_err(|err| {
+                        gst_error!(CAT, obj: &self.srcpad, "Pushing buffer returned {:?}", err);
+                        err
+                    })?;
+                }
+                Output::Gap(gap) => {
+                    let _ = self.srcpad.push_event(gap);
+                }
+                Output::Message(message) => {
+                    let _ = element.post_message(message);
+                }
+                Output::CaptionStats(pts, s) => {
+                    element
+                        .emit("caption-stats", &[&pts, &s])
+                        .map_err(|_| gst::FlowError::Error)?;
+                }
+                Output::CaptionRendered(pts, duration, row, text) => {
+                    element
+                        .emit("caption-rendered", &[&pts, &duration, &row, &text])
+                        .map_err(|_| gst::FlowError::Error)?;
+                }
+            }
+        }
+
+        Ok(gst::FlowSuccess::Ok)
+    }
+
+    /* Builds the erase_display_memory control codes for the given channels,
+     * on the frames preceding erase_display_frame_no, and returns them
+     * along with the frame number of the first one */
+    fn erase_display_list(
+        state: &mut State,
+        min_frame_no: u64,
+        mut erase_display_frame_no: u64,
+        channels: &[i32],
+    ) -> (gst::BufferList, u64) {
+        let (fps_n, fps_d) = (
+            *state.framerate.numer() as u64,
+            *state.framerate.denom() as u64,
+        );
+        let cc_data_pool = state.cc_data_pool.clone();
+        let pool = cc_data_pool.as_ref();
+
+        let mut bufferlist = gst::BufferList::new();
+
+        state.last_frame_no = erase_display_frame_no;
+        state.painted_rows.clear();
+        state.stats.num_erase_display += 1;
+        /* The next roll-up caption starts over on an empty window */
+        if state.settings.mode.is_roll_up() {
+            state.send_roll_up = true;
+        }
+
+        for channel in channels.iter().rev() {
+            for _ in 0..state.control_code_copies() {
+                let (pts, duration) =
+                    decrement_pts(min_frame_no, &mut erase_display_frame_no, fps_n, fps_d);
+                erase_display_memory_with_pts(bufferlist.make_mut(), pool, pts, duration, *channel);
+            }
+        }
+
+        (bufferlist, erase_display_frame_no)
+    }
+
+    fn do_erase_display(
+        &self,
+        state: &mut State,
+        outputs: &mut Vec<Output>,
+        min_frame_no: u64,
+        erase_display_frame_no: u64,
+        channels: &[i32],
+    ) -> Result<(), gst::FlowError> {
+        let (bufferlist, start_frame_no) =
+            Self::erase_display_list(state, min_frame_no, erase_display_frame_no, channels);
+        outputs.push(self.prepare_list(state, bufferlist, min_frame_no, start_frame_no)?);
+
+        Ok(())
+    }
+
+    /* Outputs the pending erase_display_memory when nothing follows it */
+    fn drain_erase_display(
+        &self,
+        state: &mut State,
+        outputs: &mut Vec<Output>,
+    ) -> Result<(), gst::FlowError> {
+        let erase_display_frame_no = match state.erase_display_frame_no.take() {
+            Some(erase_display_frame_no) => erase_display_frame_no,
+            None => return Ok(()),
+        };
+        let min_frame_no = state.last_frame_no;
+        let channels = state.displayed_channels.clone();
+        let copies = state.control_code_copies();
+
+        /* Make sure there is room for its control codes after the last
+         * frame we output so they don't get collapsed on top of each other */
+        let erase_display_frame_no = std::cmp::max(
+            erase_display_frame_no,
+            min_frame_no + copies * channels.len() as u64,
+        );
+
+        self.do_erase_display(
+            state,
+            outputs,
+            min_frame_no,
+            erase_display_frame_no,
+            &channels,
+        )
+    }
+
+    /* Erases the roll-up window if it expired before the caption at pts,
+     * otherwise that caption keeps it displayed */
+    fn expire_roll_up(
+        &self,
+        state: &mut State,
+        outputs: &mut Vec<Output>,
+        pts: gst::ClockTime,
+    ) -> Result<(), gst::FlowError> {
+        if !state.settings.mode.is_roll_up() {
+            return Ok(());
+        }
+
+        let erase_display_frame_no = match state.erase_display_frame_no.take() {
+            Some(erase_display_frame_no) => erase_display_frame_no,
+            None => return Ok(()),
+        };
+
+        let (fps_n, fps_d) = (
+            *state.framerate.numer() as u64,
+            *state.framerate.denom() as u64,
+        );
+        let frame_no = (pts.mul_div_round(fps_n, fps_d).unwrap() / gst::SECOND).unwrap();
+        if erase_display_frame_no > frame_no {
+            return Ok(());
+        }
+
+        let min_frame_no = state.last_frame_no;
+        let channels = state.displayed_channels.clone();
+
+        self.do_erase_display(
+            state,
+            outputs,
+            min_frame_no,
+            erase_display_frame_no,
+            &channels,
+        )
+    }
+
+    /* Erases the display right after the last frame we output, for when
+     * no erasure is pending but a caption may still be displayed */
+    fn force_erase_display(
+        &self,
+        state: &mut State,
+        outputs: &mut Vec<Output>,
+    ) -> Result<(), gst::FlowError> {
+        let min_frame_no = state.last_frame_no;
+        let channels = if state.displayed_channels.is_empty() {
+            vec![state.settings.channel_index()]
+        } else {
+            std::mem::replace(&mut state.displayed_channels, vec![])
+        };
+        let copies = state.control_code_copies();
+
+        self.do_erase_display(
+            state,
+            outputs,
+            min_frame_no,
+            min_frame_no + copies * channels.len() as u64,
+            &channels,
+        )
+    }
+
+    fn output_cert_pattern(
+        &self,
+        state: &mut State,
+        outputs: &mut Vec<Output>,
+        pts: gst::ClockTime,
+    ) -> Result<(), gst::FlowError> {
+        let (fps_n, fps_d) = (
+            *state.framerate.numer() as u64,
+            *state.framerate.denom() as u64,
+        );
+        let cc_data_pool = state.cc_data_pool.clone();
+        let pool = cc_data_pool.as_ref();
+
+        let mut frame_no = (pts.mul_div_round(fps_n, fps_d).unwrap() / gst::SECOND).unwrap();
+        let last_frame_no = state.last_frame_no;
+        frame_no = std::cmp::max(frame_no, last_frame_no);
+        let start_frame_no = frame_no;
+
+        let mut bufferlist = gst::BufferList::new();
+        {
+            let bufferlist = bufferlist.make_mut();
+
+            for cc_data in cert_pattern(state.settings.channel_index()) {
+                let (pts, duration) = increment_pts(&mut frame_no, std::u64::MAX, fps_n, fps_d);
+                let mut buffer = buffer_from_cc_data(pool, cc_data);
+                set_timestamps(&mut buffer, pts, duration);
+                bufferlist.insert(-1, buffer);
+            }
+        }
+
+        state.last_frame_no = frame_no;
+
+        outputs.push(self.prepare_list(state, bufferlist, last_frame_no, start_frame_no)?);
+
+        Ok(())
+    }
+
+    /* Everything up to the pad pushes happens under a single lock of the
+     * state, what to push is collected in outputs and pushed once it is
+     * released */
+    #[allow(clippy::cognitive_complexity)]
+    fn sink_chain(
+        &self,
+        pad: &gst::Pad,
+        element: &gst::Element,
+        buffer: gst::Buffer,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let mut state = self.state.lock().unwrap();
+
+        let pts = match buffer.get_pts() {
+            gst::CLOCK_TIME_NONE if state.settings.synthesize_pts => {
+                let pts = if state.next_pts != gst::CLOCK_TIME_NONE {
+                    state.next_pts
+                } else if state.segment.get_position() != gst::CLOCK_TIME_NONE {
+                    state.segment.get_position()
+                } else {
+                    state.segment.get_start()
+                };
+
+                gst_debug!(CAT, obj: pad, "Synthesized PTS {} for buffer", pts);
+
+                pts
+            }
+            gst::CLOCK_TIME_NONE => {
+                drop(state);
+                gst_element_error!(
+                    element,
+                    gst::StreamError::Format,
+                    ["Stream with timestamped buffers required"]
+                );
+                return Err(gst::FlowError::Error);
+            }
+            pts => pts,
+        };
+
+        /* Synthesized timestamps follow the end of this buffer */
+        state.next_pts = match buffer.get_duration() {
+            gst::CLOCK_TIME_NONE => pts,
+            duration => pts + duration,
+        };
+
+        let pts = state.segment.to_running_time(pts);
+        if pts == gst::CLOCK_TIME_NONE {
+            gst_debug!(CAT, obj: pad, "Dropping buffer outside of the segment");
+            return Ok(gst::FlowSuccess::Ok);
+        }
+
+        let data = buffer.map_readable().map_err(|_| {
+            gst_error!(CAT, obj: pad, "Can't map buffer readable");
+
+            gst::FlowError::Error
+        })?;
+
+        let decoded;
+        let data = match std::str::from_utf8(&data) {
+            _ if state.settings.charset != Charset::Utf8 => {
+                decoded = decode_8bit(&data, state.settings.charset);
+                decoded.as_str()
+            }
+            Ok(data) => data,
+            Err(err) if state.settings.lossy_decode => {
+                gst_warning!(
+                    CAT,
+                    obj: pad,
+                    "Replacing invalid utf8 from byte {}: {}",
+                    err.valid_up_to(),
+                    err
+                );
+
+                decoded = String::from_utf8_lossy(&data).into_owned();
+                decoded.as_str()
+            }
+            Err(err) => {
+                gst_error!(
+                    CAT,
+                    obj: pad,
+                    "Can't decode utf8 at byte {}: {}",
+                    err.valid_up_to(),
+                    err
+                );
+
+                return Err(gst::FlowError::Error);
+            }
+        };
+
+        let trimmed;
+        let data = if state.settings.trim_trailing {
+            trimmed = trim_trailing(data);
+            trimmed.as_str()
+        } else {
+            data
+        };
+
+        let duration = buffer.get_duration();
+
+        if state.last_pts != gst::CLOCK_TIME_NONE && pts < state.last_pts {
+            if state.settings.out_of_order == OutOfOrder::Clamp {
+                gst_debug!(
+                    CAT,
+                    obj: pad,
+                    "Caption at {} before the previous one at {}",
+                    pts,
+                    state.last_pts
+                );
+            } else {
+                gst_warning!(
+                    CAT,
+                    obj: pad,
+                    "Dropping caption at {} before the previous one at {}",
+                    pts,
+                    state.last_pts
+                );
+                return Ok(gst::FlowSuccess::Ok);
+            }
+        }
+
+        let mut outputs = vec![];
+
+        if state.settings.out_of_order == OutOfOrder::Reorder {
+            let idx = state
+                .reorder_queue
+                .iter()
+                .position(|(_, queued_pts, _)| *queued_pts > pts)
+                .unwrap_or_else(|| state.reorder_queue.len());
+            state
+                .reorder_queue
+                .insert(idx, (data.to_string(), pts, duration));
+
+            if state.reorder_queue.len() > REORDER_QUEUE_SIZE {
+                let (data, pts, duration) = state.reorder_queue.remove(0);
+                self.handle_text(&mut state, &mut outputs, element, &data, pts, duration)?;
+            }
+        } else {
+            self.handle_text(&mut state, &mut outputs, element, data, pts, duration)?;
+        }
+
+        drop(state);
+
+        self.push_outputs(element, outputs)
+    }
+
+    /* Handles the text of an input buffer, in running time */
+    fn handle_text(
+        &self,
+        state: &mut State,
+        outputs: &mut Vec<Output>,
+        element: &gst::Element,
+        data: &str,
+        pts: gst::ClockTime,
+        duration: gst::ClockTime,
+    ) -> Result<(), gst::FlowError> {
+        state.last_pts = pts;
+
+        /* A caption without a duration lasts until the next one */
+        if let Some((text, pending_pts)) = state.pending_untimed.take() {
+            let duration = if pts > pending_pts {
+                pts - pending_pts
+            } else {
+                0.into()
+            };
+
+            self.handle_caption(state, outputs, element, &text, pending_pts, duration)?;
+        }
+
+        if is_blank(data, state.markup()) {
+            return self.handle_empty_caption(state, outputs, element, pts);
+        }
+
+        if duration == gst::CLOCK_TIME_NONE {
+            gst_debug!(
+                CAT,
+                obj: element,
+                "Holding caption at {} without a duration until the next one",
+                pts
+            );
+            state.pending_untimed = Some((data.to_string(), pts));
+            return Ok(());
+        }
+
+        self.handle_caption(state, outputs, element, data, pts, duration)
+    }
+
+    /* Nothing to encode until end, either because upstream sent a gap or
+     * because we dropped a whole caption: erase the display if it is
+     * due by then, otherwise push a gap up to it or up to the pending
+     * erase_display_memory, whichever comes first */
+    fn handle_gap(
+        &self,
+        state: &mut State,
+        outputs: &mut Vec<Output>,
+        end: gst::ClockTime,
+    ) -> Result<(), gst::FlowError> {
+        let (fps_n, fps_d) = (
+            *state.framerate.numer() as u64,
+            *state.framerate.denom() as u64,
+        );
+
+        let mut frame_no = (end.mul_div_round(fps_n, fps_d).unwrap() / gst::SECOND).unwrap();
+
+        if state.settings.mode == Mode::PopOn {
+            let latency = state.latency();
+
+            if frame_no < latency {
+                return Ok(());
+            }
+
+            frame_no -= latency;
+        }
+
+        /* Roll-up captions only have an erasure pending with
+         * max-on-screen */
+        if let Some(erase_display_frame_no) = state.erase_display_frame_no {
+            if erase_display_frame_no <= frame_no {
+                let min_frame_no = state.last_frame_no;
+                let channels = state.displayed_channels.clone();
+                state.erase_display_frame_no = None;
+
+                return self.do_erase_display(
+                    state,
+                    outputs,
+                    min_frame_no,
+                    erase_display_frame_no,
+                    &channels,
+                );
+            }
+
+            /* Leave room for its control codes */
+            let n_frames = state.control_code_copies() * state.displayed_channels.len() as u64;
+            frame_no = std::cmp::min(frame_no, erase_display_frame_no.saturating_sub(n_frames));
+        }
+
+        let last_frame_no = state.last_frame_no;
+        state.last_frame_no = std::cmp::max(last_frame_no, frame_no);
+        outputs.extend(state.gap_event(last_frame_no, frame_no).map(Output::Gap));
+
+        Ok(())
+    }
+
+    /* Empty captions are skipped, or erase the display with clear-on-empty */
+    fn handle_empty_caption(
+        &self,
+        state: &mut State,
+        outputs: &mut Vec<Output>,
+        element: &gst::Element,
+        pts: gst::ClockTime,
+    ) -> Result<(), gst::FlowError> {
+        if !state.settings.clear_on_empty {
+            gst_debug!(CAT, obj: element, "Skipping empty caption at {}", pts);
+            return Ok(());
+        }
+
+        let (pts, _) = schedule(&state.settings, pts, 0.into());
+        let (fps_n, fps_d) = (
+            *state.framerate.numer() as u64,
+            *state.framerate.denom() as u64,
+        );
+        let frame_no = (pts.mul_div_round(fps_n, fps_d).unwrap() / gst::SECOND).unwrap();
+
+        let min_frame_no = state.last_frame_no;
+        let channels = if state.displayed_channels.is_empty() {
+            vec![state.settings.channel_index()]
+        } else {
+            std::mem::replace(&mut state.displayed_channels, vec![])
+        };
+        state.erase_display_frame_no = None;
+        let copies = state.control_code_copies();
+
+        gst_debug!(CAT, obj: element, "Erasing display for empty caption at {}", pts);
+
+        /* do_erase_display outputs its control codes on the frames
+         * preceding that one */
+        self.do_erase_display(
+            state,
+            outputs,
+            min_frame_no,
+            std::cmp::max(frame_no, min_frame_no) + copies,
+            &channels,
+        )
+    }
+
+    /* Handles a caption, then tells the application what encoding it
+     * took, even when it was merged or held back */
+    fn handle_caption(
+        &self,
+        state: &mut State,
+        outputs: &mut Vec<Output>,
+        element: &gst::Element,
+        data: &str,
+        pts: gst::ClockTime,
+        duration: gst::ClockTime,
+    ) -> Result<(), gst::FlowError> {
+        let before = state.stats.clone();
+
+        self.process_caption(state, outputs, element, data, pts, duration)?;
+
+        let stats = &state.stats;
+        let s = gst::Structure::builder("application/x-tttocea608-caption-stats")
+            .field("num-chars", &(stats.num_chars - before.num_chars))
+            .field("num-dropped", &(stats.num_dropped - before.num_dropped))
+            .field("num-replaced", &(stats.num_replaced - before.num_replaced))
+            .field("num-rows", &(stats.num_rows - before.num_rows))
+            .field("num-pairs", &(stats.num_pairs - before.num_pairs))
+            .build();
+        outputs.push(Output::CaptionStats(pts.unwrap(), s));
+
+        Ok(())
+    }
+
+    fn process_caption(
+        &self,
+        state: &mut State,
+        outputs: &mut Vec<Output>,
+        element: &gst::Element,
+        data: &str,
+        pts: gst::ClockTime,
+        duration: gst::ClockTime,
+    ) -> Result<(), gst::FlowError> {
+        let settings = state.settings.clone();
+
+        if pts.unwrap() < settings.window_start || pts.unwrap() >= settings.window_stop {
+            gst_debug!(
+                CAT,
+                obj: element,
+                "Dropping caption at {} outside of the window",
+                pts
+            );
+            return Ok(());
+        }
+
+        let (pts, duration) = schedule(&settings, pts, duration);
+
+        /* Make sure the caption is erased at the end of the window */
+        let duration: gst::ClockTime = std::cmp::min(
+            duration.unwrap(),
+            settings.window_stop.saturating_sub(pts.unwrap()),
+        )
+        .into();
+
+        if settings.mode == Mode::CertPattern {
+            return self.output_cert_pattern(state, outputs, pts);
+        }
+
+        /* Short captions are displayed along with the following one */
+        let merged;
+        let (data, pts, duration) = match state.pending_short.take() {
+            Some((text, short_pts, _)) => {
+                merged = format!("{}\n{}", text, data);
+                (merged.as_str(), short_pts, pts + duration - short_pts)
+            }
+            None => (data, pts, duration),
+        };
+
+        if !settings.mode.is_roll_up() && duration.unwrap() < settings.merge_short {
+            gst_debug!(
+                CAT,
+                obj: element,
+                "Merging caption of duration {} with the next one",
+                duration
+            );
+            state.pending_short = Some((data.to_string(), pts, duration));
+            return Ok(());
+        }
+
+        if settings.reading_speed == 0 || duration.unwrap() == 0 {
+            return self.encode_text(state, outputs, element, data, pts, duration);
+        }
+
+        let n_chars = parse_markup(data, state.markup())
+            .iter()
+            .filter(|token| match token {
+                Token::Char(c) => !c.is_control(),
+                _ => false,
+            })
+            .count() as u64;
+        let reading_duration = gst::SECOND
+            .mul_div_round(n_chars, settings.reading_speed as u64)
+            .unwrap();
+
+        if reading_duration <= duration {
+            return self.encode_text(state, outputs, element, data, pts, duration);
+        }
+
+        match settings.reading_speed_action {
+            ReadingSpeedAction::Extend => {
+                gst_debug!(
+                    CAT,
+                    obj: element,
+                    "Extending duration from {} to {} for reading speed",
+                    duration,
+                    reading_duration
+                );
+                self.encode_text(state, outputs, element, data, pts, reading_duration)
+            }
+            ReadingSpeedAction::Split if !settings.mode.is_roll_up() => {
+                let n_screens =
+                    (reading_duration.unwrap() + duration.unwrap() - 1) / duration.unwrap();
+                let screens = split_screens(data, state.markup(), n_screens as usize);
+                let n_screens = screens.len() as u64;
+
+                gst_debug!(
+                    CAT,
+                    obj: element,
+                    "Splitting caption into {} screens for reading speed",
+                    n_screens
+                );
+
+                for (i, screen) in screens.iter().enumerate() {
+                    let start = pts + duration.mul_div_round(i as u64, n_screens).unwrap();
+                    let end = pts + duration.mul_div_round(i as u64 + 1, n_screens).unwrap();
+
+                    self.encode_text(state, outputs, element, screen, start, end - start)?;
+                }
+
+                Ok(())
+            }
+            _ => {
+                gst_warning!(
+                    CAT,
+                    obj: element,
+                    "Caption needs {} to be read, but is only displayed for {}",
+                    reading_duration,
+                    duration
+                );
+                self.encode_text(state, outputs, element, data, pts, duration)
+            }
+        }
+    }
+
+    /* Tells the application about the first character of a caption
+     * that can't be represented, at most once per second */
+    fn post_untranslatable(
+        state: &mut State,
+        outputs: &mut Vec<Output>,
+        element: &gst::Element,
+        data: &str,
+        pts: gst::ClockTime,
+    ) {
+        let fallback = state.settings.fallback;
+        let untranslatable = untranslatable_chars(data, fallback);
+
+        let (position, c) = match untranslatable.first() {
+            Some(untranslatable) => *untranslatable,
+            None => return,
+        };
+
+        if let Some(last_pts) = state.last_untranslatable_pts {
+            if pts < last_pts + gst::SECOND {
+                return;
+            }
+        }
+        state.last_untranslatable_pts = Some(pts);
+
+        let s = gst::Structure::builder("tttocea608-untranslatable")
+            .field("character", &c.to_string())
+            .field("position", &(position as u32))
+            .field("count", &(untranslatable.len() as u32))
+            .field(
+                "action",
+                &if fallback == Fallback::Drop {
+                    "dropped"
+                } else {
+                    "replaced"
+                },
+            )
+            .field("pts", &pts)
+            .build();
+
+        outputs.push(Output::Message(
+            gst::message::Element::builder(s).src(element).build(),
+        ));
+    }
+
+    /* Encodes a caption, paginated if needed, then tells the application
+     * about each page */
+    fn encode_text(
+        &self,
+        state: &mut State,
+        outputs: &mut Vec<Output>,
+        element: &gst::Element,
+        data: &str,
+        pts: gst::ClockTime,
+        duration: gst::ClockTime,
+    ) -> Result<(), gst::FlowError> {
+        let pages = if state.settings.paginate && state.settings.mode == Mode::PopOn {
+            split_pages(data, state.settings.max_rows as usize)
+        } else {
+            vec![data.to_string()]
+        };
+        let n_pages = pages.len() as u64;
+
+        if n_pages > 1 {
+            gst_debug!(CAT, obj: element, "Splitting caption into {} pages", n_pages);
+        }
+
+        for (i, page) in pages.into_iter().enumerate() {
+            let start = pts + duration.mul_div_round(i as u64, n_pages).unwrap();
+            let end = pts + duration.mul_div_round(i as u64 + 1, n_pages).unwrap();
+
+            self.encode_caption(state, outputs, element, &page, start, end - start)?;
+
+            let row = state.caption_row.unwrap_or(0);
+            outputs.push(Output::CaptionRendered(
+                start.unwrap(),
+                (end - start).unwrap(),
+                row as u32,
+                page,
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn encode_caption(
+        &self,
+        state: &mut State,
+        outputs: &mut Vec<Output>,
+        element: &gst::Element,
+        data: &str,
+        pts: gst::ClockTime,
+        duration: gst::ClockTime,
+    ) -> Result<(), gst::FlowError> {
+        Self::post_untranslatable(state, outputs, element, data, pts);
+        self.expire_roll_up(state, outputs, pts)?;
+
+        state.caption_row = None;
+        let copies = state.control_code_copies();
+        let cc_data_pool = state.cc_data_pool.clone();
+        let pool = cc_data_pool.as_ref();
+        /* Roll-up captions get erased max-on-screen after the last one
+         * instead */
+        let duration = match state.settings.max_on_screen {
+            0 => duration,
+            _ if state.settings.mode.is_roll_up() => duration,
+            max_on_screen => std::cmp::min(duration.unwrap(), max_on_screen).into(),
+        };
+        let mut buffers = std::mem::replace(&mut state.scratch_buffers, vec![]);
+        let origin_column = state.settings.origin_column;
+        let channel = state.settings.channel_index();
+        /* Index in buffers and PTS of the word-timed segments */
+        let mut timings = vec![];
+        /* Whether we overwrite the displayed paint-on caption */
+        let mut overwrite = false;
+
+        if state.send_roll_up {
+            erase_display_memory(&mut buffers, pool, channel, copies);
+            if let Some(rows) = state.settings.roll_up_rows() {
+                roll_up(&mut buffers, pool, rows, channel, copies);
+            }
+            state.emitted_style = preamble_buffer(
+                &mut buffers,
+                pool,
+                state.settings.base_row as i32,
+                origin_column as i32,
+                channel,
+                state.style,
+                copies,
+            );
+            state.send_roll_up = false;
+            state.roll_up_column = origin_column;
+        }
+
+        /* In pop-on mode, lines can be routed to other channels with
+         * markers */
+        let captions = if state.markup() && state.settings.mode == Mode::PopOn {
+            split_channels(data, channel)
+        } else {
+            vec![(channel, data.to_string())]
+        };
+
+        let mut encoded_text = false;
+        for (channel, data) in captions.iter() {
+            let channel = *channel;
+            let mut tokens = apply_fallback(
+                expand_two_char_fallbacks(parse_markup(data, state.markup())),
+                state.settings.fallback,
+            );
+            if state.settings.collapse_spaces {
+                tokens = collapse_spaces(tokens);
+            }
+            if state.settings.wrap && !state.settings.mode.is_roll_up() {
+                tokens = wrap_tokens(tokens, 32 - origin_column as usize);
+            }
+            let mut row = state.settings.origin_row as i32;
+
+            let mut last_row = 14;
+
+            /* Move the caption up if it doesn't fit below the origin row */
+            if !state.settings.mode.is_roll_up() {
+                let n_rows = 1 + tokens
+                    .iter()
+                    .filter(|token| **token == Token::Char('\n'))
+                    .count() as i32;
+                let n_rows = std::cmp::min(n_rows, state.settings.max_rows as i32);
+
+                if row + n_rows > 15 {
+                    let new_row = std::cmp::max(15 - n_rows, 0);
+                    gst_warning!(
+                        CAT,
+                        obj: element,
+                        "Caption with {} rows doesn't fit below row {}, starting from row {}",
+                        n_rows,
+                        row,
+                        new_row
+                    );
+                    row = new_row;
+                }
+
+                last_row = row + n_rows - 1;
+            }
+
+            if state.caption_row.is_none() {
+                state.caption_row = Some(if state.settings.mode.is_roll_up() {
+                    state.settings.base_row as i32
+                } else {
+                    row
+                });
+            }
+
+            /* Each pop-on or paint-on caption is styled independently */
+            if !state.settings.mode.is_roll_up() {
+                state.style = Style {
+                    italics: state.settings.italics,
+                    underline: state.settings.underline,
+                    color: state.settings.color,
+                };
+            }
+
+            let mut indents = vec![];
+            if !state.settings.mode.is_roll_up() {
+                let (stripped, line_indents) = strip_leading_spaces(tokens);
+                tokens = stripped;
+                indents = line_indents;
+            }
+
+            let columns = line_columns(
+                &tokens,
+                origin_column,
+                state.settings.alignment,
+                state.style,
+                &indents,
+            );
+
+            /* In paint-on mode, a caption replacing one that is still
+             * displayed overwrites it when it covers the start of its rows,
+             * and deletes what's left past its end, instead of erasing the
+             * whole display first. Tab offsets leave the columns before
+             * ours untouched, so those must not hold any of its text */
+            if state.settings.mode == Mode::PaintOn {
+                let (fps_n, fps_d) = (
+                    *state.framerate.numer() as u64,
+                    *state.framerate.denom() as u64,
+                );
+                let frame_no = std::cmp::max(
+                    (pts.mul_div_round(fps_n, fps_d).unwrap() / gst::SECOND).unwrap(),
+                    state.last_frame_no,
+                );
+
+                overwrite = state
+                    .erase_display_frame_no
+                    .map_or(false, |erase_display_frame_no| {
+                        erase_display_frame_no > frame_no
+                    })
+                    && columns.iter().enumerate().all(|(line, col)| {
+                        state
+                            .painted_rows
+                            .iter()
+                            .find(|(painted_row, _, _)| *painted_row == row + line as i32)
+                            .map_or(true, |(_, start, _)| col <= start)
+                    });
+            }
+
+            let mut painted_rows = vec![];
+            let mut line = 0;
+            let mut col = if state.settings.mode.is_roll_up() {
+                state.roll_up_column
+            } else {
+                columns[line]
+            };
+
+            /* The preamble for a new row is only sent along with its first
+             * character, so that it can carry the style of that character */
+            let mut need_preamble = !state.settings.mode.is_roll_up();
+            /* Whether a character was written on the current row */
+            let mut row_has_text = false;
+
+            match state.settings.mode {
+                Mode::PopOn => {
+                    resume_caption_loading(&mut buffers, pool, channel, copies);
+                    if state.settings.erase_non_displayed {
+                        erase_non_displayed_memory(&mut buffers, pool, channel, copies);
+                    }
+                }
+                Mode::PaintOn => {
+                    /* Decoders stay in paint-on mode until told otherwise */
+                    if state.send_direct_captioning {
+                        resume_direct_captioning(&mut buffers, pool, channel, copies);
+                        state.send_direct_captioning = false;
+                    }
+                }
+                _ => (),
+            }
+
+            let mut prev_char: u16 = if !state.settings.mode.is_roll_up() || col == origin_column {
+                0
+            } else if col >= 31 {
+                if let Some(rows) = state.settings.roll_up_rows() {
+                    roll_up(&mut buffers, pool, rows, channel, copies);
+                }
+                carriage_return(&mut buffers, pool, channel, copies);
+                state.emitted_style = preamble_buffer(
+                    &mut buffers,
+                    pool,
+                    state.settings.base_row as i32,
+                    origin_column as i32,
+                    channel,
+                    state.style,
+                    copies,
+                );
+                col = origin_column;
+                0
+            } else {
+                // In roll-up mode, the typical input will not have surrounding
+                // whitespaces. This could be improved by detecting whether the
+                // last character that was output was some sort of whitespace,
+                // and we could avoid the white space before punctuation, but
+                // this is complicated by the fact that in some languages,
+                // some punctuation must be preceded by a white space, eg in
+                // French that is the case for '?' and '!', but not for '.' or
+                // ';'. Let's not go down that rabbit hole.
+                col += 1;
+                *SPACE
+            };
+
+            let mut tokens = tokens.into_iter();
+            for token in tokens.by_ref() {
+                let mut c = match token {
+                    Token::Char(c) => c,
+                    Token::Underline(underline) => {
+                        state.style.underline = underline;
+                        continue;
+                    }
+                    Token::Italics(italics) => {
+                        state.style.italics = italics;
+                        continue;
+                    }
+                    Token::Color(color) => {
+                        state.style.color = color;
+                        continue;
+                    }
+                    Token::Timing(ms) => {
+                        if state.settings.mode == Mode::PaintOn {
+                            if prev_char != 0 {
+                                buffers.push(buffer_from_cc_data(pool, prev_char));
+                                prev_char = 0;
+                            }
+
+                            timings.push((buffers.len(), pts + ms * gst::MSECOND));
+                        } else {
+                            gst_debug!(
+                                CAT,
+                                obj: element,
+                                "Ignoring word timing outside of paint-on mode"
+                            );
+                        }
+                        continue;
+                    }
+                };
+
+                if c == '\n' && !state.settings.mode.is_roll_up() {
+                    if prev_char != 0 {
+                        buffers.push(buffer_from_cc_data(pool, prev_char));
+                        prev_char = 0;
+                    }
+
+                    if state.settings.mode == Mode::PaintOn && !need_preamble {
+                        if overwrite {
+                            delete_overwritten(
+                                &mut buffers,
+                                pool,
+                                &state.painted_rows,
+                                row,
+                                col,
+                                channel,
+                                copies,
+                            );
+                        }
+                        painted_rows.push((row, columns[line], col));
+                    }
+
+                    row += 1;
+
+                    if row > last_row {
+                        gst_warning!(
+                            CAT,
+                            obj: element,
+                            "Dropping text after the last row"
+                        );
+                        break;
+                    }
+
+                    need_preamble = true;
+                    row_has_text = false;
+
+                    line += 1;
+                    col = columns[line];
+                    continue;
+                } else if c == '\n' {
+                    c = ' ';
+                } else if c == '\r' {
+                    continue;
+                }
+
+                /* A full roll-up row is only scrolled up along with the
+                 * next character, so that a caption ending the row doesn't
+                 * scroll the window before the next one is due. That one
+                 * does it at its PTS, when starting on a full row */
+                if col > 31 && state.settings.mode.is_roll_up() {
+                    if prev_char != 0 {
+                        buffers.push(buffer_from_cc_data(pool, prev_char));
+                        prev_char = 0;
+                    }
+
+                    if let Some(rows) = state.settings.roll_up_rows() {
+                        roll_up(&mut buffers, pool, rows, channel, copies);
+                    }
+
+                    carriage_return(&mut buffers, pool, channel, copies);
+                    state.emitted_style = preamble_buffer(
+                        &mut buffers,
+                        pool,
+                        state.settings.base_row as i32,
+                        origin_column as i32,
+                        channel,
+                        state.style,
+                        copies,
+                    );
+                    col = origin_column;
+                    row_has_text = false;
+                }
+
+                /* Rows are 32 columns wide, numbered from 0 to 31 */
+                if col > 31 && !state.settings.mode.is_roll_up() {
+                    gst_warning!(
+                        CAT,
+                        obj: element,
+                        "Dropping character after 32nd column: {}",
+                        c
+                    );
+                    state.stats.num_dropped += 1;
+                    continue;
+                }
+
+                if need_preamble {
+                    state.emitted_style = preamble_buffer(
+                        &mut buffers,
+                        pool,
+                        row,
+                        col as i32,
+                        channel,
+                        state.style,
+                        copies,
+                    );
+                    need_preamble = false;
+                } else if state.emitted_style != state.style {
+                    if prev_char != 0 {
+                        buffers.push(buffer_from_cc_data(pool, prev_char));
+                        prev_char = 0;
+                    }
+
+                    let (emitted_style, n_cols) = midrow_change_buffer(
+                        &mut buffers,
+                        pool,
+                        state.emitted_style,
+                        state.style,
+                        channel,
+                        copies,
+                    );
+                    state.emitted_style = emitted_style;
+                    col += n_cols;
+
+                    /* The mid-row code is displayed as a space already */
+                    if c == ' ' {
+                        continue;
+                    }
+
+                    if col > 31 && !state.settings.mode.is_roll_up() {
+                        gst_warning!(
+                            CAT,
+                            obj: element,
+                            "Dropping character after 32nd column: {}",
+                            c
+                        );
+                        state.stats.num_dropped += 1;
+                        continue;
+                    }
+                }
+
+                let mut encoded = [0; 5];
+                c.encode_utf8(&mut encoded);
+                let mut cc_data = eia608_from_utf8_1(&encoded, channel);
+
+                /* Base letter of the character, if CEA-608 has it */
+                let base = strip_diacritics(c)
+                    .map(|base| encode_char(base, channel))
+                    .filter(|base| is_basicna(*base));
+
+                if cc_data == 0 {
+                    if let Some(base) = base {
+                        gst_debug!(CAT, obj: element, "Approximating UTF8: {}", c);
+                        cc_data = base;
+                    } else {
+                        gst_warning!(CAT, obj: element, "Not translating UTF8: {}", c);
+                        cc_data = encode_char(state.settings.replacement_char, channel);
+                        state.stats.num_replaced += 1;
+                    }
+                }
+
+                /* Decoders without support for extended characters
+                 * only display the character they overwrite */
+                let substitute = base.unwrap_or(*SPACE);
+
+                if is_basicna(prev_char) {
+                    if is_basicna(cc_data) {
+                        bna_buffer(&mut buffers, pool, prev_char, cc_data);
+                    } else if is_westeu(cc_data) {
+                        // extended characters overwrite the previous character,
+                        // so insert a dummy char then write the extended char
+                        bna_buffer(&mut buffers, pool, prev_char, substitute);
+                        buffers.push(buffer_from_cc_data(pool, cc_data));
+                    } else {
+                        buffers.push(buffer_from_cc_data(pool, prev_char));
+                        buffers.push(buffer_from_cc_data(pool, cc_data));
+                    }
+                    prev_char = 0;
+                } else if is_westeu(cc_data) {
+                    // extended characters overwrite the previous character,
+                    // so insert a dummy char then write the extended char
+                    buffers.push(buffer_from_cc_data(pool, substitute));
+                    buffers.push(buffer_from_cc_data(pool, cc_data));
+                } else if is_basicna(cc_data) {
+                    prev_char = cc_data;
+                } else {
+                    buffers.push(buffer_from_cc_data(pool, cc_data));
+                }
+
+                if is_specialna(cc_data) {
+                    resume_caption_loading(&mut buffers, pool, channel, copies);
+                }
+
+                state.stats.num_chars += 1;
+                encoded_text = true;
+                if !row_has_text {
+                    state.stats.num_rows += 1;
+                    row_has_text = true;
+                }
+
+                col += 1;
+            }
+
+            /* Left over when text was dropped after the last row */
+            state.stats.num_dropped += tokens
+                .filter(|token| match token {
+                    Token::Char(c) => *c != '\n' && *c != '\r',
+                    _ => false,
+                })
+                .count() as u64;
+
+            if prev_char != 0 {
+                buffers.push(buffer_from_cc_data(pool, prev_char));
+            }
+
+            if state.settings.mode == Mode::PaintOn && !need_preamble && row <= last_row {
+                if overwrite {
+                    delete_overwritten(
+                        &mut buffers,
+                        pool,
+                        &state.painted_rows,
+                        row,
+                        col,
+                        channel,
+                        copies,
+                    );
+                }
+                painted_rows.push((row, columns[line], col));
+            }
+
+            /* Clear the rows of the caption we overwrite that we didn't
+             * paint on */
+            if overwrite {
+                let cleared_rows = state
+                    .painted_rows
+                    .iter()
+                    .filter(|(painted_row, _, _)| {
+                        painted_rows.iter().all(|(row, _, _)| row != painted_row)
+                    })
+                    .cloned()
+                    .collect::<Vec<_>>();
+
+                for (cleared_row, start, _) in cleared_rows {
+                    state.emitted_style = preamble_buffer(
+                        &mut buffers,
+                        pool,
+                        cleared_row,
+                        (start / 4 * 4) as i32,
+                        channel,
+                        state.style,
+                        copies,
+                    );
+                    delete_to_end_of_row(&mut buffers, pool, channel, copies);
+                }
+            }
+
+            if state.settings.mode == Mode::PaintOn {
+                state.painted_rows = painted_rows;
+            }
+
+            match state.settings.mode {
+                Mode::PopOn => end_of_caption(&mut buffers, pool, channel, copies),
+                Mode::PaintOn => (),
+                _ => state.roll_up_column = col,
+            }
+        }
+
+        /* Flipping an empty caption would erase the displayed one, keep
+         * downstream going with a gap over ours instead */
+        if state.settings.mode == Mode::PopOn && !encoded_text {
+            buffers.clear();
+            state.scratch_buffers = buffers;
+
+            gst_debug!(
+                CAT,
+                obj: element,
+                "Dropped every character of the caption at {}, pushing a gap",
+                pts
+            );
+
+            return self.handle_gap(state, outputs, pts + duration);
+        }
+
+        let mut bufferlist = gst::BufferList::new_sized(LATENCY_BUFFERS as usize);
+
+        let (fps_n, fps_d) = (
+            *state.framerate.numer() as u64,
+            *state.framerate.denom() as u64,
+        );
+
+        /* Calculate the frame for which we want the first of our
+         * (doubled) end_of_caption control codes to be output
+         */
+        let mut frame_no = (pts.mul_div_round(fps_n, fps_d).unwrap() / gst::SECOND).unwrap();
+
+        if state.settings.mode == Mode::PopOn {
+            /* Count the byte pairs of an erase_display_memory in case we
+             * need to splice it in, like LATENCY_BUFFERS does */
+            let n_buffers = buffers.len() as u64 + copies;
+            let latency_changed = state.settings.dynamic_latency && n_buffers > state.max_buffers;
+            state.max_buffers = std::cmp::max(state.max_buffers, n_buffers);
+
+            if !state.settings.dynamic_latency && n_buffers > state.latency() {
+                gst_warning!(
+                    CAT,
+                    obj: element,
+                    "Caption needs {} byte pairs, more than the latency of {} frames",
+                    n_buffers,
+                    state.latency()
+                );
+            }
+
+            /* Add 2: One for our second end_of_caption control
+             * code, another to calculate its duration, then delay
+             * the flip by the requested number of frames */
+            frame_no += copies + state.settings.flip_offset as u64;
+
+            /* A caption overlapping the previous one replaces it when it
+             * flips, which truncates the previous one: its erasure is
+             * dropped instead of being spliced in */
+            if state.erase_display_frame_no > Some(frame_no) {
+                gst_debug!(
+                    CAT,
+                    obj: element,
+                    "Truncating the previous caption at {}",
+                    pts
+                );
+                state.erase_display_frame_no = None;
+            }
+
+            /* Each byte pair needs a frame of its own after the ones we
+             * already output, outputting multiple buffers with a 0
+             * duration would break strict line-21 encoding, so a caption
+             * too close to the previous one flips late instead */
+            let n_frames = buffers.len() as u64
+                + if state.erase_display_frame_no.is_some() {
+                    copies * state.displayed_channels.len() as u64
+                } else {
+                    0
+                };
+            if frame_no < state.last_frame_no + n_frames {
+                gst_debug!(
+                    CAT,
+                    obj: element,
+                    "Delaying caption at {} by {} frames to fit its byte pairs",
+                    pts,
+                    state.last_frame_no + n_frames - frame_no
+                );
+                frame_no = state.last_frame_no + n_frames;
+            }
+
+            /* Store that frame number, so we can make sure not to output
+             * overlapped timestamps */
+            let mut min_frame_no = state.last_frame_no;
+            state.last_frame_no = frame_no;
+
+            let mut erase_display_frame_no = {
+                if state.erase_display_frame_no < Some(frame_no) {
+                    state.erase_display_frame_no
+                } else {
+                    None
+                }
+            };
+
+            state.erase_display_frame_no = Some(
+                ((pts + duration).mul_div_round(fps_n, fps_d).unwrap() / gst::SECOND).unwrap()
+                    + copies,
+            );
+            let erase_channels = std::mem::replace(
+                &mut state.displayed_channels,
+                captions.iter().map(|(channel, _)| *channel).collect(),
+            );
+
+            for mut buffer in buffers.drain(..).rev() {
+                /* Insert display erasure at the correct moment */
+                if erase_display_frame_no == Some(frame_no) {
+                    for channel in erase_channels.iter().rev() {
+                        for _ in 0..copies {
+                            let (pts, duration) =
+                                decrement_pts(min_frame_no, &mut frame_no, fps_n, fps_d);
+                            erase_display_memory_with_pts(
+                                bufferlist.make_mut(),
+                                pool,
+                                pts,
+                                duration,
+                                *channel,
+                            );
+                        }
+                    }
+
+                    erase_display_frame_no = None;
+                }
+
+                let (pts, duration) = decrement_pts(min_frame_no, &mut frame_no, fps_n, fps_d);
+
+                set_timestamps(&mut buffer, pts, duration);
+                bufferlist.make_mut().insert(0, buffer);
+            }
+            state.scratch_buffers = buffers;
+
+            if let Some(erase_display_frame_no) = erase_display_frame_no {
+                let (erase_bufferlist, start_frame_no) = Self::erase_display_list(
+                    state,
+                    min_frame_no,
+                    erase_display_frame_no,
+                    &erase_channels,
+                );
+                outputs.push(self.prepare_list(
+                    state,
+                    erase_bufferlist,
+                    min_frame_no,
+                    start_frame_no,
+                )?);
+                min_frame_no = erase_display_frame_no;
+            }
+            outputs.push(self.prepare_list(state, bufferlist, min_frame_no, frame_no)?);
+
+            if latency_changed {
+                gst_debug!(CAT, obj: element, "Latency increased to {} frames", n_buffers);
+                outputs.push(Output::Message(
+                    gst::message::Latency::builder().src(element).build(),
+                ));
+            }
+
+            Ok(())
+        } else if state.settings.mode == Mode::PaintOn {
+            /* Text gets painted on the display as it arrives, so we output
+             * forward from our PTS, and erase the display once the caption
+             * has expired, like in pop-on mode */
+            frame_no = std::cmp::max(frame_no, state.last_frame_no);
+            let end_frame_no =
+                ((pts + duration).mul_div_round(fps_n, fps_d).unwrap() / gst::SECOND).unwrap();
+            let min_frame_no = state.last_frame_no;
+            let mut erase_buffers = vec![];
+
+            /* If the previous caption is still displayed at our PTS, erase
+             * it right before painting ours, otherwise at its expected time */
+            let erase_display_frame_no = match state.erase_display_frame_no.take() {
+                Some(erase_display_frame_no) if erase_display_frame_no > frame_no => {
+                    if !overwrite {
+                        erase_display_memory(&mut erase_buffers, pool, channel, copies);
+                    }
+                    None
+                }
+                erase_display_frame_no => erase_display_frame_no,
+            };
+
+            if let Some(erase_display_frame_no) = erase_display_frame_no {
+                let (erase_bufferlist, start_frame_no) = Self::erase_display_list(
+                    state,
+                    min_frame_no,
+                    erase_display_frame_no,
+                    &[channel],
+                );
+                outputs.push(self.prepare_list(
+                    state,
+                    erase_bufferlist,
+                    min_frame_no,
+                    start_frame_no,
+                )?);
+            }
+
+            let mut last_frame_no = state.last_frame_no;
+            frame_no = std::cmp::max(frame_no, last_frame_no);
+            let mut start_frame_no = frame_no;
+
+            let n_erase_buffers = erase_buffers.len();
+            let mut timings = timings
+                .into_iter()
+                .map(|(idx, pts): (usize, gst::ClockTime)| {
+                    (
+                        idx + n_erase_buffers,
+                        (pts.mul_div_round(fps_n, fps_d).unwrap() / gst::SECOND).unwrap(),
+                    )
+                })
+                .peekable();
+            let mut bufferlists = vec![];
+
+            for (idx, mut buffer) in erase_buffers.drain(..).chain(buffers.drain(..)).enumerate() {
+                /* Word-timed segments are held back until their time, and
+                 * pushed as separate lists so that gaps cover the pauses */
+                while let Some(&(timing_idx, timing_frame_no)) = timings.peek() {
+                    if timing_idx != idx {
+                        break;
+                    }
+                    timings.next();
+
+                    if timing_frame_no > frame_no {
+                        if frame_no > start_frame_no {
+                            let bufferlist =
+                                std::mem::replace(&mut bufferlist, gst::BufferList::new());
+                            bufferlists.push((bufferlist, last_frame_no, start_frame_no));
+                            last_frame_no = frame_no;
+                        }
+                        frame_no = timing_frame_no;
+                        start_frame_no = frame_no;
+                    }
+                }
+
+                let (pts, duration) = increment_pts(&mut frame_no, std::u64::MAX, fps_n, fps_d);
+                set_timestamps(&mut buffer, pts, duration);
+                bufferlist.make_mut().insert(-1, buffer);
+            }
+            bufferlists.push((bufferlist, last_frame_no, start_frame_no));
+
+            state.scratch_buffers = buffers;
+            state.last_frame_no = frame_no;
+            /* do_erase_display outputs its control codes on the frames
+             * preceding that one */
+            state.erase_display_frame_no = Some(std::cmp::max(end_frame_no, frame_no) + copies);
+            state.displayed_channels = vec![channel];
+
+            for (bufferlist, last_frame_no, start_frame_no) in bufferlists {
+                outputs.push(self.prepare_list(
+                    state,
+                    bufferlist,
+                    last_frame_no,
+                    start_frame_no,
+                )?);
+            }
+
+            Ok(())
+        } else {
+            // Make sure our first buffer doesn't overlap with the last
+            // gap / buffer we pushed
+            frame_no = std::cmp::max(frame_no, state.last_frame_no);
+            let start_frame_no = frame_no;
+            let max_frame_no =
+                ((pts + duration).mul_div_round(fps_n, fps_d).unwrap() / gst::SECOND).unwrap();
+            for mut buffer in buffers.drain(..) {
+                let (pts, duration) = increment_pts(&mut frame_no, max_frame_no, fps_n, fps_d);
+                set_timestamps(&mut buffer, pts, duration);
+                bufferlist.make_mut().insert(-1, buffer);
+            }
+            state.scratch_buffers = buffers;
+            let last_frame_no = state.last_frame_no;
+            state.last_frame_no = max_frame_no;
+
+            /* Output along with the next buffer, gap event or EOS, the
+             * next caption cancels it if it comes before */
+            if state.settings.max_on_screen != 0 {
+                let expiry: gst::ClockTime = (pts.unwrap() + state.settings.max_on_screen).into();
+                let expiry_frame_no =
+                    (expiry.mul_div_round(fps_n, fps_d).unwrap() / gst::SECOND).unwrap();
+                state.erase_display_frame_no =
+                    Some(std::cmp::max(expiry_frame_no, max_frame_no) + copies);
+                state.displayed_channels = vec![channel];
+            }
+
+            outputs.push(self.prepare_list(state, bufferlist, last_frame_no, start_frame_no)?);
+            outputs.extend(state.gap_event(frame_no, max_frame_no).map(Output::Gap));
+
+            Ok(())
+        }
+    }
+
+    fn src_query(&self, pad: &gst::Pad, element: &gst::Element, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryView;
+
+        gst_log!(CAT, obj: pad, "Handling query {:?}", query);
+
+        match query.view_mut() {
+            QueryView::Latency(ref mut q) => {
+                let mut peer_query = gst::query::Latency::new();
+
+                let ret = self.sinkpad.peer_query(&mut peer_query);
+
+                if ret {
+                    let state = self.state.lock().unwrap();
+                    let (live, mut min, mut max) = peer_query.get_result();
+                    let (fps_n, fps_d) = (
+                        *state.framerate.numer() as u64,
+                        *state.framerate.denom() as u64,
+                    );
+
+                    if state.settings.mode == Mode::PopOn {
+                        let our_latency: gst::ClockTime = (state.latency() * gst::SECOND)
+                            .mul_div_round(fps_d, fps_n)
+                            .unwrap();
+
+                        min += our_latency;
+                        max += our_latency;
+                    } else {
+                        /* We introduce at most a one-frame latency due to rounding */
+                        let our_latency: gst::ClockTime =
+                            gst::SECOND.mul_div_round(fps_d, fps_n).unwrap();
+
+                        min += our_latency;
+                        max += our_latency;
+                    }
+
+                    q.set(live, min, max);
+                }
+                ret
+            }
+            _ => pad.query_default(Some(element), query),
+        }
+    }
+
+    /* Framerate downstream itself asks for in its structure matching ours,
+     * the first one if it lists several, as opposed to one picked from
+     * a range or from our template */
+    fn preferred_framerate(&self, s: &gst::StructureRef) -> Option<gst::Fraction> {
+        let peer_caps = self.srcpad.peer_query_caps(None)?;
+        let format = s.get::<&str>("format").ok().flatten();
+
+        peer_caps
+            .iter()
+            .filter(|peer_s| {
+                peer_s.get_name() == s.get_name()
+                    && match peer_s.get::<&str>("format") {
+                        Ok(Some(peer_format)) => Some(peer_format) == format,
+                        _ => true,
+                    }
+            })
+            .find_map(|peer_s| {
+                if let Ok(Some(framerate)) = peer_s.get::<gst::Fraction>("framerate") {
+                    Some(framerate)
+                } else if let Ok(Some(framerates)) = peer_s.get::<gst::List>("framerate") {
+                    framerates
+                        .as_slice()
+                        .iter()
+                        .find_map(|framerate| framerate.get::<gst::Fraction>().ok().flatten())
+                } else {
+                    None
+                }
+            })
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, element: &gst::Element, event: gst::Event) -> bool {
+        gst_log!(CAT, obj: pad, "Handling event {:?}", event);
+
+        use gst::EventView;
+
+        match event.view() {
+            EventView::Segment(e) => {
+                let segment = match e.get_segment().clone().downcast::<gst::ClockTime>() {
+                    Err(segment) => {
+                        gst_element_error!(
+                            element,
+                            gst::StreamError::Format,
+                            [
+                                "Only Time segments supported, got {:?}",
+                                segment.get_format()
+                            ]
+                        );
+                        return false;
+                    }
+                    Ok(segment) => segment,
+                };
+
+                gst_debug!(CAT, obj: pad, "Got new segment {:?}", segment);
+                let mut state = self.state.lock().unwrap();
+                let mut outputs = vec![];
+                let (fps_n, fps_d) = (
+                    *state.framerate.numer() as u64,
+                    *state.framerate.denom() as u64,
+                );
+
+                /* Without a flush, running time can go back when looping,
+                 * rebase our frame numbers on the start of the new segment */
+                let start = segment.to_running_time(segment.get_start());
+                if start != gst::CLOCK_TIME_NONE {
+                    let start_frame_no =
+                        (start.mul_div_round(fps_n, fps_d).unwrap() / gst::SECOND).unwrap();
+
+                    if start_frame_no < state.last_frame_no {
+                        gst_debug!(
+                            CAT,
+                            obj: pad,
+                            "Running time went back, rebasing on frame {}",
+                            start_frame_no
+                        );
+
+                        let _ = self.drain_erase_display(&mut state, &mut outputs);
+
+                        state.last_frame_no = start_frame_no;
+                    }
+                }
+
+                state.segment = segment;
+                state.next_pts = gst::CLOCK_TIME_NONE;
+                drop(state);
+
+                let _ = self.push_outputs(element, outputs);
+
+                /* We timestamp our output with the running time */
+                let new_event =
+                    gst::event::Segment::builder(&gst::FormattedSegment::<gst::ClockTime>::new())
+                        .seqnum(event.get_seqnum())
+                        .build();
+
+                if let Some(preview) = self.preview_srcpad.lock().unwrap().clone() {
+                    preview.push_event(new_event.clone());
+                }
+
+                self.srcpad.push_event(new_event)
+            }
+            EventView::Caps(e) => {
+                let caps_markup = {
+                    let s = e.get_caps().get_structure(0).unwrap();
+
+                    if s.get_name() == "text/x-pango-markup" {
+                        Some(true)
+                    } else {
+                        match s.get::<&str>("format") {
+                            Ok(Some("pango-markup")) => Some(true),
+                            Ok(Some(_)) => Some(false),
+                            _ => None,
+                        }
+                    }
+                };
+                gst_debug!(CAT, obj: pad, "Input is markup: {:?}", caps_markup);
+
+                let debug_output = {
+                    let mut state = self.state.lock().unwrap();
+                    state.caps_markup = caps_markup;
+                    state.settings.debug_output
+                };
+                let filter = if debug_output {
+                    gst::Caps::new_simple("text/x-raw", &[])
+                } else {
+                    let mut filter = gst::Caps::new_simple("closedcaption/x-cea-608", &[]);
+                    filter
+                        .get_mut()
+                        .unwrap()
+                        .append_structure(gst::Structure::new_empty("closedcaption/x-cea-708"));
+                    filter
+                };
+
+                let mut downstream_caps = match self.srcpad.get_allowed_caps() {
+                    None => self.srcpad.get_pad_template_caps().unwrap(),
+                    Some(caps) => caps,
+                }
+                .intersect_with_mode(&filter, gst::CapsIntersectMode::First);
+
+                if downstream_caps.is_empty() {
+                    gst_error!(CAT, obj: pad, "Empty downstream caps");
+                    return false;
+                }
+
+                let caps = downstream_caps.make_mut();
+                let s = caps.get_mut_structure(0).unwrap();
+
+                let preferred_framerate = self
+                    .preferred_framerate(s)
+                    .unwrap_or_else(|| gst::Fraction::new(DEFAULT_FPS_N, DEFAULT_FPS_D));
+                s.fixate_field_nearest_fraction("framerate", preferred_framerate);
+                s.fixate();
+
+                let mut state = self.state.lock().unwrap();
+                let framerate = s.get_some::<gst::Fraction>("framerate").unwrap();
+                if framerate != state.framerate {
+                    gst_debug!(
+                        CAT,
+                        obj: pad,
+                        "Framerate changed from {} to {}",
+                        state.framerate,
+                        framerate
+                    );
+                    state.rescale_frame_numbers(framerate);
+                }
+                state.framerate = framerate;
+                state.output_format = if debug_output {
+                    OutputFormat::Debug
+                } else if s.get_name() == "closedcaption/x-cea-708" {
+                    match s.get::<&str>("format") {
+                        Ok(Some("cdp")) => OutputFormat::Cdp,
+                        _ => OutputFormat::CcData,
+                    }
+                } else if let Ok(Some("s334-1a")) = s.get::<&str>("format") {
+                    OutputFormat::S3341a
+                } else {
+                    /* Fields are numbered from 0 in caps */
+                    s.set("field", &(state.settings.channel_index() >> 1));
+                    OutputFormat::Raw
+                };
+
+                gst_debug!(CAT, obj: pad, "Pushing caps {}", caps);
+
+                let new_event = gst::event::Caps::new(&downstream_caps);
+
+                drop(state);
+
+                if let Some(preview) = self.preview_srcpad.lock().unwrap().clone() {
+                    let preview_caps = gst::Caps::builder("text/x-raw")
+                        .field("format", &"utf8")
+                        .build();
+                    preview.push_event(gst::event::Caps::new(&preview_caps));
+                }
+
+                self.srcpad.push_event(new_event)
+            }
+            EventView::Gap(e) => {
+                let mut state = self.state.lock().unwrap();
+                let mut outputs = vec![];
+
+                let (timestamp, duration) = e.get();
+                let end = state.segment.to_running_time(timestamp + duration);
+                if end == gst::CLOCK_TIME_NONE {
+                    gst_debug!(CAT, obj: pad, "Ignoring gap outside of the segment");
+                    return true;
+                }
+
+                let _ = self.handle_gap(&mut state, &mut outputs, end);
+                drop(state);
+
+                /* Ignore return value, we may be flushing here and can't
+                 * communicate that through a boolean
+                 */
+                let _ = self.push_outputs(element, outputs);
+
+                true
+            }
+            EventView::Eos(_) => {
+                let mut state = self.state.lock().unwrap();
+                let mut outputs = vec![];
+
+                let reorder_queue = std::mem::replace(&mut state.reorder_queue, vec![]);
+                for (text, pts, duration) in reorder_queue {
+                    let _ =
+                        self.handle_text(&mut state, &mut outputs, element, &text, pts, duration);
+                }
+
+                if let Some((text, pts)) = state.pending_untimed.take() {
+                    let duration: gst::ClockTime = state.settings.default_duration.into();
+                    let _ = self.handle_caption(
+                        &mut state,
+                        &mut outputs,
+                        element,
+                        &text,
+                        pts,
+                        duration,
+                    );
+                }
+
+                if let Some((text, pts, duration)) = state.pending_short.take() {
+                    let _ =
+                        self.encode_text(&mut state, &mut outputs, element, &text, pts, duration);
+                }
+
+                let _ = if state.settings.clear_on_eos && state.erase_display_frame_no.is_none() {
+                    self.force_erase_display(&mut state, &mut outputs)
+                } else {
+                    self.drain_erase_display(&mut state, &mut outputs)
+                };
+                drop(state);
+
+                /* Ignore return value, we may be flushing here and can't
+                 * communicate that through a boolean
+                 */
+                let _ = self.push_outputs(element, outputs);
+
+                pad.event_default(Some(element), event)
+            }
+            EventView::FlushStop(_) => {
+                let mut state = self.state.lock().unwrap();
+
+                state.pending_short = None;
+                state.pending_untimed = None;
+                state.reorder_queue.clear();
+                /* Start over with a whole XDS packet */
+                state.xds_queue.clear();
+                state.last_pts = gst::CLOCK_TIME_NONE;
+                state.next_pts = gst::CLOCK_TIME_NONE;
+                state.meta_queue.clear();
+                state.painted_rows.clear();
+
+                /* Timestamps restart after a seek, the framerate stays the same */
+                state.erase_display_frame_no = None;
+                state.displayed_channels = vec![];
+                state.last_frame_no = 0;
+
+                if state.settings.mode.is_roll_up() {
+                    state.send_roll_up = true;
+                } else if state.settings.mode == Mode::PaintOn {
+                    state.send_direct_captioning = true;
+                }
+
+                state.preview_frame = CaptionFrame::default();
+
+                pad.event_default(Some(element), event)
+            }
+            _ => pad.event_default(Some(element), event),
+        }
+    }
+
+    /* Keeps the text pads from forwarding to the video pads and the other
+     * way around */
+    fn iterate_internal_links(&self, pad: &gst::Pad) -> gst::Iterator<gst::Pad> {
+        if let Some((video_sinkpad, video_srcpad)) = self.video_pads.lock().unwrap().clone() {
+            if pad == &video_sinkpad {
+                return gst::Iterator::from_vec(vec![video_srcpad]);
+            } else if pad == &video_srcpad {
+                return gst::Iterator::from_vec(vec![video_sinkpad]);
+            }
+        }
+
+        if pad == &self.sinkpad {
+            let mut pads = vec![self.srcpad.clone()];
+            pads.extend(self.preview_srcpad.lock().unwrap().clone());
+            gst::Iterator::from_vec(pads)
+        } else {
+            gst::Iterator::from_vec(vec![self.sinkpad.clone()])
+        }
+    }
+
+    /* When video_sink is requested, video buffers are passed through to
+     * video_src with the byte pairs output for their frame attached as
+     * a GstVideoCaptionMeta: CEA-608 raw for field 1, and S334-1A for
+     * field 2 as raw can't tell the fields apart. The byte pairs are
+     * still output on the src pad, and text must be received ahead of
+     * the video it applies to, pairs for frames we already passed being
+     * dropped. */
+    fn video_sink_chain(
+        &self,
+        pad: &gst::Pad,
+        _element: &gst::Element,
+        mut buffer: gst::Buffer,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let video_srcpad = match self.video_pads.lock().unwrap().clone() {
+            Some((_, video_srcpad)) => video_srcpad,
+            None => return Err(gst::FlowError::Flushing),
+        };
+
+        let mut state = self.state.lock().unwrap();
+
+        let pts = buffer.get_pts();
+        let start = state.video_segment.to_running_time(pts);
+        let end = match buffer.get_duration() {
+            gst::CLOCK_TIME_NONE => start + gst::ClockTime::from_nseconds(1),
+            duration => state.video_segment.to_running_time(pts + duration),
+        };
+
+        let mut data = vec![];
+        if start != gst::CLOCK_TIME_NONE {
+            let s334_1a = state.settings.channel_index() >> 1 == 1;
+
+            while let Some((cc_pts, cc_data)) = state.meta_queue.front().cloned() {
+                if end != gst::CLOCK_TIME_NONE && cc_pts >= end {
+                    break;
+                }
+
+                state.meta_queue.pop_front();
+
+                if cc_pts < start {
+                    gst_debug!(CAT, obj: pad, "Dropping late byte pair at {}", cc_pts);
+                    continue;
+                }
+
+                if s334_1a {
+                    /* Field 2, line offset 0 */
+                    data.push(0x00);
+                }
+                data.extend_from_slice(&cc_data);
+            }
+        }
+
+        let caption_type = if state.settings.channel_index() >> 1 == 1 {
+            gst_video::VideoCaptionType::Cea608S3341a
+        } else {
+            gst_video::VideoCaptionType::Cea608Raw
+        };
+
+        drop(state);
+
+        if !data.is_empty() {
+            gst_log!(CAT, obj: pad, "Attaching {} bytes of caption data", data.len());
+            gst_video::VideoCaptionMeta::add(buffer.make_mut(), caption_type, &data);
+        }
+
+        video_srcpad.push(buffer)
+    }
+
+    fn video_sink_event(&self, pad: &gst::Pad, element: &gst::Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(CAT, obj: pad, "Handling event {:?}", event);
+
+        match event.view() {
+            EventView::Segment(e) => {
+                if let Ok(segment) = e.get_segment().clone().downcast::<gst::ClockTime>() {
+                    self.state.lock().unwrap().video_segment = segment;
+                }
+            }
+            EventView::FlushStop(_) => {
+                self.state.lock().unwrap().video_segment = gst::FormattedSegment::new();
+            }
+            _ => (),
+        }
+
+        pad.event_default(Some(element), event)
+    }
+
+    fn request_video_pads(&self, element: &gst::Element) -> Option<gst::Pad> {
+        let mut video_pads = self.video_pads.lock().unwrap();
+
+        if video_pads.is_some() {
+            gst_error!(CAT, obj: element, "Video pad already requested");
+            return None;
+        }
+
+        let templ = element.get_pad_template("video_sink").unwrap();
+        let video_sinkpad = gst::Pad::builder_with_template(&templ, Some("video_sink"))
+            .chain_function(|pad, parent, buffer| {
+                TtToCea608::catch_panic_pad_function(
+                    parent,
+                    || Err(gst::FlowError::Error),
+                    |this, element| this.video_sink_chain(pad, element, buffer),
+                )
+            })
+            .event_function(|pad, parent, event| {
+                TtToCea608::catch_panic_pad_function(
+                    parent,
+                    || false,
+                    |this, element| this.video_sink_event(pad, element, event),
+                )
+            })
+            .iterate_internal_links_function(|pad, parent| {
+                TtToCea608::catch_panic_pad_function(
+                    parent,
+                    || gst::Iterator::from_vec(vec![]),
+                    |this, _element| this.iterate_internal_links(pad),
+                )
+            })
+            .flags(gst::PadFlags::PROXY_CAPS | gst::PadFlags::PROXY_ALLOCATION)
+            .build();
+
+        let templ = element.get_pad_template("video_src").unwrap();
+        let video_srcpad = gst::Pad::builder_with_template(&templ, Some("video_src"))
+            .iterate_internal_links_function(|pad, parent| {
+                TtToCea608::catch_panic_pad_function(
+                    parent,
+                    || gst::Iterator::from_vec(vec![]),
+                    |this, _element| this.iterate_internal_links(pad),
+                )
+            })
+            .flags(gst::PadFlags::PROXY_CAPS | gst::PadFlags::PROXY_ALLOCATION)
+            .build();
+
+        video_sinkpad.set_active(true).unwrap();
+        video_srcpad.set_active(true).unwrap();
+        *video_pads = Some((video_sinkpad.clone(), video_srcpad.clone()));
+        drop(video_pads);
+
+        element.add_pad(&video_sinkpad).unwrap();
+        element.add_pad(&video_srcpad).unwrap();
+
+        Some(video_sinkpad)
+    }
+}
+
+impl ObjectSubclass for TtToCea608 {
+    const NAME: &'static str = "TtToCea608";
+    type ParentType = gst::Element;
+    type Instance = gst::subclass::ElementInstanceStruct<Self>;
+    type Class = subclass::simple::ClassStruct<Self>;
+
+    glib_object_subclass!();
 
     fn with_class(klass: &subclass::simple::ClassStruct<Self>) -> Self {
         let templ = klass.get_pad_template("sink").unwrap();
@@ -808,6 +4902,13 @@ impl ObjectSubclass for TtToCea608 {
                     |this, element| this.sink_event(pad, element, event),
                 )
             })
+            .iterate_internal_links_function(|pad, parent| {
+                TtToCea608::catch_panic_pad_function(
+                    parent,
+                    || gst::Iterator::from_vec(vec![]),
+                    |this, _element| this.iterate_internal_links(pad),
+                )
+            })
             .flags(gst::PadFlags::FIXED_CAPS)
             .build();
 
@@ -820,12 +4921,21 @@ impl ObjectSubclass for TtToCea608 {
                     |this, element| this.src_query(pad, element, query),
                 )
             })
+            .iterate_internal_links_function(|pad, parent| {
+                TtToCea608::catch_panic_pad_function(
+                    parent,
+                    || gst::Iterator::from_vec(vec![]),
+                    |this, _element| this.iterate_internal_links(pad),
+                )
+            })
             .flags(gst::PadFlags::FIXED_CAPS)
             .build();
 
         Self {
             srcpad,
             sinkpad,
+            preview_srcpad: Mutex::new(None),
+            video_pads: Mutex::new(None),
             state: Mutex::new(State::default()),
             settings: Mutex::new(Settings::default()),
         }
@@ -839,7 +4949,10 @@ impl ObjectSubclass for TtToCea608 {
             "Mathieu Duponchelle <mathieu@centricular.com>",
         );
 
-        let caps = gst::Caps::builder("text/x-raw").build();
+        let mut caps = gst::Caps::new_simple("text/x-raw", &[]);
+        caps.get_mut()
+            .unwrap()
+            .append_structure(gst::Structure::new_empty("text/x-pango-markup"));
 
         let sink_pad_template = gst::PadTemplate::new(
             "sink",
@@ -855,10 +4968,45 @@ impl ObjectSubclass for TtToCea608 {
             gst::Fraction::new(std::i32::MAX, 1),
         );
 
-        let caps = gst::Caps::builder("closedcaption/x-cea-608")
+        let mut caps = gst::Caps::builder("closedcaption/x-cea-608")
             .field("format", &"raw")
             .field("framerate", &framerate)
+            .field("field", &gst::IntRange::<i32>::new(0, 1))
             .build();
+        caps.get_mut().unwrap().append_structure(
+            gst::Structure::builder("closedcaption/x-cea-608")
+                .field("format", &"s334-1a")
+                .field("framerate", &framerate)
+                .build(),
+        );
+        caps.get_mut().unwrap().append_structure(
+            gst::Structure::builder("closedcaption/x-cea-708")
+                .field("format", &"cc_data")
+                .field("framerate", &framerate)
+                .build(),
+        );
+        caps.get_mut().unwrap().append_structure(
+            gst::Structure::builder("closedcaption/x-cea-708")
+                .field("format", &"cdp")
+                .field(
+                    "framerate",
+                    &gst::List::from_owned(
+                        cdp::CDP_FRAMERATES
+                            .iter()
+                            .map(|(fps_n, fps_d, _, _)| {
+                                gst::Fraction::new(*fps_n, *fps_d).to_send_value()
+                            })
+                            .collect(),
+                    ),
+                )
+                .build(),
+        );
+        caps.get_mut().unwrap().append_structure(
+            gst::Structure::builder("text/x-raw")
+                .field("format", &"utf8")
+                .field("framerate", &framerate)
+                .build(),
+        );
 
         let src_pad_template = gst::PadTemplate::new(
             "src",
@@ -869,7 +5017,62 @@ impl ObjectSubclass for TtToCea608 {
         .unwrap();
         klass.add_pad_template(src_pad_template);
 
+        let caps = gst::Caps::builder("text/x-raw")
+            .field("format", &"utf8")
+            .build();
+
+        let preview_pad_template = gst::PadTemplate::new(
+            "preview",
+            gst::PadDirection::Src,
+            gst::PadPresence::Request,
+            &caps,
+        )
+        .unwrap();
+        klass.add_pad_template(preview_pad_template);
+
+        let caps = gst::Caps::new_any();
+
+        let video_sink_pad_template = gst::PadTemplate::new(
+            "video_sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Request,
+            &caps,
+        )
+        .unwrap();
+        klass.add_pad_template(video_sink_pad_template);
+
+        let video_src_pad_template = gst::PadTemplate::new(
+            "video_src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Sometimes,
+            &caps,
+        )
+        .unwrap();
+        klass.add_pad_template(video_src_pad_template);
+
         klass.install_properties(&PROPERTIES);
+
+        /* PTS, duration, first row and text of each caption once pushed */
+        klass.add_signal(
+            "caption-rendered",
+            glib::SignalFlags::RUN_LAST,
+            &[
+                u64::static_type(),
+                u64::static_type(),
+                u32::static_type(),
+                String::static_type(),
+            ],
+            glib::Type::Unit,
+        );
+
+        /* PTS of each input caption and what encoding it took, to detect
+         * truncation without parsing the logs */
+        klass.add_signal(
+            "caption-stats",
+            glib::SignalFlags::RUN_LAST,
+            &[u64::static_type(), gst::Structure::static_type()],
+            glib::Type::Unit,
+        );
     }
 }
 
@@ -882,13 +5085,237 @@ impl ObjectImpl for TtToCea608 {
         element.add_pad(&self.srcpad).unwrap();
     }
 
-    fn set_property(&self, _obj: &glib::Object, id: usize, value: &glib::Value) {
-        let prop = &PROPERTIES[id];
+    fn set_property(&self, _obj: &glib::Object, id: usize, value: &glib::Value) {
+        let prop = &PROPERTIES[id];
+
+        match *prop {
+            subclass::Property("mode", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.mode = value.get_some::<Mode>().expect("type checked upstream");
+            }
+            subclass::Property("base-row", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.base_row = value.get_some::<u32>().expect("type checked upstream");
+            }
+            subclass::Property("origin-row", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.origin_row = value.get_some::<u32>().expect("type checked upstream");
+            }
+            subclass::Property("origin-column", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.origin_column = value.get_some::<u32>().expect("type checked upstream");
+            }
+            subclass::Property("reading-speed", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.reading_speed = value.get_some::<u32>().expect("type checked upstream");
+            }
+            subclass::Property("reading-speed-action", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.reading_speed_action = value
+                    .get_some::<ReadingSpeedAction>()
+                    .expect("type checked upstream");
+            }
+            subclass::Property("enable-markup", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.enable_markup = value.get_some::<bool>().expect("type checked upstream");
+            }
+            subclass::Property("italics", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.italics = value.get_some::<bool>().expect("type checked upstream");
+            }
+            subclass::Property("underline", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.underline = value.get_some::<bool>().expect("type checked upstream");
+            }
+            subclass::Property("color", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.color = value.get_some::<Color>().expect("type checked upstream");
+            }
+            subclass::Property("ts-offset", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.ts_offset = value.get_some::<i64>().expect("type checked upstream");
+            }
+            subclass::Property("lead-time", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.lead_time = value.get_some::<u64>().expect("type checked upstream");
+            }
+            subclass::Property("min-duration", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.min_duration = value.get_some::<u64>().expect("type checked upstream");
+            }
+            subclass::Property("pre-clear-gap", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.pre_clear_gap = value.get_some::<u64>().expect("type checked upstream");
+            }
+            subclass::Property("window-start", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.window_start = value.get_some::<u64>().expect("type checked upstream");
+            }
+            subclass::Property("window-stop", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.window_stop = value.get_some::<u64>().expect("type checked upstream");
+            }
+            subclass::Property("merge-short", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.merge_short = value.get_some::<u64>().expect("type checked upstream");
+            }
+            subclass::Property("channel", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.channel = value.get_some::<u32>().expect("type checked upstream");
+            }
+            subclass::Property("field", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.field = value.get_some::<u32>().expect("type checked upstream");
+            }
+            subclass::Property("flip-offset", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.flip_offset = value.get_some::<u32>().expect("type checked upstream");
+            }
+            subclass::Property("wrap", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.wrap = value.get_some::<bool>().expect("type checked upstream");
+            }
+            subclass::Property("max-rows", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.max_rows = value.get_some::<u32>().expect("type checked upstream");
+            }
+            subclass::Property("paginate", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.paginate = value.get_some::<bool>().expect("type checked upstream");
+            }
+            subclass::Property("debug-output", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.debug_output = value.get_some::<bool>().expect("type checked upstream");
+            }
+            subclass::Property("alignment", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.alignment = value
+                    .get_some::<Alignment>()
+                    .expect("type checked upstream");
+            }
+            subclass::Property("fallback", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.fallback = value.get_some::<Fallback>().expect("type checked upstream");
+            }
+            subclass::Property("latency", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                let latency = value.get_some::<i32>().expect("type checked upstream");
+                settings.latency = if latency < 0 {
+                    None
+                } else {
+                    Some(latency as u32)
+                };
+            }
+            subclass::Property("replacement-char", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                let replacement = value
+                    .get::<String>()
+                    .expect("type checked upstream")
+                    .unwrap_or_default();
+                let mut chars = replacement.chars();
+
+                settings.replacement_char = match (chars.next(), chars.next()) {
+                    (Some(c), None) if encode_char(c, 0) != 0 => c,
+                    _ => {
+                        gst_warning!(
+                            CAT,
+                            "Replacement {:?} is not a single CEA-608 character, using a space",
+                            replacement
+                        );
+                        DEFAULT_REPLACEMENT_CHAR
+                    }
+                };
+            }
+            subclass::Property("default-duration", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.default_duration = value.get_some::<u64>().expect("type checked upstream");
+            }
+            subclass::Property("clear-on-empty", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.clear_on_empty = value.get_some::<bool>().expect("type checked upstream");
+            }
+            subclass::Property("dynamic-latency", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.dynamic_latency = value.get_some::<bool>().expect("type checked upstream");
+            }
+            subclass::Property("synthesize-pts", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.synthesize_pts = value.get_some::<bool>().expect("type checked upstream");
+            }
+            subclass::Property("trim-trailing", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.trim_trailing = value.get_some::<bool>().expect("type checked upstream");
+            }
+            subclass::Property("roll-up-rows", ..) => {
+                let mut roll_up_rows = value.get_some::<u32>().expect("type checked upstream");
+                if roll_up_rows == 1 {
+                    gst_warning!(CAT, "Roll-up windows have at least 2 rows, using 2");
+                    roll_up_rows = 2;
+                }
+
+                self.settings.lock().unwrap().roll_up_rows = roll_up_rows;
 
-        match *prop {
-            subclass::Property("mode", ..) => {
+                /* Picked up by the next carriage return */
+                let mut state = self.state.lock().unwrap();
+                state.settings.roll_up_rows = roll_up_rows;
+                if let Some(rows) = state.settings.roll_up_rows() {
+                    if state.settings.base_row + 1 < rows {
+                        gst_warning!(
+                            CAT,
+                            "Base row {} leaves no room for {} roll-up rows, using {}",
+                            state.settings.base_row,
+                            rows,
+                            rows - 1
+                        );
+                        state.settings.base_row = rows - 1;
+                    }
+                }
+            }
+            subclass::Property("collapse-spaces", ..) => {
                 let mut settings = self.settings.lock().unwrap();
-                settings.mode = value.get_some::<Mode>().expect("type checked upstream");
+                settings.collapse_spaces = value.get_some::<bool>().expect("type checked upstream");
+            }
+            subclass::Property("erase-non-displayed", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.erase_non_displayed =
+                    value.get_some::<bool>().expect("type checked upstream");
+            }
+            subclass::Property("out-of-order", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.out_of_order = value
+                    .get_some::<OutOfOrder>()
+                    .expect("type checked upstream");
+            }
+            subclass::Property("lossy-decode", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.lossy_decode = value.get_some::<bool>().expect("type checked upstream");
+            }
+            subclass::Property("charset", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.charset = value.get_some::<Charset>().expect("type checked upstream");
+            }
+            subclass::Property("double-control-codes", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.double_control_codes =
+                    value.get_some::<bool>().expect("type checked upstream");
+            }
+            subclass::Property("clear-on-eos", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.clear_on_eos = value.get_some::<bool>().expect("type checked upstream");
+            }
+            subclass::Property("xds-program-name", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.xds_program_name = value.get::<String>().expect("type checked upstream");
+            }
+            subclass::Property("xds-tv-rating", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.xds_tv_rating = value
+                    .get_some::<XdsTvRating>()
+                    .expect("type checked upstream");
+            }
+            subclass::Property("max-on-screen", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.max_on_screen = value.get_some::<u64>().expect("type checked upstream");
             }
             _ => unimplemented!(),
         }
@@ -902,6 +5329,201 @@ impl ObjectImpl for TtToCea608 {
                 let settings = self.settings.lock().unwrap();
                 Ok(settings.mode.to_value())
             }
+            subclass::Property("base-row", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.base_row.to_value())
+            }
+            subclass::Property("roll-up-window", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.roll_up_rows().unwrap_or(0).to_value())
+            }
+            subclass::Property("origin-row", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.origin_row.to_value())
+            }
+            subclass::Property("origin-column", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.origin_column.to_value())
+            }
+            subclass::Property("reading-speed", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.reading_speed.to_value())
+            }
+            subclass::Property("reading-speed-action", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.reading_speed_action.to_value())
+            }
+            subclass::Property("enable-markup", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.enable_markup.to_value())
+            }
+            subclass::Property("italics", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.italics.to_value())
+            }
+            subclass::Property("underline", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.underline.to_value())
+            }
+            subclass::Property("color", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.color.to_value())
+            }
+            subclass::Property("ts-offset", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.ts_offset.to_value())
+            }
+            subclass::Property("lead-time", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.lead_time.to_value())
+            }
+            subclass::Property("min-duration", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.min_duration.to_value())
+            }
+            subclass::Property("pre-clear-gap", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.pre_clear_gap.to_value())
+            }
+            subclass::Property("window-start", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.window_start.to_value())
+            }
+            subclass::Property("window-stop", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.window_stop.to_value())
+            }
+            subclass::Property("merge-short", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.merge_short.to_value())
+            }
+            subclass::Property("channel", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.channel.to_value())
+            }
+            subclass::Property("field", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.field.to_value())
+            }
+            subclass::Property("flip-offset", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.flip_offset.to_value())
+            }
+            subclass::Property("wrap", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.wrap.to_value())
+            }
+            subclass::Property("max-rows", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.max_rows.to_value())
+            }
+            subclass::Property("paginate", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.paginate.to_value())
+            }
+            subclass::Property("debug-output", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.debug_output.to_value())
+            }
+            subclass::Property("alignment", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.alignment.to_value())
+            }
+            subclass::Property("fallback", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.fallback.to_value())
+            }
+            subclass::Property("latency", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings
+                    .latency
+                    .map(|latency| latency as i32)
+                    .unwrap_or(-1)
+                    .to_value())
+            }
+            subclass::Property("replacement-char", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.replacement_char.to_string().to_value())
+            }
+            subclass::Property("default-duration", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.default_duration.to_value())
+            }
+            subclass::Property("clear-on-empty", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.clear_on_empty.to_value())
+            }
+            subclass::Property("dynamic-latency", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.dynamic_latency.to_value())
+            }
+            subclass::Property("synthesize-pts", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.synthesize_pts.to_value())
+            }
+            subclass::Property("trim-trailing", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.trim_trailing.to_value())
+            }
+            subclass::Property("roll-up-rows", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.roll_up_rows.to_value())
+            }
+            subclass::Property("collapse-spaces", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.collapse_spaces.to_value())
+            }
+            subclass::Property("stats", ..) => {
+                let state = self.state.lock().unwrap();
+                let s = gst::Structure::new(
+                    "application/x-tttocea608-stats",
+                    &[
+                        ("num-pairs", &state.stats.num_pairs),
+                        ("num-chars", &state.stats.num_chars),
+                        ("num-rows", &state.stats.num_rows),
+                        ("num-erase-display", &state.stats.num_erase_display),
+                        ("num-dropped", &state.stats.num_dropped),
+                        ("num-replaced", &state.stats.num_replaced),
+                    ],
+                );
+                Ok(s.to_value())
+            }
+            subclass::Property("erase-non-displayed", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.erase_non_displayed.to_value())
+            }
+            subclass::Property("out-of-order", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.out_of_order.to_value())
+            }
+            subclass::Property("lossy-decode", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.lossy_decode.to_value())
+            }
+            subclass::Property("charset", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.charset.to_value())
+            }
+            subclass::Property("double-control-codes", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.double_control_codes.to_value())
+            }
+            subclass::Property("clear-on-eos", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.clear_on_eos.to_value())
+            }
+            subclass::Property("xds-program-name", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.xds_program_name.to_value())
+            }
+            subclass::Property("xds-tv-rating", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.xds_tv_rating.to_value())
+            }
+            subclass::Property("max-on-screen", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.max_on_screen.to_value())
+            }
             _ => unimplemented!(),
         }
     }
@@ -921,8 +5543,40 @@ impl ElementImpl for TtToCea608 {
                 let settings = self.settings.lock().unwrap();
                 *state = State::default();
                 state.settings = settings.clone();
-                if state.settings.mode != Mode::PopOn {
+                state.cc_data_pool = new_cc_data_pool(element);
+                state.style.italics = state.settings.italics;
+                state.style.underline = state.settings.underline;
+                state.style.color = state.settings.color;
+                if state.settings.max_rows != DEFAULT_MAX_ROWS
+                    && state.settings.origin_row + state.settings.max_rows > 15
+                {
+                    gst_warning!(
+                        CAT,
+                        obj: element,
+                        "{} rows don't fit below origin row {}, longer captions will be moved up",
+                        state.settings.max_rows,
+                        state.settings.origin_row
+                    );
+                }
+                if state.settings.mode.is_roll_up() {
                     state.send_roll_up = true;
+                } else if state.settings.mode == Mode::PaintOn {
+                    state.send_direct_captioning = true;
+                }
+
+                /* Make sure the whole roll-up window stays on screen */
+                if let Some(rows) = state.settings.roll_up_rows() {
+                    if state.settings.base_row + 1 < rows {
+                        gst_warning!(
+                            CAT,
+                            obj: element,
+                            "Base row {} leaves no room for {} roll-up rows, using {}",
+                            state.settings.base_row,
+                            rows,
+                            rows - 1
+                        );
+                        state.settings.base_row = rows - 1;
+                    }
                 }
             }
             _ => (),
@@ -933,6 +5587,16 @@ impl ElementImpl for TtToCea608 {
         match transition {
             gst::StateChange::PausedToReady => {
                 let mut state = self.state.lock().unwrap();
+                if let Some(pool) = state.cc_data_pool.take() {
+                    if let Err(err) = pool.set_active(false) {
+                        gst_warning!(
+                            CAT,
+                            obj: element,
+                            "Failed to deactivate the cc_data buffer pool: {}",
+                            err
+                        );
+                    }
+                }
                 *state = State::default();
             }
             _ => (),
@@ -940,6 +5604,68 @@ impl ElementImpl for TtToCea608 {
 
         Ok(ret)
     }
+
+    fn request_new_pad(
+        &self,
+        element: &gst::Element,
+        templ: &gst::PadTemplate,
+        _name: Option<String>,
+        _caps: Option<&gst::Caps>,
+    ) -> Option<gst::Pad> {
+        if Some(templ) == element.get_pad_template("video_sink").as_ref() {
+            return self.request_video_pads(element);
+        }
+
+        let mut preview_srcpad = self.preview_srcpad.lock().unwrap();
+
+        if preview_srcpad.is_some() {
+            gst_error!(CAT, obj: element, "Preview pad already requested");
+            return None;
+        }
+
+        let pad = gst::Pad::builder_with_template(templ, Some("preview"))
+            .flags(gst::PadFlags::FIXED_CAPS)
+            .build();
+
+        pad.set_active(true).unwrap();
+        *preview_srcpad = Some(pad.clone());
+        drop(preview_srcpad);
+
+        element.add_pad(&pad).unwrap();
+
+        Some(pad)
+    }
+
+    fn release_pad(&self, element: &gst::Element, pad: &gst::Pad) {
+        let mut video_pads = self.video_pads.lock().unwrap();
+        if let Some((video_sinkpad, video_srcpad)) = video_pads.clone() {
+            if pad == &video_sinkpad {
+                *video_pads = None;
+                drop(video_pads);
+
+                self.state.lock().unwrap().meta_queue.clear();
+
+                for pad in &[video_sinkpad, video_srcpad] {
+                    let _ = pad.set_active(false);
+                    element.remove_pad(pad).unwrap();
+                }
+                return;
+            }
+        }
+        drop(video_pads);
+
+        let mut preview_srcpad = self.preview_srcpad.lock().unwrap();
+
+        if preview_srcpad.as_ref() != Some(pad) {
+            return;
+        }
+
+        *preview_srcpad = None;
+        drop(preview_srcpad);
+
+        let _ = pad.set_active(false);
+        element.remove_pad(pad).unwrap();
+    }
 }
 
 pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
@@ -950,3 +5676,212 @@ pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
         TtToCea608::get_type(),
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preamble_row_clamping() {
+        gst::init().unwrap();
+
+        for (row, clamped_row) in &[(-1, 0), (15, 14), (20, 14)] {
+            let mut buffers = vec![];
+            preamble_buffer(&mut buffers, None, *row, 0, 0, Style::default(), 2);
+
+            let expected = eia608_row_style_preamble(
+                *clamped_row,
+                ffi::eia608_style_t_eia608_style_white,
+                0,
+                false,
+            )
+            .to_be_bytes();
+
+            assert_eq!(buffers.len(), 2);
+            for buffer in &buffers {
+                assert_eq!(&*buffer.map_readable().unwrap(), &expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_preamble_tab_offset() {
+        gst::init().unwrap();
+
+        let mut buffers = vec![];
+        preamble_buffer(&mut buffers, None, 13, 6, 0, Style::default(), 2);
+
+        let indent = eia608_row_column_preamble(13, 4, 0, false).to_be_bytes();
+        let tab_offset =
+            eia608_control_command(ffi::eia608_control_t_eia608_tab_offset_2, 0).to_be_bytes();
+
+        let data: Vec<Vec<u8>> = buffers
+            .iter()
+            .map(|buffer| buffer.map_readable().unwrap().to_vec())
+            .collect();
+        assert_eq!(
+            data,
+            vec![
+                indent.to_vec(),
+                indent.to_vec(),
+                tab_offset.to_vec(),
+                tab_offset.to_vec()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_split_screens() {
+        for (data, markup, expected) in &[
+            ("one\ntwo three four", false, vec!["one\ntwo", "three four"]),
+            (
+                "<u>one two three four</u>",
+                false,
+                vec!["<u>one two", "three four</u>"],
+            ),
+            (
+                "<u>one two three four</u>",
+                true,
+                vec!["<u>one two</u>", "<u>three four</u>"],
+            ),
+            (
+                "<span foreground=\"red\">one two three four</span>",
+                true,
+                vec![
+                    "<span foreground=\"red\">one two</span>",
+                    "<span foreground=\"red\">three four</span>",
+                ],
+            ),
+            (
+                "<i>one\ntwo three</i> <span foreground=\"red\">four five six</span>",
+                true,
+                vec![
+                    "<i>one\ntwo three</i>",
+                    "<span foreground=\"red\">four five six</span>",
+                ],
+            ),
+        ] {
+            assert_eq!(&split_screens(data, *markup, 2), expected);
+        }
+    }
+
+    #[test]
+    fn test_schedule_extreme_values() {
+        let max = MAX_RUNNING_TIME;
+
+        /* A caption from 1s to 2s */
+        for (ts_offset, lead_time, min_duration, pre_clear_gap, expected) in &[
+            (std::i64::MAX, 0, 0, 0, (max, 0)),
+            (std::i64::MIN, 0, 0, 0, (0, 0)),
+            (0, std::u64::MAX, 0, 0, (0, 2_000_000_000)),
+            (0, 0, std::u64::MAX, 0, (1_000_000_000, max - 1_000_000_000)),
+            (0, 0, 0, std::u64::MAX, (1_000_000_000, 0)),
+            (std::i64::MAX, 0, std::u64::MAX, 0, (max, 0)),
+            (
+                std::i64::MIN,
+                std::u64::MAX,
+                std::u64::MAX,
+                std::u64::MAX,
+                (0, 0),
+            ),
+        ] {
+            let mut settings = Settings::default();
+            settings.ts_offset = *ts_offset;
+            settings.lead_time = *lead_time;
+            settings.min_duration = *min_duration;
+            settings.pre_clear_gap = *pre_clear_gap;
+
+            assert_eq!(
+                schedule(&settings, gst::SECOND, gst::SECOND),
+                (expected.0.into(), expected.1.into())
+            );
+        }
+    }
+
+    #[test]
+    fn test_cc_data_pool() {
+        gst::init().unwrap();
+
+        let buffer_pool = |buffer: &gst::Buffer| -> Option<gst::BufferPool> {
+            unsafe { glib::translate::from_glib_none((*buffer.as_ptr()).pool) }
+        };
+
+        let element = gst::Bin::new(None).upcast::<gst::Element>();
+        let pool = new_cc_data_pool(&element).unwrap();
+
+        let buffer = buffer_from_cc_data(Some(&pool), 0x9420);
+        assert_eq!(&*buffer.map_readable().unwrap(), &[0x94, 0x20]);
+        drop(buffer);
+
+        /* Comes from the pool, and released buffers get the new pair */
+        let buffer = buffer_from_cc_data(Some(&pool), 0xc1c2);
+        assert_eq!(&*buffer.map_readable().unwrap(), &[0xc1, 0xc2]);
+        assert_eq!(buffer_pool(&buffer), Some(pool.clone()));
+        drop(buffer);
+
+        /* Without a pool the byte pairs are allocated one by one */
+        let buffer = buffer_from_cc_data(None, 0xc1c2);
+        assert_eq!(&*buffer.map_readable().unwrap(), &[0xc1, 0xc2]);
+        assert_eq!(buffer_pool(&buffer), None);
+
+        pool.set_active(false).unwrap();
+    }
+
+    #[test]
+    fn test_set_timestamps_shared() {
+        gst::init().unwrap();
+
+        let buffer = buffer_from_cc_data(None, 0x9420);
+        let mut shared = buffer.clone();
+
+        set_timestamps(&mut shared, gst::SECOND, 33_333_333.into());
+
+        assert_eq!(shared.get_pts(), gst::SECOND);
+        assert_eq!(shared.get_duration(), 33_333_333.into());
+        assert_eq!(buffer.get_pts(), gst::CLOCK_TIME_NONE);
+        assert_eq!(&*shared.map_readable().unwrap(), &[0x94, 0x20]);
+    }
+
+    #[test]
+    fn test_native_encoding() {
+        for channel in 0..4 {
+            for c in 0..0x80u8 {
+                let encoded = [c, 0, 0, 0, 0];
+                let expected =
+                    unsafe { ffi::eia608_from_utf8_1(encoded.as_ptr() as *const _, channel & 1) };
+
+                assert_eq!(eia608_from_utf8_1(&encoded, channel), expected, "{:#x}", c);
+            }
+
+            for cmd in 0x1420..0x1430 {
+                let expected = unsafe { ffi::eia608_control_command(cmd, channel) };
+                assert_eq!(eia608_control_command(cmd, channel), expected);
+            }
+
+            /* libcaption sets the field bit on tab offsets too */
+            for cmd in 0x1721..0x1724 {
+                let expected = unsafe { ffi::eia608_control_command(cmd, channel & 1) };
+                assert_eq!(eia608_control_command(cmd, channel), expected);
+            }
+        }
+
+        for c1 in 0x20..0x80u8 {
+            for c2 in 0x20..0x80u8 {
+                let encoded1 = [c1, 0, 0, 0, 0];
+                let encoded2 = [c2, 0, 0, 0, 0];
+                let expected = unsafe {
+                    ffi::eia608_from_utf8_2(
+                        encoded1.as_ptr() as *const _,
+                        encoded2.as_ptr() as *const _,
+                    )
+                };
+
+                assert_eq!(eia608_from_utf8_2(&encoded1, &encoded2), expected);
+
+                let (bna1, bna2) = ((c1 as u16) << 8, (c2 as u16) << 8);
+                let expected = unsafe { ffi::eia608_from_basicna(bna1, bna2) };
+                assert_eq!(eia608_from_basicna(bna1, bna2), expected);
+            }
+        }
+    }
+}
@@ -0,0 +1,820 @@
+// Copyright (C) 2020 Mathieu Duponchelle <mathieu@centricular.com>
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Library General Public
+// License as published by the Free Software Foundation; either
+// version 2 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Library General Public License for more details.
+//
+// You should have received a copy of the GNU Library General Public
+// License along with this library; if not, write to the
+// Free Software Foundation, Inc., 51 Franklin Street, Suite 500,
+// Boston, MA 02110-1335, USA.
+
+use glib::prelude::*;
+use glib::subclass;
+use glib::subclass::prelude::*;
+use gst::prelude::*;
+use gst::subclass::prelude::*;
+
+use super::cdp;
+
+use std::sync::Mutex;
+
+/* DTVCC window commands, CEA-708 section 8.10.5 */
+const CLW: u8 = 0x88;
+const DSW: u8 = 0x89;
+const DLW: u8 = 0x8c;
+const SPL: u8 = 0x92;
+const DF0: u8 = 0x98;
+
+/* Service blocks hold at most 31 bytes of data, DTVCC packets at most
+ * 127 bytes after their header */
+const MAX_BLOCK_SIZE: usize = 31;
+const MAX_PACKET_SIZE: usize = 127;
+
+const MAX_ROWS: usize = 15;
+const MAX_COLUMNS: usize = 32;
+
+/* Captions are loaded into a hidden window during the frames preceding
+ * their PTS, this is how many we can use */
+const LATENCY_FRAMES: u64 = 10;
+
+const DEFAULT_FPS_N: i32 = 30;
+const DEFAULT_FPS_D: i32 = 1;
+
+const DEFAULT_SERVICE_NUMBER: u32 = 1;
+
+static PROPERTIES: [subclass::Property; 1] = [subclass::Property("service-number", |name| {
+    glib::ParamSpec::uint(
+        name,
+        "Service Number",
+        "DTVCC caption service to output the captions on",
+        1,
+        63,
+        DEFAULT_SERVICE_NUMBER,
+        glib::ParamFlags::READWRITE,
+    )
+})];
+
+#[derive(Debug, Clone)]
+struct Settings {
+    service_number: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            service_number: DEFAULT_SERVICE_NUMBER,
+        }
+    }
+}
+
+/* Format negotiated with downstream */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    /* closedcaption/x-cea-708, format=cc_data: a frame's triples per buffer */
+    CcData,
+    /* closedcaption/x-cea-708, format=cdp: one CDP per buffer */
+    Cdp,
+}
+
+struct State {
+    settings: Settings,
+    /* Input segment, we work with running times */
+    segment: gst::FormattedSegment<gst::ClockTime>,
+    framerate: gst::Fraction,
+    output_format: OutputFormat,
+    cdp_sequence_counter: u16,
+    last_frame_no: u64,
+    /* Window of the displayed caption, and the frame it expires on */
+    pending_delete: Option<(u8, u64)>,
+    /* Captions alternate between windows 0 and 1, so that the next
+     * one can be loaded while the current one is displayed */
+    next_window: u8,
+    sequence_number: u8,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            settings: Settings::default(),
+            segment: gst::FormattedSegment::new(),
+            framerate: gst::Fraction::new(DEFAULT_FPS_N, DEFAULT_FPS_D),
+            output_format: OutputFormat::CcData,
+            cdp_sequence_counter: 0,
+            last_frame_no: 0,
+            pending_delete: None,
+            next_window: 0,
+            sequence_number: 0,
+        }
+    }
+}
+
+impl State {
+    /* Number of cc_data triples per frame, for a total of 9600 bit/s */
+    fn cc_count(&self) -> usize {
+        let cc_count = 600 * *self.framerate.denom() as u64 / *self.framerate.numer() as u64;
+        std::cmp::max(cc_count, 1) as usize
+    }
+
+    fn frame_no(&self, pts: gst::ClockTime) -> u64 {
+        let (fps_n, fps_d) = (
+            *self.framerate.numer() as u64,
+            *self.framerate.denom() as u64,
+        );
+
+        (pts.mul_div_round(fps_n, fps_d).unwrap() / gst::SECOND).unwrap()
+    }
+
+    fn frame_pts(&self, frame_no: u64) -> gst::ClockTime {
+        let (fps_n, fps_d) = (
+            *self.framerate.numer() as u64,
+            *self.framerate.denom() as u64,
+        );
+
+        (frame_no * gst::SECOND)
+            .mul_div_round(fps_d, fps_n)
+            .unwrap()
+    }
+
+    /* Packs commands into service blocks, and those into DTVCC packets,
+     * never splitting a command, then returns the cc_data triples that
+     * carry the packets */
+    fn cc_data(&mut self, commands: &[Vec<u8>]) -> Vec<[u8; 3]> {
+        let service_number = self.settings.service_number as u8;
+        /* Services 7 and above have an extended block header */
+        let header_size = if service_number < 7 { 1 } else { 2 };
+
+        let mut blocks = vec![];
+        let mut block = vec![];
+        for command in commands {
+            if block.len() + command.len() > MAX_BLOCK_SIZE {
+                blocks.push(block);
+                block = vec![];
+            }
+            block.extend_from_slice(command);
+        }
+        if !block.is_empty() {
+            blocks.push(block);
+        }
+
+        let mut packets = vec![];
+        let mut packet = vec![];
+        for block in blocks {
+            if packet.len() + header_size + block.len() > MAX_PACKET_SIZE {
+                packets.push(self.finish_packet(packet));
+                packet = vec![];
+            }
+
+            if service_number < 7 {
+                packet.push((service_number << 5) | block.len() as u8);
+            } else {
+                packet.push((7 << 5) | block.len() as u8);
+                packet.push(service_number);
+            }
+            packet.extend(block);
+        }
+        if !packet.is_empty() {
+            packets.push(self.finish_packet(packet));
+        }
+
+        let mut triples = vec![];
+        for packet in packets {
+            for (i, pair) in packet.chunks(2).enumerate() {
+                /* cc_valid, with cc_type 3 on the start of a packet and 2
+                 * on its continuation */
+                let cc_type = if i == 0 { 0xff } else { 0xfe };
+                triples.push([cc_type, pair[0], pair[1]]);
+            }
+        }
+
+        triples
+    }
+
+    /* Prepends the packet header, packets being a whole number of byte
+     * pairs */
+    fn finish_packet(&mut self, data: Vec<u8>) -> Vec<u8> {
+        let mut packet = vec![0];
+        packet.extend(data);
+
+        if packet.len() % 2 == 1 {
+            /* Null service block header */
+            packet.push(0);
+        }
+
+        /* A size code of 0 means 128 bytes */
+        let size_code = (packet.len() / 2) % 64;
+        packet[0] = (self.sequence_number << 6) | size_code as u8;
+        self.sequence_number = (self.sequence_number + 1) % 4;
+
+        packet
+    }
+}
+
+/* Hidden window with the given number of rows and columns, anchored at
+ * the bottom center of the screen */
+fn define_window(window: u8, rows: u8, columns: u8) -> Vec<u8> {
+    vec![
+        DF0 + window,
+        /* Hidden, row and column locked, priority 0 */
+        0x18,
+        /* Relative positioning, 90% from the top */
+        0x80 | 90,
+        /* 50% from the left */
+        50,
+        /* Anchored on the bottom center point of the window */
+        (7 << 4) | (rows - 1),
+        columns - 1,
+        /* Window style 1, pen style 1 */
+        (1 << 3) | 1,
+    ]
+}
+
+/* G0 is ASCII with a music note instead of DEL, G1 is Latin-1 */
+fn encode_char(c: char) -> Option<u8> {
+    match c as u32 {
+        0x20..=0x7e | 0xa0..=0xff => Some(c as u8),
+        0x266a => Some(0x7f),
+        _ => None,
+    }
+}
+
+enum Output {
+    Gap(gst::ClockTime, gst::ClockTime),
+    Buffer(gst::Buffer),
+}
+
+struct TtToCea708 {
+    srcpad: gst::Pad,
+    sinkpad: gst::Pad,
+
+    state: Mutex<State>,
+    settings: Mutex<Settings>,
+}
+
+lazy_static! {
+    static ref CAT: gst::DebugCategory = gst::DebugCategory::new(
+        "tttocea708",
+        gst::DebugColorFlags::empty(),
+        Some("TT CEA 708 Element"),
+    );
+}
+
+impl TtToCea708 {
+    /* Spreads the triples over frames starting from frame_no, returning
+     * the frame following the last one */
+    fn output_frames(
+        state: &mut State,
+        outputs: &mut Vec<Output>,
+        mut frame_no: u64,
+        triples: &[[u8; 3]],
+    ) -> u64 {
+        let cc_count = state.cc_count();
+
+        if frame_no > state.last_frame_no {
+            let start = state.frame_pts(state.last_frame_no);
+            let end = state.frame_pts(frame_no);
+            outputs.push(Output::Gap(start, end - start));
+        }
+
+        let output_format = state.output_format;
+        for chunk in triples.chunks(cc_count) {
+            let data = match output_format {
+                OutputFormat::CcData => {
+                    let mut data = Vec::with_capacity(cc_count * 3);
+                    for triple in chunk {
+                        data.extend_from_slice(triple);
+                    }
+                    /* Padding */
+                    for _ in chunk.len()..cc_count {
+                        data.extend_from_slice(&[0xfa, 0x00, 0x00]);
+                    }
+                    data
+                }
+                OutputFormat::Cdp => {
+                    /* The frame rate was checked when negotiating */
+                    let (framerate_code, _) = cdp::framerate_code(state.framerate).unwrap();
+                    let sequence_counter = state.cdp_sequence_counter;
+                    state.cdp_sequence_counter = sequence_counter.wrapping_add(1);
+
+                    cdp::packet(
+                        chunk,
+                        framerate_code,
+                        cc_count,
+                        cdp::time_code(
+                            frame_no,
+                            *state.framerate.numer() as u64,
+                            *state.framerate.denom() as u64,
+                        ),
+                        sequence_counter,
+                    )
+                }
+            };
+
+            let pts = state.frame_pts(frame_no);
+            let mut buffer = gst::Buffer::from_mut_slice(data);
+            {
+                let buf_mut = buffer.get_mut().unwrap();
+                buf_mut.set_pts(pts);
+                buf_mut.set_duration(state.frame_pts(frame_no + 1) - pts);
+            }
+            outputs.push(Output::Buffer(buffer));
+
+            frame_no += 1;
+        }
+
+        state.last_frame_no = frame_no;
+
+        frame_no
+    }
+
+    /* Deletes the displayed caption if it expired by frame_no */
+    fn delete_expired(state: &mut State, outputs: &mut Vec<Output>, frame_no: u64) {
+        if let Some((window, delete_frame_no)) = state.pending_delete {
+            if delete_frame_no <= frame_no {
+                state.pending_delete = None;
+
+                let triples = state.cc_data(&[vec![DLW, 1 << window]]);
+                let delete_frame_no = std::cmp::max(delete_frame_no, state.last_frame_no);
+                Self::output_frames(state, outputs, delete_frame_no, &triples);
+            }
+        }
+    }
+
+    fn push_outputs(&self, outputs: Vec<Output>) -> Result<gst::FlowSuccess, gst::FlowError> {
+        for output in outputs {
+            match output {
+                Output::Gap(pts, duration) => {
+                    let _ = self.srcpad.push_event(gst::event::Gap::new(pts, duration));
+                }
+                Output::Buffer(buffer) => {
+                    self.srcpad.push(buffer)?;
+                }
+            }
+        }
+
+        Ok(gst::FlowSuccess::Ok)
+    }
+
+    fn sink_chain(
+        &self,
+        pad: &gst::Pad,
+        element: &gst::Element,
+        buffer: gst::Buffer,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let pts = match buffer.get_pts() {
+            gst::CLOCK_TIME_NONE => {
+                gst_element_error!(
+                    element,
+                    gst::StreamError::Format,
+                    ["Stream with timestamped buffers required"]
+                );
+                Err(gst::FlowError::Error)
+            }
+            pts => Ok(pts),
+        }?;
+
+        let duration = match buffer.get_duration() {
+            gst::CLOCK_TIME_NONE => {
+                gst_element_error!(
+                    element,
+                    gst::StreamError::Format,
+                    ["Buffers of stream need to have a duration"]
+                );
+                Err(gst::FlowError::Error)
+            }
+            duration => Ok(duration),
+        }?;
+
+        let data = buffer.map_readable().map_err(|_| {
+            gst_error!(CAT, obj: pad, "Can't map buffer readable");
+
+            gst::FlowError::Error
+        })?;
+
+        let data = std::str::from_utf8(&data).map_err(|err| {
+            gst_error!(CAT, obj: pad, "Can't decode utf8: {}", err);
+
+            gst::FlowError::Error
+        })?;
+
+        let mut lines = vec![];
+        for line in data.lines().take(MAX_ROWS) {
+            let mut encoded = vec![];
+            for c in line.chars().take(MAX_COLUMNS) {
+                encoded.push(encode_char(c).unwrap_or_else(|| {
+                    gst_warning!(CAT, obj: element, "Not translating UTF8: {}", c);
+                    b' '
+                }));
+            }
+            lines.push(encoded);
+        }
+        if lines.is_empty() {
+            lines.push(vec![]);
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let mut outputs = vec![];
+
+        let start = state.segment.to_running_time(pts);
+        let end = state.segment.to_running_time(pts + duration);
+        if start == gst::CLOCK_TIME_NONE || end == gst::CLOCK_TIME_NONE {
+            gst_debug!(CAT, obj: pad, "Dropping buffer outside of the segment");
+            return Ok(gst::FlowSuccess::Ok);
+        }
+
+        let frame_no = state.frame_no(start);
+        let end_frame_no = state.frame_no(end);
+
+        let window = state.next_window;
+        state.next_window = 1 - window;
+
+        let mut commands = vec![
+            define_window(window, lines.len() as u8, MAX_COLUMNS as u8),
+            vec![CLW, 1 << window],
+        ];
+        for (row, line) in lines.iter().enumerate() {
+            commands.push(vec![SPL, row as u8, 0]);
+            commands.extend(line.iter().map(|c| vec![*c]));
+        }
+        let load_triples = state.cc_data(&commands);
+        let cc_count = state.cc_count();
+        let n_load_frames = ((load_triples.len() + cc_count - 1) / cc_count) as u64;
+
+        /* If the displayed caption expires while we load ours, it gets
+         * deleted when ours is displayed */
+        Self::delete_expired(
+            &mut state,
+            &mut outputs,
+            frame_no.saturating_sub(n_load_frames),
+        );
+
+        let load_frame_no =
+            std::cmp::max(frame_no.saturating_sub(n_load_frames), state.last_frame_no);
+        let show_frame_no =
+            Self::output_frames(&mut state, &mut outputs, load_frame_no, &load_triples);
+
+        if show_frame_no > frame_no {
+            gst_warning!(
+                CAT,
+                obj: element,
+                "Caption at {} needs {} frames to load, displaying it late",
+                pts,
+                n_load_frames
+            );
+        }
+        let show_frame_no = std::cmp::max(show_frame_no, frame_no);
+
+        /* The previous caption is still displayed, replace it */
+        let mut show_commands = vec![];
+        if let Some((window, _)) = state.pending_delete.take() {
+            show_commands.push(vec![DLW, 1 << window]);
+        }
+        show_commands.push(vec![DSW, 1 << window]);
+
+        let show_triples = state.cc_data(&show_commands);
+        Self::output_frames(&mut state, &mut outputs, show_frame_no, &show_triples);
+
+        state.pending_delete = Some((window, std::cmp::max(end_frame_no, show_frame_no + 1)));
+
+        drop(state);
+
+        self.push_outputs(outputs)
+    }
+
+    fn src_query(&self, pad: &gst::Pad, element: &gst::Element, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryView;
+
+        gst_log!(CAT, obj: pad, "Handling query {:?}", query);
+
+        match query.view_mut() {
+            QueryView::Latency(ref mut q) => {
+                let mut peer_query = gst::query::Latency::new();
+
+                let ret = self.sinkpad.peer_query(&mut peer_query);
+
+                if ret {
+                    let state = self.state.lock().unwrap();
+                    let (live, min, max) = peer_query.get_result();
+                    let our_latency = state.frame_pts(LATENCY_FRAMES);
+
+                    q.set(live, min + our_latency, max + our_latency);
+                }
+                ret
+            }
+            _ => pad.query_default(Some(element), query),
+        }
+    }
+
+    fn sink_event(&self, pad: &gst::Pad, element: &gst::Element, event: gst::Event) -> bool {
+        use gst::EventView;
+
+        gst_log!(CAT, obj: pad, "Handling event {:?}", event);
+
+        match event.view() {
+            EventView::Segment(e) => {
+                let segment = match e.get_segment().clone().downcast::<gst::ClockTime>() {
+                    Err(segment) => {
+                        gst_element_error!(
+                            element,
+                            gst::StreamError::Format,
+                            [
+                                "Only Time segments supported, got {:?}",
+                                segment.get_format()
+                            ]
+                        );
+                        return false;
+                    }
+                    Ok(segment) => segment,
+                };
+
+                gst_debug!(CAT, obj: pad, "Got new segment {:?}", segment);
+                self.state.lock().unwrap().segment = segment;
+
+                /* We timestamp our output with the running time */
+                let new_event =
+                    gst::event::Segment::builder(&gst::FormattedSegment::<gst::ClockTime>::new())
+                        .seqnum(event.get_seqnum())
+                        .build();
+
+                self.srcpad.push_event(new_event)
+            }
+            EventView::Caps(..) => {
+                let mut downstream_caps = match self.srcpad.get_allowed_caps() {
+                    None => self.srcpad.get_pad_template_caps().unwrap(),
+                    Some(caps) => caps,
+                };
+
+                if downstream_caps.is_empty() {
+                    gst_error!(CAT, obj: pad, "Empty downstream caps");
+                    return false;
+                }
+
+                let caps = downstream_caps.make_mut();
+                let s = caps.get_mut_structure(0).unwrap();
+
+                s.fixate_field_nearest_fraction(
+                    "framerate",
+                    gst::Fraction::new(DEFAULT_FPS_N, DEFAULT_FPS_D),
+                );
+                s.fixate();
+
+                let mut state = self.state.lock().unwrap();
+                state.framerate = s.get_some::<gst::Fraction>("framerate").unwrap();
+                state.output_format = match s.get::<&str>("format") {
+                    Ok(Some("cdp")) => OutputFormat::Cdp,
+                    _ => OutputFormat::CcData,
+                };
+
+                gst_debug!(CAT, obj: pad, "Pushing caps {}", caps);
+
+                let new_event = gst::event::Caps::new(&downstream_caps);
+
+                drop(state);
+
+                self.srcpad.push_event(new_event)
+            }
+            EventView::Gap(e) => {
+                let mut state = self.state.lock().unwrap();
+                let mut outputs = vec![];
+
+                let (timestamp, duration) = e.get();
+                let end = state.segment.to_running_time(timestamp + duration);
+                if end == gst::CLOCK_TIME_NONE {
+                    gst_debug!(CAT, obj: pad, "Ignoring gap outside of the segment");
+                    return true;
+                }
+
+                let frame_no = state.frame_no(end);
+                Self::delete_expired(&mut state, &mut outputs, frame_no);
+
+                if frame_no > state.last_frame_no {
+                    let start = state.frame_pts(state.last_frame_no);
+                    let end = state.frame_pts(frame_no);
+                    outputs.push(Output::Gap(start, end - start));
+                    state.last_frame_no = frame_no;
+                }
+
+                drop(state);
+
+                /* Ignore return value, we may be flushing here and can't
+                 * communicate that through a boolean
+                 */
+                let _ = self.push_outputs(outputs);
+
+                true
+            }
+            EventView::Eos(_) => {
+                let mut state = self.state.lock().unwrap();
+                let mut outputs = vec![];
+
+                Self::delete_expired(&mut state, &mut outputs, std::u64::MAX);
+
+                drop(state);
+
+                /* Ignore return value, we may be flushing here and can't
+                 * communicate that through a boolean
+                 */
+                let _ = self.push_outputs(outputs);
+
+                pad.event_default(Some(element), event)
+            }
+            EventView::FlushStop(_) => {
+                let mut state = self.state.lock().unwrap();
+
+                /* Timestamps restart after a seek, the framerate stays the same */
+                state.last_frame_no = 0;
+                state.pending_delete = None;
+
+                pad.event_default(Some(element), event)
+            }
+            _ => pad.event_default(Some(element), event),
+        }
+    }
+}
+
+impl ObjectSubclass for TtToCea708 {
+    const NAME: &'static str = "TtToCea708";
+    type ParentType = gst::Element;
+    type Instance = gst::subclass::ElementInstanceStruct<Self>;
+    type Class = subclass::simple::ClassStruct<Self>;
+
+    glib_object_subclass!();
+
+    fn with_class(klass: &subclass::simple::ClassStruct<Self>) -> Self {
+        let templ = klass.get_pad_template("sink").unwrap();
+        let sinkpad = gst::Pad::builder_with_template(&templ, Some("sink"))
+            .chain_function(|pad, parent, buffer| {
+                TtToCea708::catch_panic_pad_function(
+                    parent,
+                    || Err(gst::FlowError::Error),
+                    |this, element| this.sink_chain(pad, element, buffer),
+                )
+            })
+            .event_function(|pad, parent, event| {
+                TtToCea708::catch_panic_pad_function(
+                    parent,
+                    || false,
+                    |this, element| this.sink_event(pad, element, event),
+                )
+            })
+            .flags(gst::PadFlags::FIXED_CAPS)
+            .build();
+
+        let templ = klass.get_pad_template("src").unwrap();
+        let srcpad = gst::Pad::builder_with_template(&templ, Some("src"))
+            .query_function(|pad, parent, query| {
+                TtToCea708::catch_panic_pad_function(
+                    parent,
+                    || false,
+                    |this, element| this.src_query(pad, element, query),
+                )
+            })
+            .flags(gst::PadFlags::FIXED_CAPS)
+            .build();
+
+        Self {
+            srcpad,
+            sinkpad,
+            state: Mutex::new(State::default()),
+            settings: Mutex::new(Settings::default()),
+        }
+    }
+
+    fn class_init(klass: &mut subclass::simple::ClassStruct<Self>) {
+        klass.set_metadata(
+            "TT to CEA-708",
+            "Generic",
+            "Converts timed text to CEA-708 Closed Captions",
+            "Mathieu Duponchelle <mathieu@centricular.com>",
+        );
+
+        let caps = gst::Caps::builder("text/x-raw").build();
+
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &caps,
+        )
+        .unwrap();
+        klass.add_pad_template(sink_pad_template);
+
+        let framerate = gst::FractionRange::new(
+            gst::Fraction::new(1, std::i32::MAX),
+            gst::Fraction::new(std::i32::MAX, 1),
+        );
+
+        let mut caps = gst::Caps::builder("closedcaption/x-cea-708")
+            .field("format", &"cc_data")
+            .field("framerate", &framerate)
+            .build();
+        caps.get_mut().unwrap().append_structure(
+            gst::Structure::builder("closedcaption/x-cea-708")
+                .field("format", &"cdp")
+                .field(
+                    "framerate",
+                    &gst::List::from_owned(
+                        cdp::CDP_FRAMERATES
+                            .iter()
+                            .map(|(fps_n, fps_d, _, _)| {
+                                gst::Fraction::new(*fps_n, *fps_d).to_send_value()
+                            })
+                            .collect(),
+                    ),
+                )
+                .build(),
+        );
+
+        let src_pad_template = gst::PadTemplate::new(
+            "src",
+            gst::PadDirection::Src,
+            gst::PadPresence::Always,
+            &caps,
+        )
+        .unwrap();
+        klass.add_pad_template(src_pad_template);
+
+        klass.install_properties(&PROPERTIES);
+    }
+}
+
+impl ObjectImpl for TtToCea708 {
+    fn constructed(&self, obj: &glib::Object) {
+        self.parent_constructed(obj);
+
+        let element = obj.downcast_ref::<gst::Element>().unwrap();
+        element.add_pad(&self.sinkpad).unwrap();
+        element.add_pad(&self.srcpad).unwrap();
+    }
+
+    fn set_property(&self, _obj: &glib::Object, id: usize, value: &glib::Value) {
+        let prop = &PROPERTIES[id];
+
+        match *prop {
+            subclass::Property("service-number", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.service_number = value.get_some::<u32>().expect("type checked upstream");
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: usize) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id];
+
+        match *prop {
+            subclass::Property("service-number", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.service_number.to_value())
+            }
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl for TtToCea708 {
+    fn change_state(
+        &self,
+        element: &gst::Element,
+        transition: gst::StateChange,
+    ) -> Result<gst::StateChangeSuccess, gst::StateChangeError> {
+        gst_trace!(CAT, obj: element, "Changing state {:?}", transition);
+
+        match transition {
+            gst::StateChange::ReadyToPaused => {
+                let mut state = self.state.lock().unwrap();
+                let settings = self.settings.lock().unwrap();
+                *state = State::default();
+                state.settings = settings.clone();
+            }
+            _ => (),
+        }
+
+        let ret = self.parent_change_state(element, transition)?;
+
+        match transition {
+            gst::StateChange::PausedToReady => {
+                let mut state = self.state.lock().unwrap();
+                *state = State::default();
+            }
+            _ => (),
+        }
+
+        Ok(ret)
+    }
+}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "tttocea708",
+        gst::Rank::None,
+        TtToCea708::get_type(),
+    )
+}
@@ -95,3 +95,46 @@ fn test_parse() {
             .build()
     );
 }
+
+/* Feed the output of tttocea608 back in, and check that we get the
+ * original text, including special and extended characters */
+#[test]
+fn test_round_trip() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=pop-on ! cea608tott");
+    h.set_src_caps_str("text/x-raw");
+    h.set_sink_caps_str("text/x-raw");
+
+    let captions: [(gst::ClockTime, &'static str, &'static str); 4] = [
+        (gst::SECOND, "Hello", "Hello"),
+        (3 * gst::SECOND, "Hello\nWorld", "Hello\r\nWorld"),
+        (
+            5 * gst::SECOND,
+            "Ça coûte 5¢, señor ♪",
+            "Ça coûte 5¢, señor ♪",
+        ),
+        (7 * gst::SECOND, "Grüße\nà bientôt", "Grüße\r\nà bientôt"),
+    ];
+
+    for (pts, text, _) in captions.iter() {
+        let mut buf = gst::Buffer::from_slice(*text);
+        {
+            let buf = buf.get_mut().unwrap();
+            buf.set_pts(*pts);
+            buf.set_duration(gst::SECOND);
+        }
+        assert_eq!(h.push(buf), Ok(gst::FlowSuccess::Ok));
+    }
+
+    h.push_event(gst::event::Eos::new());
+
+    for (i, (_, _, expected)) in captions.iter().enumerate() {
+        let buf = h.try_pull().unwrap();
+
+        let data = buf.map_readable().unwrap();
+        let s = std::str::from_utf8(&*data)
+            .unwrap_or_else(|_| panic!("Non-UTF8 data for {}th buffer", i + 1));
+        assert_eq!(*expected, s, "Unexpected data for {}th buffer", i + 1);
+    }
+}
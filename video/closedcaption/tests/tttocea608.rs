@@ -17,6 +17,7 @@
 
 #[macro_use]
 extern crate pretty_assertions;
+use gst::prelude::*;
 use gst::EventView;
 
 fn init() {
@@ -41,6 +42,19 @@ fn new_timed_buffer<T: AsRef<[u8]> + Send + 'static>(
     buf
 }
 
+/* Pulls all the byte pairs output so far as (PTS, first byte, second byte) */
+fn pull_cc_data(h: &mut gst_check::Harness) -> Vec<(gst::ClockTime, u8, u8)> {
+    let mut cc_data = vec![];
+
+    while h.buffers_in_queue() > 0 {
+        let outbuf = h.pull().unwrap();
+        let data = outbuf.map_readable().unwrap();
+        cc_data.push((outbuf.get_pts(), data[0], data[1]));
+    }
+
+    cc_data
+}
+
 #[test]
 fn test_non_timed_buffer() {
     init();
@@ -126,6 +140,41 @@ fn test_one_timed_buffer_and_eos() {
     assert_eq!(event.get_type(), gst::EventType::Eos);
 }
 
+/* Here we test the whole pop-on sequence at a framerate other than
+ * the default, with the end_of_caption on the frame of the PTS */
+#[test]
+fn test_pop_on_sequence_framerate() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=pop-on");
+    h.set_src_caps_str("text/x-raw");
+    h.set_sink_caps_str("closedcaption/x-cea-608, format=raw, framerate=(fraction)25/1");
+
+    while h.events_in_queue() != 0 {
+        let _event = h.pull_event().unwrap();
+    }
+
+    let inbuf = new_timed_buffer(&"Hello", gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    assert_eq!(
+        pull_cc_data(&mut h),
+        vec![
+            (640_000_000.into(), 0x94, 0x20),   /* resume_caption_loading */
+            (680_000_000.into(), 0x94, 0x20),   /* control doubled */
+            (720_000_000.into(), 0x94, 0xae),   /* erase_non_displayed_memory */
+            (760_000_000.into(), 0x94, 0xae),   /* control doubled */
+            (800_000_000.into(), 0x94, 0x40),   /* preamble */
+            (840_000_000.into(), 0x94, 0x40),   /* control doubled */
+            (880_000_000.into(), 0xc8, 0xe5),   /* H e */
+            (920_000_000.into(), 0xec, 0xec),   /* l l */
+            (960_000_000.into(), 0xef, 0x80),   /* o, nil */
+            (gst::SECOND, 0x94, 0x2f),          /* end_of_caption */
+            (1_040_000_000.into(), 0x94, 0x2f), /* control doubled */
+        ]
+    );
+}
+
 /* Here we test that the erase_display_memory control code
  * gets inserted at the correct moment, when there's enough
  * of an interval between two buffers
@@ -377,3 +426,3911 @@ fn test_one_timed_buffer_and_eos_roll_up2() {
     let event = h.pull_event().unwrap();
     assert_eq!(event.get_type(), gst::EventType::Eos);
 }
+
+/* Check that the preview pad outputs the text as decoded back from
+ * the emitted cc_data, so that lossy conversions become visible */
+#[test]
+fn test_preview_round_trip() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 name=enc mode=pop-on");
+    let bin = h.get_element().unwrap().downcast::<gst::Bin>().unwrap();
+    let element = bin.get_by_name("enc").unwrap();
+    let mut h_preview = gst_check::Harness::with_element(&element, None, Some("preview"));
+
+    h.set_src_caps_str("text/x-raw");
+
+    /* The snowman has no CEA-608 equivalent and gets replaced */
+    let inbuf = new_timed_buffer(&"Hello ☃", gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    /* The preview is timestamped with the running time like the cc_data */
+    let mut segments = 0;
+    while h_preview.events_in_queue() != 0 {
+        let event = h_preview.pull_event().unwrap();
+        if let EventView::Segment(e) = event.view() {
+            let segment = e.get_segment().downcast_ref::<gst::ClockTime>().unwrap();
+            assert_eq!(segment.get_start(), 0.into());
+            segments += 1;
+        }
+    }
+    assert_eq!(segments, 1);
+
+    let outbuf = h_preview.pull().unwrap();
+
+    /* Displayed on the first end_of_caption control code */
+    assert_eq!(outbuf.get_pts(), gst::SECOND);
+
+    let data = outbuf.map_readable().unwrap();
+    let text = std::str::from_utf8(&*data).unwrap();
+    assert_ne!(text, "Hello ☃");
+    assert_eq!(text, "Hello  ");
+}
+
+/* Check that the roll-up window is anchored on the configured base row,
+ * roll-up4 with base-row 5 occupying rows 2 to 5 */
+#[test]
+fn test_roll_up_base_row() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 name=enc mode=roll-up4 base-row=5");
+    h.set_src_caps_str("text/x-raw");
+
+    let bin = h.get_element().unwrap().downcast::<gst::Bin>().unwrap();
+    let element = bin.get_by_name("enc").unwrap();
+    assert_eq!(
+        element
+            .get_property("roll-up-window")
+            .unwrap()
+            .get_some::<u32>()
+            .unwrap(),
+        4
+    );
+
+    while h.events_in_queue() != 0 {
+        let _event = h.pull_event().unwrap();
+    }
+
+    let inbuf = new_timed_buffer(&"Hello", gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    let expected: [[u8; 2usize]; 6] = [
+        [0x94, 0x2c], /* erase_display_memory */
+        [0x94, 0x2c], /* control doubled */
+        [0x94, 0xa7], /* roll_up_4 */
+        [0x94, 0xa7], /* control doubled */
+        [0x15, 0xe0], /* preamble, row 5 */
+        [0x15, 0xe0], /* control doubled */
+    ];
+
+    for e in &expected {
+        let outbuf = h.try_pull().unwrap();
+        let data = outbuf.map_readable().unwrap();
+        assert_eq!(e, &*data);
+    }
+}
+
+/* Check that paint-on mode writes to displayed memory directly, starting
+ * at the PTS of the input, and erases the display once the caption expired */
+#[test]
+fn test_paint_on() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=paint-on");
+    h.set_src_caps_str("text/x-raw");
+
+    while h.events_in_queue() != 0 {
+        let _event = h.pull_event().unwrap();
+    }
+
+    let inbuf = new_timed_buffer(&"Hello", gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    let expected: [(gst::ClockTime, gst::ClockTime, [u8; 2usize]); 7] = [
+        (1_000_000_000.into(), 33_333_333.into(), [0x94, 0x29]), /* resume_direct_captioning */
+        (1_033_333_333.into(), 33_333_334.into(), [0x94, 0x29]), /* control doubled */
+        (1_066_666_667.into(), 33_333_333.into(), [0x94, 0x40]), /* preamble */
+        (1_100_000_000.into(), 33_333_333.into(), [0x94, 0x40]), /* control doubled */
+        (1_133_333_333.into(), 33_333_334.into(), [0xc8, 0xe5]), /* H e */
+        (1_166_666_667.into(), 33_333_333.into(), [0xec, 0xec]), /* l l */
+        (1_200_000_000.into(), 33_333_333.into(), [0xef, 0x80]), /* o, nil */
+    ];
+
+    for (i, e) in expected.iter().enumerate() {
+        let outbuf = h.try_pull().unwrap();
+
+        assert_eq!(
+            e.0,
+            outbuf.get_pts(),
+            "Unexpected PTS for {}th buffer",
+            i + 1
+        );
+        assert_eq!(
+            e.1,
+            outbuf.get_duration(),
+            "Unexpected duration for {}th buffer",
+            i + 1
+        );
+
+        let data = outbuf.map_readable().unwrap();
+        assert_eq!(e.2, &*data);
+    }
+
+    assert_eq!(h.buffers_in_queue(), 0);
+
+    let inbuf = new_timed_buffer(&"World", 3_000_000_000.into(), gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    let mut erase_display_buffers = 0;
+
+    while h.buffers_in_queue() > 0 {
+        let outbuf = h.pull().unwrap();
+        let data = outbuf.map_readable().unwrap();
+
+        /* No pop-on specific control codes */
+        assert_ne!(&*data, &[0x94, 0xae]);
+        assert_ne!(&*data, &[0x94, 0x2f]);
+
+        if *data == [0x94, 0x2c] {
+            assert!(
+                outbuf.get_pts() == 2_000_000_000.into()
+                    || outbuf.get_pts() == 2_033_333_333.into()
+            );
+            erase_display_buffers += 1;
+        }
+    }
+
+    assert_eq!(erase_display_buffers, 2);
+}
+
+/* Check that in paint-on mode, resume_direct_captioning is only sent
+ * once, and that gaps trigger the erasure of expired captions */
+#[test]
+fn test_paint_on_gaps() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=paint-on");
+    h.set_src_caps_str("text/x-raw");
+
+    while h.events_in_queue() != 0 {
+        let _event = h.pull_event().unwrap();
+    }
+
+    let inbuf = new_timed_buffer(&"Hello", gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    let mut resume_direct_captioning_buffers = 0;
+
+    while h.buffers_in_queue() > 0 {
+        let outbuf = h.pull().unwrap();
+        let data = outbuf.map_readable().unwrap();
+
+        if *data == [0x94, 0x29] {
+            resume_direct_captioning_buffers += 1;
+        }
+        assert_ne!(&*data, &[0x94, 0x2c]);
+    }
+
+    assert_eq!(resume_direct_captioning_buffers, 2);
+
+    let gap_event = gst::event::Gap::new(2 * gst::SECOND, gst::SECOND);
+    assert_eq!(h.push_event(gap_event), true);
+
+    assert_eq!(h.buffers_in_queue(), 2);
+    while h.buffers_in_queue() > 0 {
+        let outbuf = h.pull().unwrap();
+        let data = outbuf.map_readable().unwrap();
+        assert_eq!(&*data, &[0x94, 0x2c]);
+    }
+
+    let inbuf = new_timed_buffer(&"World", 3 * gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    /* Directly starts with the preamble */
+    let outbuf = h.pull().unwrap();
+    assert_eq!(outbuf.get_pts(), 3 * gst::SECOND);
+    let data = outbuf.map_readable().unwrap();
+    assert_eq!(&*data, &[0x94, 0x40]);
+}
+
+/* Check that captions that don't fit below the configured origin
+ * row get moved up */
+#[test]
+fn test_origin_row_overflow() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=pop-on origin-row=14");
+    h.set_src_caps_str("text/x-raw");
+
+    while h.events_in_queue() != 0 {
+        let _event = h.pull_event().unwrap();
+    }
+
+    let inbuf = new_timed_buffer(&"Hello\nWorld", gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    /* The caption gets moved up a row to fit */
+    let expected: [[u8; 2usize]; 16] = [
+        [0x94, 0x20], /* resume_caption_loading */
+        [0x94, 0x20], /* control doubled */
+        [0x94, 0xae], /* erase_non_displayed_memory */
+        [0x94, 0xae], /* control doubled */
+        [0x94, 0x40], /* preamble, row 13 */
+        [0x94, 0x40], /* control doubled */
+        [0xc8, 0xe5], /* H e */
+        [0xec, 0xec], /* l l */
+        [0xef, 0x80], /* o, nil */
+        [0x94, 0xe0], /* preamble, row 14 */
+        [0x94, 0xe0], /* control doubled */
+        [0x57, 0xef], /* W o */
+        [0xf2, 0xec], /* r l */
+        [0x64, 0x80], /* d, nil */
+        [0x94, 0x2f], /* end_of_caption */
+        [0x94, 0x2f], /* control doubled */
+    ];
+
+    for e in &expected {
+        let outbuf = h.try_pull().unwrap();
+        let data = outbuf.map_readable().unwrap();
+        assert_eq!(e, &*data);
+    }
+
+    assert_eq!(h.buffers_in_queue(), 0);
+}
+
+#[test]
+fn test_underline() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=pop-on");
+    h.set_src_caps_str("text/x-raw");
+
+    while h.events_in_queue() != 0 {
+        let _event = h.pull_event().unwrap();
+    }
+
+    let inbuf = new_timed_buffer(&"<u>hello</u>", gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    let expected: [[u8; 2usize]; 11] = [
+        [0x94, 0x20], /* resume_caption_loading */
+        [0x94, 0x20], /* control doubled */
+        [0x94, 0xae], /* erase_non_displayed_memory */
+        [0x94, 0xae], /* control doubled */
+        [0x94, 0xc1], /* preamble, row 13, underline */
+        [0x94, 0xc1], /* control doubled */
+        [0x68, 0xe5], /* h e */
+        [0xec, 0xec], /* l l */
+        [0xef, 0x80], /* o, nil */
+        [0x94, 0x2f], /* end_of_caption */
+        [0x94, 0x2f], /* control doubled */
+    ];
+
+    for e in &expected {
+        let outbuf = h.try_pull().unwrap();
+        let data = outbuf.map_readable().unwrap();
+        assert_eq!(e, &*data);
+    }
+
+    assert_eq!(h.buffers_in_queue(), 0);
+
+    /* Underlining in the middle of a row goes through a mid-row code */
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=pop-on");
+    h.set_src_caps_str("text/x-raw");
+
+    while h.events_in_queue() != 0 {
+        let _event = h.pull_event().unwrap();
+    }
+
+    let inbuf = new_timed_buffer(&"Hello <u>World</u>", gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    let expected: [[u8; 2usize]; 16] = [
+        [0x94, 0x20], /* resume_caption_loading */
+        [0x94, 0x20], /* control doubled */
+        [0x94, 0xae], /* erase_non_displayed_memory */
+        [0x94, 0xae], /* control doubled */
+        [0x94, 0x40], /* preamble, row 13 */
+        [0x94, 0x40], /* control doubled */
+        [0xc8, 0xe5], /* H e */
+        [0xec, 0xec], /* l l */
+        [0xef, 0x20], /* o, space */
+        [0x91, 0xa1], /* mid-row, underline */
+        [0x91, 0xa1], /* control doubled */
+        [0x57, 0xef], /* W o */
+        [0xf2, 0xec], /* r l */
+        [0x64, 0x80], /* d, nil */
+        [0x94, 0x2f], /* end_of_caption */
+        [0x94, 0x2f], /* control doubled */
+    ];
+
+    for e in &expected {
+        let outbuf = h.try_pull().unwrap();
+        let data = outbuf.map_readable().unwrap();
+        assert_eq!(e, &*data);
+    }
+}
+
+/* Here we test that word-timed text is painted on at the specified
+ * offsets from the start of the caption */
+#[test]
+fn test_paint_on_word_timing() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=paint-on");
+    h.set_src_caps_str("text/x-raw");
+
+    while h.events_in_queue() != 0 {
+        let _event = h.pull_event().unwrap();
+    }
+
+    let inbuf = new_timed_buffer(
+        &"<t ms=\"0\">Hello</t> <t ms=\"500\">World</t>",
+        gst::SECOND,
+        2 * gst::SECOND,
+    );
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    let mut hello_pts = gst::CLOCK_TIME_NONE;
+    let mut world_pts = gst::CLOCK_TIME_NONE;
+    let mut prev_pts: gst::ClockTime = 0.into();
+
+    while h.buffers_in_queue() > 0 {
+        let outbuf = h.pull().unwrap();
+
+        /* Check that our timestamps are strictly ascending */
+        assert!(outbuf.get_pts() > prev_pts);
+        prev_pts = outbuf.get_pts();
+
+        let data = outbuf.map_readable().unwrap();
+        if &*data == [0xc8, 0xe5] {
+            hello_pts = outbuf.get_pts();
+        } else if &*data == [0x57, 0xef] {
+            world_pts = outbuf.get_pts();
+        }
+    }
+
+    assert!(hello_pts >= gst::SECOND);
+    assert!(hello_pts < 1_500_000_000.into());
+    assert_eq!(world_pts, 1_500_000_000.into());
+}
+
+/* Here we test that captions that can't be read in time get split
+ * into multiple screens when requested */
+#[test]
+fn test_reading_speed_split() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse(
+        "tttocea608 mode=pop-on reading-speed=10 reading-speed-action=split",
+    );
+    h.set_src_caps_str("text/x-raw");
+
+    while h.events_in_queue() != 0 {
+        let _event = h.pull_event().unwrap();
+    }
+
+    /* 39 characters need 3.9 seconds, we only have 2 */
+    let inbuf = new_timed_buffer(
+        &"one two three four five six seven eight",
+        gst::SECOND,
+        2 * gst::SECOND,
+    );
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    let mut end_of_caption_pts = vec![];
+
+    while h.buffers_in_queue() > 0 {
+        let outbuf = h.pull().unwrap();
+        let data = outbuf.map_readable().unwrap();
+
+        if *data == [0x94, 0x2f] {
+            end_of_caption_pts.push(outbuf.get_pts());
+        }
+    }
+
+    assert_eq!(
+        end_of_caption_pts,
+        vec![
+            gst::SECOND,
+            1_033_333_333.into(),
+            2 * gst::SECOND,
+            2_033_333_333.into()
+        ]
+    );
+}
+
+/* Here we test that captions with more lines than max-rows are
+ * split into successive captions sharing their duration */
+#[test]
+fn test_paginate() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=pop-on max-rows=2 paginate=true");
+    h.set_src_caps_str("text/x-raw");
+
+    while h.events_in_queue() != 0 {
+        let _event = h.pull_event().unwrap();
+    }
+
+    let inbuf = new_timed_buffer(&"one\ntwo\nthree\nfour\nfive", gst::SECOND, 3 * gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    let mut end_of_caption_pts = vec![];
+    let mut caption_rows = vec![];
+    let mut rows = 0;
+    let mut prev_data = vec![];
+
+    while h.buffers_in_queue() > 0 {
+        let outbuf = h.pull().unwrap();
+        let data = outbuf.map_readable().unwrap();
+
+        /* Control codes are doubled */
+        if *data != *prev_data {
+            if *data == [0x94, 0x40] || *data == [0x94, 0xe0] {
+                rows += 1;
+            } else if *data == [0x94, 0x2f] {
+                end_of_caption_pts.push(outbuf.get_pts());
+                caption_rows.push(rows);
+                rows = 0;
+            }
+        }
+
+        prev_data = data.to_vec();
+    }
+
+    assert_eq!(
+        end_of_caption_pts,
+        vec![gst::SECOND, 2 * gst::SECOND, 3 * gst::SECOND]
+    );
+    assert_eq!(caption_rows, vec![2, 2, 1]);
+}
+
+/* Here we test that captions arriving before the previous one are
+ * either dropped or reordered */
+#[test]
+fn test_out_of_order() {
+    init();
+
+    for (out_of_order, expected) in &[
+        ("drop", vec![2 * gst::SECOND]),
+        ("reorder", vec![gst::SECOND, 2 * gst::SECOND]),
+    ] {
+        let mut h = gst_check::Harness::new_parse(&format!(
+            "tttocea608 mode=pop-on out-of-order={}",
+            out_of_order
+        ));
+        h.set_src_caps_str("text/x-raw");
+
+        while h.events_in_queue() != 0 {
+            let _event = h.pull_event().unwrap();
+        }
+
+        let inbuf = new_timed_buffer(&"Second", 2 * gst::SECOND, 500 * gst::MSECOND);
+        assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+        let inbuf = new_timed_buffer(&"First", gst::SECOND, 500 * gst::MSECOND);
+        assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+        h.push_event(gst::event::Eos::new());
+
+        let mut end_of_caption_pts = vec![];
+        let mut prev_data = vec![];
+
+        while h.buffers_in_queue() > 0 {
+            let outbuf = h.pull().unwrap();
+            let data = outbuf.map_readable().unwrap();
+
+            /* Control codes are doubled */
+            if *data == [0x94, 0x2f] && *data != *prev_data {
+                end_of_caption_pts.push(outbuf.get_pts());
+            }
+
+            prev_data = data.to_vec();
+        }
+
+        assert_eq!(&end_of_caption_pts, expected);
+    }
+}
+
+#[test]
+fn test_origin_column() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=pop-on origin-column=8");
+    h.set_src_caps_str("text/x-raw");
+
+    while h.events_in_queue() != 0 {
+        let _event = h.pull_event().unwrap();
+    }
+
+    let inbuf = new_timed_buffer(&"Hello", gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    let expected: [[u8; 2usize]; 11] = [
+        [0x94, 0x20], /* resume_caption_loading */
+        [0x94, 0x20], /* control doubled */
+        [0x94, 0xae], /* erase_non_displayed_memory */
+        [0x94, 0xae], /* control doubled */
+        [0x94, 0x54], /* preamble, row 13, indent 8 */
+        [0x94, 0x54], /* control doubled */
+        [0xc8, 0xe5], /* H e */
+        [0xec, 0xec], /* l l */
+        [0xef, 0x80], /* o, nil */
+        [0x94, 0x2f], /* end_of_caption */
+        [0x94, 0x2f], /* control doubled */
+    ];
+
+    for e in &expected {
+        let outbuf = h.try_pull().unwrap();
+        let data = outbuf.map_readable().unwrap();
+        assert_eq!(e, &*data);
+    }
+
+    assert_eq!(h.buffers_in_queue(), 0);
+}
+
+/* Here we test that invalid UTF-8 is handled like other characters that
+ * can't be represented by default, and is an error without lossy-decode */
+#[test]
+fn test_lossy_decode() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=pop-on lossy-decode=false");
+    h.set_src_caps_str("text/x-raw");
+
+    let inbuf = new_timed_buffer(&b"Hi\xffthere"[..], gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Err(gst::FlowError::Error));
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=pop-on");
+    h.set_src_caps_str("text/x-raw");
+
+    while h.events_in_queue() != 0 {
+        let _event = h.pull_event().unwrap();
+    }
+
+    /* 0xc3 starts a two-byte sequence, but isn't followed by a
+     * continuation byte */
+    let inbuf = new_timed_buffer(&b"Hi\xc3there"[..], gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    let expected: [[u8; 2usize]; 12] = [
+        [0x94, 0x20], /* resume_caption_loading */
+        [0x94, 0x20], /* control doubled */
+        [0x94, 0xae], /* erase_non_displayed_memory */
+        [0x94, 0xae], /* control doubled */
+        [0x94, 0x40], /* preamble, row 13 */
+        [0x94, 0x40], /* control doubled */
+        [0xc8, 0xe9], /* H i */
+        [0x20, 0xf4], /* SPACE t */
+        [0x68, 0xe5], /* h e */
+        [0xf2, 0xe5], /* r e */
+        [0x94, 0x2f], /* end_of_caption */
+        [0x94, 0x2f], /* control doubled */
+    ];
+
+    for e in &expected {
+        let outbuf = h.try_pull().unwrap();
+        let data = outbuf.map_readable().unwrap();
+        assert_eq!(e, &*data);
+    }
+
+    assert_eq!(h.buffers_in_queue(), 0);
+}
+
+fn assert_pop_on_cc_data<T: AsRef<[u8]> + Send + 'static>(
+    properties: &str,
+    text: T,
+    expected: &[[u8; 2]],
+) {
+    let mut h = gst_check::Harness::new_parse(&format!("tttocea608 mode=pop-on {}", properties));
+    h.set_src_caps_str("text/x-raw");
+
+    while h.events_in_queue() != 0 {
+        let _event = h.pull_event().unwrap();
+    }
+
+    let inbuf = new_timed_buffer(text, gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    for e in expected {
+        let outbuf = h.try_pull().unwrap();
+        let data = outbuf.map_readable().unwrap();
+        assert_eq!(e, &*data);
+    }
+
+    assert_eq!(h.buffers_in_queue(), 0);
+}
+
+#[test]
+fn test_italics() {
+    init();
+
+    /* Italics at the start of a row are set by the preamble */
+    assert_pop_on_cc_data(
+        "",
+        "<i>Hello</i>",
+        &[
+            [0x94, 0x20], /* resume_caption_loading */
+            [0x94, 0x20], /* control doubled */
+            [0x94, 0xae], /* erase_non_displayed_memory */
+            [0x94, 0xae], /* control doubled */
+            [0x94, 0xce], /* preamble, row 13, italics */
+            [0x94, 0xce], /* control doubled */
+            [0xc8, 0xe5], /* H e */
+            [0xec, 0xec], /* l l */
+            [0xef, 0x80], /* o, nil */
+            [0x94, 0x2f], /* end_of_caption */
+            [0x94, 0x2f], /* control doubled */
+        ],
+    );
+
+    /* In the middle of a row, a mid-row code is needed */
+    assert_pop_on_cc_data(
+        "",
+        "Hello <i>World</i>",
+        &[
+            [0x94, 0x20], /* resume_caption_loading */
+            [0x94, 0x20], /* control doubled */
+            [0x94, 0xae], /* erase_non_displayed_memory */
+            [0x94, 0xae], /* control doubled */
+            [0x94, 0x40], /* preamble, row 13 */
+            [0x94, 0x40], /* control doubled */
+            [0xc8, 0xe5], /* H e */
+            [0xec, 0xec], /* l l */
+            [0xef, 0x20], /* o, space */
+            [0x91, 0xae], /* mid-row, italics */
+            [0x91, 0xae], /* control doubled */
+            [0x57, 0xef], /* W o */
+            [0xf2, 0xec], /* r l */
+            [0x64, 0x80], /* d, nil */
+            [0x94, 0x2f], /* end_of_caption */
+            [0x94, 0x2f], /* control doubled */
+        ],
+    );
+
+    /* Tags are encoded as is without markup */
+    assert_pop_on_cc_data(
+        "enable-markup=false",
+        "<i>Hi",
+        &[
+            [0x94, 0x20], /* resume_caption_loading */
+            [0x94, 0x20], /* control doubled */
+            [0x94, 0xae], /* erase_non_displayed_memory */
+            [0x94, 0xae], /* control doubled */
+            [0x94, 0x40], /* preamble, row 13 */
+            [0x94, 0x40], /* control doubled */
+            [0xbc, 0xe9], /* < i */
+            [0x3e, 0xc8], /* > H */
+            [0xe9, 0x80], /* i, nil */
+            [0x94, 0x2f], /* end_of_caption */
+            [0x94, 0x2f], /* control doubled */
+        ],
+    );
+}
+
+/* Here we test that bold tags, which CEA-608 can't express, are
+ * dropped while the tags they enclose are still interpreted */
+#[test]
+fn test_bold() {
+    init();
+
+    assert_pop_on_cc_data(
+        "",
+        "<b><i>Hi</i></b>",
+        &[
+            [0x94, 0x20], /* resume_caption_loading */
+            [0x94, 0x20], /* control doubled */
+            [0x94, 0xae], /* erase_non_displayed_memory */
+            [0x94, 0xae], /* control doubled */
+            [0x94, 0xce], /* preamble, row 13, italics */
+            [0x94, 0xce], /* control doubled */
+            [0xc8, 0xe9], /* H i */
+            [0x94, 0x2f], /* end_of_caption */
+            [0x94, 0x2f], /* control doubled */
+        ],
+    );
+}
+
+#[test]
+fn test_italics_restore() {
+    init();
+
+    /* The mid-row code restoring plain text takes the place of the space */
+    assert_pop_on_cc_data(
+        "",
+        "<i>Hello</i> World",
+        &[
+            [0x94, 0x20], /* resume_caption_loading */
+            [0x94, 0x20], /* control doubled */
+            [0x94, 0xae], /* erase_non_displayed_memory */
+            [0x94, 0xae], /* control doubled */
+            [0x94, 0xce], /* preamble, row 13, italics */
+            [0x94, 0xce], /* control doubled */
+            [0xc8, 0xe5], /* H e */
+            [0xec, 0xec], /* l l */
+            [0xef, 0x80], /* o, nil */
+            [0x91, 0x20], /* mid-row, white */
+            [0x91, 0x20], /* control doubled */
+            [0x57, 0xef], /* W o */
+            [0xf2, 0xec], /* r l */
+            [0x64, 0x80], /* d, nil */
+            [0x94, 0x2f], /* end_of_caption */
+            [0x94, 0x2f], /* control doubled */
+        ],
+    );
+
+    assert_pop_on_cc_data(
+        "italics=true",
+        "Hi",
+        &[
+            [0x94, 0x20], /* resume_caption_loading */
+            [0x94, 0x20], /* control doubled */
+            [0x94, 0xae], /* erase_non_displayed_memory */
+            [0x94, 0xae], /* control doubled */
+            [0x94, 0xce], /* preamble, row 13, italics */
+            [0x94, 0xce], /* control doubled */
+            [0xc8, 0xe9], /* H i */
+            [0x94, 0x2f], /* end_of_caption */
+            [0x94, 0x2f], /* control doubled */
+        ],
+    );
+}
+
+#[test]
+fn test_colors() {
+    init();
+
+    assert_pop_on_cc_data(
+        "",
+        "Hello <span foreground=\"red\">World</span>",
+        &[
+            [0x94, 0x20], /* resume_caption_loading */
+            [0x94, 0x20], /* control doubled */
+            [0x94, 0xae], /* erase_non_displayed_memory */
+            [0x94, 0xae], /* control doubled */
+            [0x94, 0x40], /* preamble, row 13 */
+            [0x94, 0x40], /* control doubled */
+            [0xc8, 0xe5], /* H e */
+            [0xec, 0xec], /* l l */
+            [0xef, 0x20], /* o, space */
+            [0x91, 0xa8], /* mid-row, red */
+            [0x91, 0xa8], /* control doubled */
+            [0x57, 0xef], /* W o */
+            [0xf2, 0xec], /* r l */
+            [0x64, 0x80], /* d, nil */
+            [0x94, 0x2f], /* end_of_caption */
+            [0x94, 0x2f], /* control doubled */
+        ],
+    );
+
+    /* At the start of a row, the color is set by the preamble */
+    assert_pop_on_cc_data(
+        "",
+        "<span foreground=\"#00ff00\">Hi</span>",
+        &[
+            [0x94, 0x20], /* resume_caption_loading */
+            [0x94, 0x20], /* control doubled */
+            [0x94, 0xae], /* erase_non_displayed_memory */
+            [0x94, 0xae], /* control doubled */
+            [0x94, 0xc2], /* preamble, row 13, green */
+            [0x94, 0xc2], /* control doubled */
+            [0xc8, 0xe9], /* H i */
+            [0x94, 0x2f], /* end_of_caption */
+            [0x94, 0x2f], /* control doubled */
+        ],
+    );
+}
+
+/* Here we test that the certification pattern is output regardless of
+ * the input text */
+#[test]
+fn test_cert_pattern() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=cert-pattern");
+    h.set_src_caps_str("text/x-raw");
+
+    while h.events_in_queue() != 0 {
+        let _event = h.pull_event().unwrap();
+    }
+
+    let inbuf = new_timed_buffer(&"Ignored", gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    assert_eq!(h.buffers_in_queue(), 294);
+
+    let expected: [[u8; 2usize]; 20] = [
+        [0x94, 0x20], /* resume_caption_loading */
+        [0x94, 0x20], /* control doubled */
+        [0x94, 0xa1], /* backspace */
+        [0x94, 0xa1], /* control doubled */
+        [0x94, 0xa2], /* alarm_off */
+        [0x94, 0xa2], /* control doubled */
+        [0x94, 0x23], /* alarm_on */
+        [0x94, 0x23], /* control doubled */
+        [0x94, 0xa4], /* delete_to_end_of_row */
+        [0x94, 0xa4], /* control doubled */
+        [0x94, 0x25], /* roll_up_2 */
+        [0x94, 0x25], /* control doubled */
+        [0x94, 0x26], /* roll_up_3 */
+        [0x94, 0x26], /* control doubled */
+        [0x94, 0xa7], /* roll_up_4 */
+        [0x94, 0xa7], /* control doubled */
+        [0x94, 0xa8], /* flash_on */
+        [0x94, 0xa8], /* control doubled */
+        [0x94, 0x29], /* resume_direct_captioning */
+        [0x94, 0x29], /* control doubled */
+    ];
+
+    for (i, e) in expected.iter().enumerate() {
+        let outbuf = h.pull().unwrap();
+
+        if i == 0 {
+            assert_eq!(outbuf.get_pts(), gst::SECOND);
+        }
+
+        let data = outbuf.map_readable().unwrap();
+        assert_eq!(e, &*data);
+    }
+}
+
+#[test]
+fn test_origin_row() {
+    init();
+
+    assert_pop_on_cc_data(
+        "origin-row=2",
+        "Hi",
+        &[
+            [0x94, 0x20], /* resume_caption_loading */
+            [0x94, 0x20], /* control doubled */
+            [0x94, 0xae], /* erase_non_displayed_memory */
+            [0x94, 0xae], /* control doubled */
+            [0x92, 0x40], /* preamble, row 2 */
+            [0x92, 0x40], /* control doubled */
+            [0xc8, 0xe9], /* H i */
+            [0x94, 0x2f], /* end_of_caption */
+            [0x94, 0x2f], /* control doubled */
+        ],
+    );
+}
+
+/* Here we test that ts-offset, lead-time, min-duration and pre-clear-gap
+ * are applied in that order to the display window of a caption */
+#[test]
+fn test_schedule() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse(
+        "tttocea608 mode=pop-on ts-offset=500000000 lead-time=200000000 \
+         min-duration=2000000000 pre-clear-gap=300000000",
+    );
+    h.set_src_caps_str("text/x-raw");
+
+    while h.events_in_queue() != 0 {
+        let _event = h.pull_event().unwrap();
+    }
+
+    /* 1.0 - 1.5 becomes 1.5 - 2.0, 1.3 - 2.0, 1.3 - 3.3 then 1.3 - 3.0 */
+    let inbuf = new_timed_buffer(&"Hello", gst::SECOND, 500_000_000.into());
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+    h.push_event(gst::event::Eos::new());
+
+    let mut end_of_caption_pts = vec![];
+    let mut erase_display_pts = vec![];
+    let mut prev_pts: gst::ClockTime = 0.into();
+
+    while h.buffers_in_queue() > 0 {
+        let outbuf = h.pull().unwrap();
+
+        assert!(outbuf.get_pts() > prev_pts);
+        prev_pts = outbuf.get_pts();
+
+        let data = outbuf.map_readable().unwrap();
+        if *data == [0x94, 0x2f] {
+            end_of_caption_pts.push(outbuf.get_pts());
+        } else if *data == [0x94, 0x2c] {
+            erase_display_pts.push(outbuf.get_pts());
+        }
+    }
+
+    assert_eq!(
+        end_of_caption_pts,
+        vec![gst::ClockTime::from(1_300_000_000), 1_333_333_333.into()]
+    );
+    assert_eq!(
+        erase_display_pts,
+        vec![gst::ClockTime::from(3_000_000_000), 3_033_333_333.into()]
+    );
+}
+
+#[test]
+fn test_underline_property() {
+    init();
+
+    /* Underlining can be ended with markup */
+    assert_pop_on_cc_data(
+        "underline=true",
+        "Hi</u> you",
+        &[
+            [0x94, 0x20], /* resume_caption_loading */
+            [0x94, 0x20], /* control doubled */
+            [0x94, 0xae], /* erase_non_displayed_memory */
+            [0x94, 0xae], /* control doubled */
+            [0x94, 0xc1], /* preamble, row 13, underline */
+            [0x94, 0xc1], /* control doubled */
+            [0xc8, 0xe9], /* H i */
+            [0x91, 0x20], /* mid-row, white */
+            [0x91, 0x20], /* control doubled */
+            [0x79, 0xef], /* y o */
+            [0x75, 0x80], /* u, nil */
+            [0x94, 0x2f], /* end_of_caption */
+            [0x94, 0x2f], /* control doubled */
+        ],
+    );
+}
+
+#[test]
+fn test_origin_column_padding() {
+    init();
+
+    /* Columns that aren't a multiple of 4 are reached with a tab offset */
+    assert_pop_on_cc_data(
+        "origin-column=10",
+        "Hello",
+        &[
+            [0x94, 0x20], /* resume_caption_loading */
+            [0x94, 0x20], /* control doubled */
+            [0x94, 0xae], /* erase_non_displayed_memory */
+            [0x94, 0xae], /* control doubled */
+            [0x94, 0x54], /* preamble, row 13, indent 8 */
+            [0x94, 0x54], /* control doubled */
+            [0x97, 0xa2], /* tab_offset_2 */
+            [0x97, 0xa2], /* control doubled */
+            [0xc8, 0xe5], /* H e */
+            [0xec, 0xec], /* l l */
+            [0xef, 0x80], /* o, nil */
+            [0x94, 0x2f], /* end_of_caption */
+            [0x94, 0x2f], /* control doubled */
+        ],
+    );
+}
+
+/* Here we test that trailing whitespace doesn't get encoded, unless
+ * trim-trailing is disabled */
+#[test]
+fn test_trim_trailing() {
+    init();
+
+    assert_pop_on_cc_data(
+        "",
+        "hi   \n",
+        &[
+            [0x94, 0x20], /* resume_caption_loading */
+            [0x94, 0x20], /* control doubled */
+            [0x94, 0xae], /* erase_non_displayed_memory */
+            [0x94, 0xae], /* control doubled */
+            [0x94, 0x40], /* preamble, row 13 */
+            [0x94, 0x40], /* control doubled */
+            [0x68, 0xe9], /* h i */
+            [0x94, 0x2f], /* end_of_caption */
+            [0x94, 0x2f], /* control doubled */
+        ],
+    );
+
+    /* Trailing spaces don't push the next line along */
+    assert_pop_on_cc_data(
+        "",
+        "hello   \nworld",
+        &[
+            [0x94, 0x20], /* resume_caption_loading */
+            [0x94, 0x20], /* control doubled */
+            [0x94, 0xae], /* erase_non_displayed_memory */
+            [0x94, 0xae], /* control doubled */
+            [0x94, 0x40], /* preamble, row 13 */
+            [0x94, 0x40], /* control doubled */
+            [0x68, 0xe5], /* h e */
+            [0xec, 0xec], /* l l */
+            [0xef, 0x80], /* o, nil */
+            [0x94, 0xe0], /* preamble, row 14 */
+            [0x94, 0xe0], /* control doubled */
+            [0xf7, 0xef], /* w o */
+            [0xf2, 0xec], /* r l */
+            [0x64, 0x80], /* d, nil */
+            [0x94, 0x2f], /* end_of_caption */
+            [0x94, 0x2f], /* control doubled */
+        ],
+    );
+
+    assert_pop_on_cc_data(
+        "trim-trailing=false",
+        "hi  ",
+        &[
+            [0x94, 0x20], /* resume_caption_loading */
+            [0x94, 0x20], /* control doubled */
+            [0x94, 0xae], /* erase_non_displayed_memory */
+            [0x94, 0xae], /* control doubled */
+            [0x94, 0x40], /* preamble, row 13 */
+            [0x94, 0x40], /* control doubled */
+            [0x68, 0xe9], /* h i */
+            [0x20, 0x20], /* space, space */
+            [0x94, 0x2f], /* end_of_caption */
+            [0x94, 0x2f], /* control doubled */
+        ],
+    );
+}
+
+/* Here we test that leading spaces are turned into an indent instead
+ * of being encoded */
+#[test]
+fn test_leading_spaces() {
+    init();
+
+    assert_pop_on_cc_data(
+        "",
+        "    indented",
+        &[
+            [0x94, 0x20], /* resume_caption_loading */
+            [0x94, 0x20], /* control doubled */
+            [0x94, 0xae], /* erase_non_displayed_memory */
+            [0x94, 0xae], /* control doubled */
+            [0x94, 0x52], /* preamble, row 13, indent 4 */
+            [0x94, 0x52], /* control doubled */
+            [0xe9, 0x6e], /* i n */
+            [0x64, 0xe5], /* d e */
+            [0x6e, 0xf4], /* n t */
+            [0xe5, 0x64], /* e d */
+            [0x94, 0x2f], /* end_of_caption */
+            [0x94, 0x2f], /* control doubled */
+        ],
+    );
+
+    /* Each line has its own indent */
+    assert_pop_on_cc_data(
+        "",
+        "Hi\n      there",
+        &[
+            [0x94, 0x20], /* resume_caption_loading */
+            [0x94, 0x20], /* control doubled */
+            [0x94, 0xae], /* erase_non_displayed_memory */
+            [0x94, 0xae], /* control doubled */
+            [0x94, 0x40], /* preamble, row 13 */
+            [0x94, 0x40], /* control doubled */
+            [0xc8, 0xe9], /* H i */
+            [0x94, 0xf2], /* preamble, row 14, indent 4 */
+            [0x94, 0xf2], /* control doubled */
+            [0x97, 0xa2], /* tab_offset_2 */
+            [0x97, 0xa2], /* control doubled */
+            [0xf4, 0x68], /* t h */
+            [0xe5, 0xf2], /* e r */
+            [0xe5, 0x80], /* e, nil */
+            [0x94, 0x2f], /* end_of_caption */
+            [0x94, 0x2f], /* control doubled */
+        ],
+    );
+}
+
+/* Here we test that runs of spaces are encoded as is, unless
+ * collapse-spaces is enabled */
+#[test]
+fn test_collapse_spaces() {
+    init();
+
+    assert_pop_on_cc_data(
+        "",
+        "a    b",
+        &[
+            [0x94, 0x20], /* resume_caption_loading */
+            [0x94, 0x20], /* control doubled */
+            [0x94, 0xae], /* erase_non_displayed_memory */
+            [0x94, 0xae], /* control doubled */
+            [0x94, 0x40], /* preamble, row 13 */
+            [0x94, 0x40], /* control doubled */
+            [0x61, 0x20], /* a space */
+            [0x20, 0x20], /* space, space */
+            [0x20, 0x62], /* space b */
+            [0x94, 0x2f], /* end_of_caption */
+            [0x94, 0x2f], /* control doubled */
+        ],
+    );
+
+    assert_pop_on_cc_data(
+        "collapse-spaces=true",
+        "a    b",
+        &[
+            [0x94, 0x20], /* resume_caption_loading */
+            [0x94, 0x20], /* control doubled */
+            [0x94, 0xae], /* erase_non_displayed_memory */
+            [0x94, 0xae], /* control doubled */
+            [0x94, 0x40], /* preamble, row 13 */
+            [0x94, 0x40], /* control doubled */
+            [0x61, 0x20], /* a space */
+            [0x62, 0x80], /* b, nil */
+            [0x94, 0x2f], /* end_of_caption */
+            [0x94, 0x2f], /* control doubled */
+        ],
+    );
+}
+
+#[test]
+fn test_color_property() {
+    init();
+
+    assert_pop_on_cc_data(
+        "color=cyan",
+        "Hi",
+        &[
+            [0x94, 0x20], /* resume_caption_loading */
+            [0x94, 0x20], /* control doubled */
+            [0x94, 0xae], /* erase_non_displayed_memory */
+            [0x94, 0xae], /* control doubled */
+            [0x94, 0x46], /* preamble, row 13, cyan */
+            [0x94, 0x46], /* control doubled */
+            [0xc8, 0xe9], /* H i */
+            [0x94, 0x2f], /* end_of_caption */
+            [0x94, 0x2f], /* control doubled */
+        ],
+    );
+
+    /* Indent preambles are always white, a mid-row code sets the color */
+    assert_pop_on_cc_data(
+        "color=red origin-column=4",
+        "Hi",
+        &[
+            [0x94, 0x20], /* resume_caption_loading */
+            [0x94, 0x20], /* control doubled */
+            [0x94, 0xae], /* erase_non_displayed_memory */
+            [0x94, 0xae], /* control doubled */
+            [0x94, 0x52], /* preamble, row 13, indent 4 */
+            [0x94, 0x52], /* control doubled */
+            [0x91, 0xa8], /* mid-row, red */
+            [0x91, 0xa8], /* control doubled */
+            [0xc8, 0xe9], /* H i */
+            [0x94, 0x2f], /* end_of_caption */
+            [0x94, 0x2f], /* control doubled */
+        ],
+    );
+}
+
+/* Here we test that only captions within the window are output, and
+ * that the display is erased at the end of the window */
+#[test]
+fn test_window() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse(
+        "tttocea608 mode=pop-on window-start=2000000000 window-stop=4000000000",
+    );
+    h.set_src_caps_str("text/x-raw");
+
+    while h.events_in_queue() != 0 {
+        let _event = h.pull_event().unwrap();
+    }
+
+    let inbuf = new_timed_buffer(&"A", gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+    let inbuf = new_timed_buffer(&"B", 3 * gst::SECOND, 2 * gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+    let inbuf = new_timed_buffer(&"C", 5 * gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+    h.push_event(gst::event::Eos::new());
+
+    let mut end_of_caption_pts = vec![];
+    let mut erase_display_pts = vec![];
+
+    while h.buffers_in_queue() > 0 {
+        let outbuf = h.pull().unwrap();
+        let data = outbuf.map_readable().unwrap();
+
+        assert_ne!(*data, [0xc1, 0x80]);
+        assert_ne!(*data, [0x43, 0x80]);
+
+        if *data == [0x94, 0x2f] {
+            end_of_caption_pts.push(outbuf.get_pts());
+        } else if *data == [0x94, 0x2c] {
+            erase_display_pts.push(outbuf.get_pts());
+        }
+    }
+
+    assert_eq!(
+        end_of_caption_pts,
+        vec![3 * gst::SECOND, 3_033_333_333.into()]
+    );
+    assert_eq!(
+        erase_display_pts,
+        vec![4 * gst::SECOND, 4_033_333_333.into()]
+    );
+}
+
+/* Here we test that a short caption is displayed along with the
+ * following one */
+#[test]
+fn test_merge_short() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=pop-on merge-short=500000000");
+    h.set_src_caps_str("text/x-raw");
+
+    while h.events_in_queue() != 0 {
+        let _event = h.pull_event().unwrap();
+    }
+
+    let inbuf = new_timed_buffer(&"Hi", gst::SECOND, 100_000_000.into());
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+    assert_eq!(h.buffers_in_queue(), 0);
+
+    let inbuf = new_timed_buffer(&"you", 1_200_000_000.into(), gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    let expected: [[u8; 2usize]; 13] = [
+        [0x94, 0x20], /* resume_caption_loading */
+        [0x94, 0x20], /* control doubled */
+        [0x94, 0xae], /* erase_non_displayed_memory */
+        [0x94, 0xae], /* control doubled */
+        [0x94, 0x40], /* preamble, row 13 */
+        [0x94, 0x40], /* control doubled */
+        [0xc8, 0xe9], /* H i */
+        [0x94, 0xe0], /* preamble, row 14 */
+        [0x94, 0xe0], /* control doubled */
+        [0x79, 0xef], /* y o */
+        [0x75, 0x80], /* u, nil */
+        [0x94, 0x2f], /* end_of_caption */
+        [0x94, 0x2f], /* control doubled */
+    ];
+
+    for e in &expected {
+        let outbuf = h.try_pull().unwrap();
+        let data = outbuf.map_readable().unwrap();
+        assert_eq!(e, &*data);
+
+        /* Displayed at the time of the short caption */
+        if *data == [0x94, 0x2f] {
+            assert!(outbuf.get_pts() <= 1_033_333_333.into());
+        }
+    }
+
+    assert_eq!(h.buffers_in_queue(), 0);
+}
+
+/* Here we test that the data channel bit is set on control codes,
+ * preambles, mid-row codes and special characters, and that the field
+ * bit is only set on control codes */
+#[test]
+fn test_channel() {
+    init();
+
+    assert_pop_on_cc_data(
+        "channel=2",
+        "♪",
+        &[
+            [0x1c, 0x20], /* resume_caption_loading */
+            [0x1c, 0x20], /* control doubled */
+            [0x1c, 0xae], /* erase_non_displayed_memory */
+            [0x1c, 0xae], /* control doubled */
+            [0x1c, 0x40], /* preamble, row 13 */
+            [0x1c, 0x40], /* control doubled */
+            [0x19, 0x37], /* ♪ */
+            [0x1c, 0x20], /* resume_caption_loading */
+            [0x1c, 0x20], /* control doubled */
+            [0x1c, 0x2f], /* end_of_caption */
+            [0x1c, 0x2f], /* control doubled */
+        ],
+    );
+
+    assert_pop_on_cc_data(
+        "channel=3",
+        "Hi",
+        &[
+            [0x15, 0x20], /* resume_caption_loading */
+            [0x15, 0x20], /* control doubled */
+            [0x15, 0xae], /* erase_non_displayed_memory */
+            [0x15, 0xae], /* control doubled */
+            [0x94, 0x40], /* preamble, row 13 */
+            [0x94, 0x40], /* control doubled */
+            [0xc8, 0xe9], /* H i */
+            [0x15, 0x2f], /* end_of_caption */
+            [0x15, 0x2f], /* control doubled */
+        ],
+    );
+
+    /* Tab offsets are the same on both fields */
+    assert_pop_on_cc_data(
+        "channel=3 origin-column=6",
+        "Hi",
+        &[
+            [0x15, 0x20], /* resume_caption_loading */
+            [0x15, 0x20], /* control doubled */
+            [0x15, 0xae], /* erase_non_displayed_memory */
+            [0x15, 0xae], /* control doubled */
+            [0x94, 0x52], /* preamble, row 13, indent 4 */
+            [0x94, 0x52], /* control doubled */
+            [0x97, 0xa2], /* tab_offset_2 */
+            [0x97, 0xa2], /* control doubled */
+            [0xc8, 0xe9], /* H i */
+            [0x15, 0x2f], /* end_of_caption */
+            [0x15, 0x2f], /* control doubled */
+        ],
+    );
+
+    assert_pop_on_cc_data(
+        "channel=4 color=red origin-column=4",
+        "Hi",
+        &[
+            [0x9d, 0x20], /* resume_caption_loading */
+            [0x9d, 0x20], /* control doubled */
+            [0x9d, 0xae], /* erase_non_displayed_memory */
+            [0x9d, 0xae], /* control doubled */
+            [0x1c, 0x52], /* preamble, row 13, indent 4 */
+            [0x1c, 0x52], /* control doubled */
+            [0x19, 0xa8], /* mid-row, red */
+            [0x19, 0xa8], /* control doubled */
+            [0xc8, 0xe9], /* H i */
+            [0x9d, 0x2f], /* end_of_caption */
+            [0x9d, 0x2f], /* control doubled */
+        ],
+    );
+}
+
+/* Here we test that on the second field, CC1 is output as CC3, with the
+ * same timestamps as on the first field, and that the caps say so */
+#[test]
+fn test_field() {
+    init();
+
+    let mut timestamps = vec![];
+
+    for field in 1..=2 {
+        let mut h =
+            gst_check::Harness::new_parse(&format!("tttocea608 mode=pop-on field={}", field));
+        h.set_src_caps_str("text/x-raw");
+
+        while h.events_in_queue() != 0 {
+            let event = h.pull_event().unwrap();
+            if let EventView::Caps(e) = event.view() {
+                let s = e.get_caps().get_structure(0).unwrap();
+                assert_eq!(s.get_some::<i32>("field").unwrap(), field - 1);
+            }
+        }
+
+        let inbuf = new_timed_buffer(&"Hi", gst::SECOND, gst::SECOND);
+        assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+        let control_byte = if field == 1 { 0x94 } else { 0x15 };
+        let expected: [[u8; 2]; 9] = [
+            [control_byte, 0x20], /* resume_caption_loading */
+            [control_byte, 0x20], /* control doubled */
+            [control_byte, 0xae], /* erase_non_displayed_memory */
+            [control_byte, 0xae], /* control doubled */
+            [0x94, 0x40],         /* preamble, row 13 */
+            [0x94, 0x40],         /* control doubled */
+            [0xc8, 0xe9],         /* H i */
+            [control_byte, 0x2f], /* end_of_caption */
+            [control_byte, 0x2f], /* control doubled */
+        ];
+
+        let mut field_timestamps = vec![];
+        for e in &expected {
+            let outbuf = h.try_pull().unwrap();
+            field_timestamps.push((outbuf.get_pts(), outbuf.get_duration()));
+            let data = outbuf.map_readable().unwrap();
+            assert_eq!(e, &*data);
+        }
+
+        assert_eq!(h.buffers_in_queue(), 0);
+        timestamps.push(field_timestamps);
+    }
+
+    assert_eq!(timestamps[0], timestamps[1]);
+}
+
+/* Here we test that characters past the 32nd column are dropped */
+#[test]
+fn test_column_overflow() {
+    init();
+
+    assert_pop_on_cc_data(
+        "",
+        "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmn",
+        &[
+            [0x94, 0x20], /* resume_caption_loading */
+            [0x94, 0x20], /* control doubled */
+            [0x94, 0xae], /* erase_non_displayed_memory */
+            [0x94, 0xae], /* control doubled */
+            [0x94, 0x40], /* preamble, row 13 */
+            [0x94, 0x40], /* control doubled */
+            [0xc1, 0xc2], /* A B */
+            [0x43, 0xc4], /* C D */
+            [0x45, 0x46], /* E F */
+            [0xc7, 0xc8], /* G H */
+            [0x49, 0x4a], /* I J */
+            [0xcb, 0x4c], /* K L */
+            [0xcd, 0xce], /* M N */
+            [0x4f, 0xd0], /* O P */
+            [0x51, 0x52], /* Q R */
+            [0xd3, 0x54], /* S T */
+            [0xd5, 0xd6], /* U V */
+            [0x57, 0x58], /* W X */
+            [0xd9, 0xda], /* Y Z */
+            [0x61, 0x62], /* a b */
+            [0xe3, 0x64], /* c d */
+            [0xe5, 0xe6], /* e f */
+            [0x94, 0x2f], /* end_of_caption */
+            [0x94, 0x2f], /* control doubled */
+        ],
+    );
+}
+
+/* Here we test that an extended character in the 32nd column is kept,
+ * and that the one following it is dropped before being encoded */
+#[test]
+fn test_column_overflow_extended() {
+    init();
+
+    let mut expected = vec![
+        [0x94, 0x20], /* resume_caption_loading */
+        [0x94, 0x20], /* control doubled */
+        [0x94, 0xae], /* erase_non_displayed_memory */
+        [0x94, 0xae], /* control doubled */
+        [0x94, 0x40], /* preamble, row 13 */
+        [0x94, 0x40], /* control doubled */
+    ];
+    expected.extend(std::iter::repeat([0xc1, 0xc1]).take(15)); /* A A */
+    expected.extend(&[
+        [0xc1, 0x75], /* A u */
+        [0x92, 0x25], /* ü */
+        [0x94, 0x2f], /* end_of_caption */
+        [0x94, 0x2f], /* control doubled */
+    ]);
+
+    assert_pop_on_cc_data("", "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAüü", &expected);
+}
+
+/* Here we test that a basic character waiting for a second one to be
+ * paired with in the 32nd column is output on its own row, before the
+ * characters past it are dropped and the next row starts */
+#[test]
+fn test_column_overflow_pending_char() {
+    init();
+
+    let mut expected = vec![
+        [0x94, 0x20], /* resume_caption_loading */
+        [0x94, 0x20], /* control doubled */
+        [0x94, 0xae], /* erase_non_displayed_memory */
+        [0x94, 0xae], /* control doubled */
+        [0x94, 0x40], /* preamble, row 13 */
+        [0x94, 0x40], /* control doubled */
+        [0x97, 0xa1], /* tab offset 1 */
+        [0x97, 0xa1], /* control doubled */
+    ];
+    expected.extend(std::iter::repeat([0xc1, 0xc1]).take(15)); /* A A */
+    expected.extend(&[
+        [0xda, 0x80], /* Z, nil */
+        [0x94, 0xe0], /* preamble, row 14 */
+        [0x94, 0xe0], /* control doubled */
+        [0x97, 0xa1], /* tab offset 1 */
+        [0x97, 0xa1], /* control doubled */
+        [0xc2, 0x80], /* B, nil */
+        [0x94, 0x2f], /* end_of_caption */
+        [0x94, 0x2f], /* control doubled */
+    ]);
+
+    assert_pop_on_cc_data(
+        "origin-column=1",
+        "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAZXY\nB",
+        &expected,
+    );
+}
+
+/* Here we test that a character pending at a line break is output on
+ * its own row, and that an extended character starting the next row
+ * overwrites its base character on that row */
+#[test]
+fn test_line_break_extended() {
+    init();
+
+    assert_pop_on_cc_data(
+        "",
+        "ab\nécd",
+        &[
+            [0x94, 0x20], /* resume_caption_loading */
+            [0x94, 0x20], /* control doubled */
+            [0x94, 0xae], /* erase_non_displayed_memory */
+            [0x94, 0xae], /* control doubled */
+            [0x94, 0x40], /* preamble, row 13 */
+            [0x94, 0x40], /* control doubled */
+            [0x61, 0x62], /* a b */
+            [0x94, 0xe0], /* preamble, row 14 */
+            [0x94, 0xe0], /* control doubled */
+            [0xdc, 0xe3], /* é c */
+            [0x64, 0x80], /* d, nil */
+            [0x94, 0x2f], /* end_of_caption */
+            [0x94, 0x2f], /* control doubled */
+        ],
+    );
+
+    assert_pop_on_cc_data(
+        "",
+        "a\nücd",
+        &[
+            [0x94, 0x20], /* resume_caption_loading */
+            [0x94, 0x20], /* control doubled */
+            [0x94, 0xae], /* erase_non_displayed_memory */
+            [0x94, 0xae], /* control doubled */
+            [0x94, 0x40], /* preamble, row 13 */
+            [0x94, 0x40], /* control doubled */
+            [0x61, 0x80], /* a, nil */
+            [0x94, 0xe0], /* preamble, row 14 */
+            [0x94, 0xe0], /* control doubled */
+            [0x75, 0x80], /* u, nil */
+            [0x92, 0x25], /* ü */
+            [0xe3, 0x64], /* c d */
+            [0x94, 0x2f], /* end_of_caption */
+            [0x94, 0x2f], /* control doubled */
+        ],
+    );
+}
+
+/* Here we test that lines are routed to the channel of the marker
+ * preceding them, each channel getting its own caption */
+#[test]
+fn test_channel_markers() {
+    init();
+
+    assert_pop_on_cc_data(
+        "",
+        "[CC1]Hi\n[CC3]yo",
+        &[
+            [0x94, 0x20], /* resume_caption_loading, CC1 */
+            [0x94, 0x20], /* control doubled */
+            [0x94, 0xae], /* erase_non_displayed_memory, CC1 */
+            [0x94, 0xae], /* control doubled */
+            [0x94, 0x40], /* preamble, row 13 */
+            [0x94, 0x40], /* control doubled */
+            [0xc8, 0xe9], /* H i */
+            [0x94, 0x2f], /* end_of_caption, CC1 */
+            [0x94, 0x2f], /* control doubled */
+            [0x15, 0x20], /* resume_caption_loading, CC3 */
+            [0x15, 0x20], /* control doubled */
+            [0x15, 0xae], /* erase_non_displayed_memory, CC3 */
+            [0x15, 0xae], /* control doubled */
+            [0x94, 0x40], /* preamble, row 13 */
+            [0x94, 0x40], /* control doubled */
+            [0x79, 0xef], /* y o */
+            [0x15, 0x2f], /* end_of_caption, CC3 */
+            [0x15, 0x2f], /* control doubled */
+        ],
+    );
+}
+
+/* Here we test that the end_of_caption control code can be delayed
+ * by a number of frames */
+#[test]
+fn test_flip_offset() {
+    init();
+
+    for (flip_offset, expected_pts) in &[
+        (0, [1_000_000_000, 1_033_333_333]),
+        (5, [1_166_666_667, 1_200_000_000]),
+    ] {
+        let mut h = gst_check::Harness::new_parse(&format!(
+            "tttocea608 mode=pop-on flip-offset={}",
+            flip_offset
+        ));
+        h.set_src_caps_str("text/x-raw");
+
+        while h.events_in_queue() != 0 {
+            let _event = h.pull_event().unwrap();
+        }
+
+        let inbuf = new_timed_buffer(&"Hi", gst::SECOND, gst::SECOND);
+        assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+        let mut outbufs = vec![];
+        while h.buffers_in_queue() > 0 {
+            outbufs.push(h.pull().unwrap());
+        }
+
+        assert_eq!(outbufs.len(), 9);
+
+        for (outbuf, pts) in outbufs[7..].iter().zip(expected_pts.iter()) {
+            assert_eq!(outbuf.get_pts(), gst::ClockTime::from(*pts));
+            let data = outbuf.map_readable().unwrap();
+            assert_eq!(&*data, &[0x94, 0x2f]);
+        }
+    }
+}
+
+/* Here we test that lines too long for a row are wrapped at their last
+ * space, or within words longer than a row */
+#[test]
+fn test_wrap() {
+    init();
+
+    assert_pop_on_cc_data(
+        "wrap=true",
+        "Long lines are wrapped at the last space",
+        &[
+            [0x94, 0x20], /* resume_caption_loading */
+            [0x94, 0x20], /* control doubled */
+            [0x94, 0xae], /* erase_non_displayed_memory */
+            [0x94, 0xae], /* control doubled */
+            [0x94, 0x40], /* preamble, row 13 */
+            [0x94, 0x40], /* control doubled */
+            [0x4c, 0xef], /* L o */
+            [0x6e, 0x67], /* n g */
+            [0x20, 0xec], /*   l */
+            [0xe9, 0x6e], /* i n */
+            [0xe5, 0x73], /* e s */
+            [0x20, 0x61], /*   a */
+            [0xf2, 0xe5], /* r e */
+            [0x20, 0xf7], /*   w */
+            [0xf2, 0x61], /* r a */
+            [0x70, 0x70], /* p p */
+            [0xe5, 0x64], /* e d */
+            [0x20, 0x61], /*   a */
+            [0xf4, 0x20], /* t   */
+            [0xf4, 0x68], /* t h */
+            [0xe5, 0x80], /* e, nil */
+            [0x94, 0xe0], /* preamble, row 14 */
+            [0x94, 0xe0], /* control doubled */
+            [0xec, 0x61], /* l a */
+            [0x73, 0xf4], /* s t */
+            [0x20, 0x73], /*   s */
+            [0x70, 0x61], /* p a */
+            [0xe3, 0xe5], /* c e */
+            [0x94, 0x2f], /* end_of_caption */
+            [0x94, 0x2f], /* control doubled */
+        ],
+    );
+
+    assert_pop_on_cc_data(
+        "wrap=true",
+        "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMN",
+        &[
+            [0x94, 0x20], /* resume_caption_loading */
+            [0x94, 0x20], /* control doubled */
+            [0x94, 0xae], /* erase_non_displayed_memory */
+            [0x94, 0xae], /* control doubled */
+            [0x94, 0x40], /* preamble, row 13 */
+            [0x94, 0x40], /* control doubled */
+            [0x61, 0x62], /* a b */
+            [0xe3, 0x64], /* c d */
+            [0xe5, 0xe6], /* e f */
+            [0x67, 0x68], /* g h */
+            [0xe9, 0xea], /* i j */
+            [0x6b, 0xec], /* k l */
+            [0x6d, 0x6e], /* m n */
+            [0xef, 0x70], /* o p */
+            [0xf1, 0xf2], /* q r */
+            [0x73, 0xf4], /* s t */
+            [0x75, 0x76], /* u v */
+            [0xf7, 0xf8], /* w x */
+            [0x79, 0x7a], /* y z */
+            [0xc1, 0xc2], /* A B */
+            [0x43, 0xc4], /* C D */
+            [0x45, 0x46], /* E F */
+            [0x94, 0xe0], /* preamble, row 14 */
+            [0x94, 0xe0], /* control doubled */
+            [0xc7, 0xc8], /* G H */
+            [0x49, 0x4a], /* I J */
+            [0xcb, 0x4c], /* K L */
+            [0xcd, 0xce], /* M N */
+            [0x94, 0x2f], /* end_of_caption */
+            [0x94, 0x2f], /* control doubled */
+        ],
+    );
+}
+
+/* Here we test that a sentence is wrapped at a word boundary, the
+ * space at the break being dropped */
+#[test]
+fn test_wrap_sentence() {
+    init();
+
+    assert_pop_on_cc_data(
+        "wrap=true",
+        "The quick brown fox jumps over the lazy dog again.",
+        &[
+            [0x94, 0x20], /* resume_caption_loading */
+            [0x94, 0x20], /* control doubled */
+            [0x94, 0xae], /* erase_non_displayed_memory */
+            [0x94, 0xae], /* control doubled */
+            [0x94, 0x40], /* preamble, row 13 */
+            [0x94, 0x40], /* control doubled */
+            [0x54, 0x68], /* T h */
+            [0xe5, 0x20], /* e   */
+            [0xf1, 0x75], /* q u */
+            [0xe9, 0xe3], /* i c */
+            [0x6b, 0x20], /* k   */
+            [0x62, 0xf2], /* b r */
+            [0xef, 0xf7], /* o w */
+            [0x6e, 0x20], /* n   */
+            [0xe6, 0xef], /* f o */
+            [0xf8, 0x20], /* x   */
+            [0xea, 0x75], /* j u */
+            [0x6d, 0x70], /* m p */
+            [0x73, 0x20], /* s   */
+            [0xef, 0x76], /* o v */
+            [0xe5, 0xf2], /* e r */
+            [0x94, 0xe0], /* preamble, row 14 */
+            [0x94, 0xe0], /* control doubled */
+            [0xf4, 0x68], /* t h */
+            [0xe5, 0x20], /* e   */
+            [0xec, 0x61], /* l a */
+            [0x7a, 0x79], /* z y */
+            [0x20, 0x64], /*   d */
+            [0xef, 0x67], /* o g */
+            [0x20, 0x61], /*   a */
+            [0x67, 0x61], /* g a */
+            [0xe9, 0x6e], /* i n */
+            [0xae, 0x80], /* ., nil */
+            [0x94, 0x2f], /* end_of_caption */
+            [0x94, 0x2f], /* control doubled */
+        ],
+    );
+}
+
+/* Here we test that rows past max-rows are dropped */
+#[test]
+fn test_max_rows() {
+    init();
+
+    assert_pop_on_cc_data(
+        "origin-row=10 max-rows=2",
+        "A\nB\nC",
+        &[
+            [0x94, 0x20], /* resume_caption_loading */
+            [0x94, 0x20], /* control doubled */
+            [0x94, 0xae], /* erase_non_displayed_memory */
+            [0x94, 0xae], /* control doubled */
+            [0x10, 0x40], /* preamble, row 10 */
+            [0x10, 0x40], /* control doubled */
+            [0xc1, 0x80], /* A, nil */
+            [0x13, 0x40], /* preamble, row 11 */
+            [0x13, 0x40], /* control doubled */
+            [0xc2, 0x80], /* B, nil */
+            [0x94, 0x2f], /* end_of_caption */
+            [0x94, 0x2f], /* control doubled */
+        ],
+    );
+}
+
+/* Here we test that characters without a CEA-608 glyph are spelled
+ * with two characters when possible */
+#[test]
+fn test_two_char_fallback() {
+    init();
+
+    assert_pop_on_cc_data(
+        "",
+        "Œuvre",
+        &[
+            [0x94, 0x20], /* resume_caption_loading */
+            [0x94, 0x20], /* control doubled */
+            [0x94, 0xae], /* erase_non_displayed_memory */
+            [0x94, 0xae], /* control doubled */
+            [0x94, 0x40], /* preamble, row 13 */
+            [0x94, 0x40], /* control doubled */
+            [0x4f, 0x45], /* O E */
+            [0x75, 0x76], /* u v */
+            [0xf2, 0xe5], /* r e */
+            [0x94, 0x2f], /* end_of_caption */
+            [0x94, 0x2f], /* control doubled */
+        ],
+    );
+}
+
+/* Here we test the per-frame description of the byte pairs output
+ * in debug mode */
+#[test]
+fn test_debug_output() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=pop-on debug-output=true");
+    h.set_src_caps_str("text/x-raw");
+
+    while h.events_in_queue() != 0 {
+        let event = h.pull_event().unwrap();
+        if let EventView::Caps(e) = event.view() {
+            let s = e.get_caps().get_structure(0).unwrap();
+            assert_eq!(s.get_name(), "text/x-raw");
+        }
+    }
+
+    let inbuf = new_timed_buffer(&"Hi", gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    let expected = [
+        "frame 23: resume_caption_loading",
+        "frame 24: resume_caption_loading",
+        "frame 25: erase_non_displayed_memory",
+        "frame 26: erase_non_displayed_memory",
+        "frame 27: preamble row 13 column 0 white",
+        "frame 28: preamble row 13 column 0 white",
+        "frame 29: text 'Hi'",
+        "frame 30: end_of_caption",
+        "frame 31: end_of_caption",
+    ];
+
+    for e in &expected {
+        let outbuf = h.try_pull().unwrap();
+        let data = outbuf.map_readable().unwrap();
+        assert_eq!(*e, std::str::from_utf8(&*data).unwrap());
+    }
+
+    assert_eq!(h.buffers_in_queue(), 0);
+}
+
+/* Here we test that lines are centered or right-aligned with an
+ * indent preamble followed by a tab offset */
+#[test]
+fn test_alignment() {
+    init();
+
+    /* (32 - 10) / 2 = 11 */
+    assert_pop_on_cc_data(
+        "alignment=center",
+        "0123456789",
+        &[
+            [0x94, 0x20], /* resume_caption_loading */
+            [0x94, 0x20], /* control doubled */
+            [0x94, 0xae], /* erase_non_displayed_memory */
+            [0x94, 0xae], /* control doubled */
+            [0x94, 0x54], /* preamble, row 13, indent 8 */
+            [0x94, 0x54], /* control doubled */
+            [0x97, 0x23], /* tab_offset_3 */
+            [0x97, 0x23], /* control doubled */
+            [0xb0, 0x31], /* 0 1 */
+            [0x32, 0xb3], /* 2 3 */
+            [0x34, 0xb5], /* 4 5 */
+            [0xb6, 0x37], /* 6 7 */
+            [0x38, 0xb9], /* 8 9 */
+            [0x94, 0x2f], /* end_of_caption */
+            [0x94, 0x2f], /* control doubled */
+        ],
+    );
+
+    /* 32 - 10 = 22 */
+    assert_pop_on_cc_data(
+        "alignment=right",
+        "0123456789",
+        &[
+            [0x94, 0x20], /* resume_caption_loading */
+            [0x94, 0x20], /* control doubled */
+            [0x94, 0xae], /* erase_non_displayed_memory */
+            [0x94, 0xae], /* control doubled */
+            [0x94, 0xda], /* preamble, row 13, indent 20 */
+            [0x94, 0xda], /* control doubled */
+            [0x97, 0xa2], /* tab_offset_2 */
+            [0x97, 0xa2], /* control doubled */
+            [0xb0, 0x31], /* 0 1 */
+            [0x32, 0xb3], /* 2 3 */
+            [0x34, 0xb5], /* 4 5 */
+            [0xb6, 0x37], /* 6 7 */
+            [0x38, 0xb9], /* 8 9 */
+            [0x94, 0x2f], /* end_of_caption */
+            [0x94, 0x2f], /* control doubled */
+        ],
+    );
+
+    /* The mid-row code takes a column: (32 - 11) / 2 = 10 */
+    assert_pop_on_cc_data(
+        "alignment=center",
+        "01234<i>56789</i>",
+        &[
+            [0x94, 0x20], /* resume_caption_loading */
+            [0x94, 0x20], /* control doubled */
+            [0x94, 0xae], /* erase_non_displayed_memory */
+            [0x94, 0xae], /* control doubled */
+            [0x94, 0x54], /* preamble, row 13, indent 8 */
+            [0x94, 0x54], /* control doubled */
+            [0x97, 0xa2], /* tab_offset_2 */
+            [0x97, 0xa2], /* control doubled */
+            [0xb0, 0x31], /* 0 1 */
+            [0x32, 0xb3], /* 2 3 */
+            [0x34, 0x80], /* 4, nil */
+            [0x91, 0xae], /* mid-row, italics */
+            [0x91, 0xae], /* control doubled */
+            [0xb5, 0xb6], /* 5 6 */
+            [0x37, 0x38], /* 7 8 */
+            [0xb9, 0x80], /* 9, nil */
+            [0x94, 0x2f], /* end_of_caption */
+            [0x94, 0x2f], /* control doubled */
+        ],
+    );
+}
+
+/* Here we test that each byte pair is wrapped in a cc_data triple
+ * when downstream wants CEA-708 cc_data */
+#[test]
+fn test_cc_data_output() {
+    init();
+
+    for (channel, cc_type, controls) in &[
+        (1, 0xfc, [0x94, 0x20, 0x94, 0xae, 0x94, 0x2f]),
+        (3, 0xfd, [0x15, 0x20, 0x15, 0xae, 0x15, 0x2f]),
+    ] {
+        let mut h =
+            gst_check::Harness::new_parse(&format!("tttocea608 mode=pop-on channel={}", channel));
+        h.set_src_caps_str("text/x-raw");
+        h.set_sink_caps_str("closedcaption/x-cea-708, format=cc_data");
+
+        while h.events_in_queue() != 0 {
+            let event = h.pull_event().unwrap();
+            if let EventView::Caps(e) = event.view() {
+                let s = e.get_caps().get_structure(0).unwrap();
+                assert_eq!(s.get_name(), "closedcaption/x-cea-708");
+                assert_eq!(s.get::<&str>("format").unwrap(), Some("cc_data"));
+            }
+        }
+
+        let inbuf = new_timed_buffer(&"Hi", gst::SECOND, gst::SECOND);
+        assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+        let expected: [[u8; 3]; 9] = [
+            [*cc_type, controls[0], controls[1]], /* resume_caption_loading */
+            [*cc_type, controls[0], controls[1]], /* control doubled */
+            [*cc_type, controls[2], controls[3]], /* erase_non_displayed_memory */
+            [*cc_type, controls[2], controls[3]], /* control doubled */
+            [*cc_type, 0x94, 0x40],               /* preamble */
+            [*cc_type, 0x94, 0x40],               /* control doubled */
+            [*cc_type, 0xc8, 0xe9],               /* H i */
+            [*cc_type, controls[4], controls[5]], /* end_of_caption */
+            [*cc_type, controls[4], controls[5]], /* control doubled */
+        ];
+
+        for e in &expected {
+            let outbuf = h.try_pull().unwrap();
+            let data = outbuf.map_readable().unwrap();
+            assert_eq!(&e[..], &*data);
+        }
+
+        assert_eq!(h.buffers_in_queue(), 0);
+    }
+}
+
+/* Here we test that each byte pair is prefixed with the field flag
+ * when downstream wants S334-1A, and that raw is preferred otherwise */
+#[test]
+fn test_s334_1a_output() {
+    init();
+
+    for (channel, flags, controls) in &[
+        (1, 0x80, [0x94, 0x20, 0x94, 0xae, 0x94, 0x2f]),
+        (3, 0x00, [0x15, 0x20, 0x15, 0xae, 0x15, 0x2f]),
+    ] {
+        let mut h =
+            gst_check::Harness::new_parse(&format!("tttocea608 mode=pop-on channel={}", channel));
+        h.set_src_caps_str("text/x-raw");
+        h.set_sink_caps_str("closedcaption/x-cea-608, format=s334-1a");
+
+        while h.events_in_queue() != 0 {
+            let event = h.pull_event().unwrap();
+            if let EventView::Caps(e) = event.view() {
+                let s = e.get_caps().get_structure(0).unwrap();
+                assert_eq!(s.get_name(), "closedcaption/x-cea-608");
+                assert_eq!(s.get::<&str>("format").unwrap(), Some("s334-1a"));
+            }
+        }
+
+        let inbuf = new_timed_buffer(&"Hi", gst::SECOND, gst::SECOND);
+        assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+        let expected: [[u8; 3]; 9] = [
+            [*flags, controls[0], controls[1]], /* resume_caption_loading */
+            [*flags, controls[0], controls[1]], /* control doubled */
+            [*flags, controls[2], controls[3]], /* erase_non_displayed_memory */
+            [*flags, controls[2], controls[3]], /* control doubled */
+            [*flags, 0x94, 0x40],               /* preamble */
+            [*flags, 0x94, 0x40],               /* control doubled */
+            [*flags, 0xc8, 0xe9],               /* H i */
+            [*flags, controls[4], controls[5]], /* end_of_caption */
+            [*flags, controls[4], controls[5]], /* control doubled */
+        ];
+
+        for e in &expected {
+            let outbuf = h.try_pull().unwrap();
+            let data = outbuf.map_readable().unwrap();
+            assert_eq!(&e[..], &*data);
+        }
+
+        assert_eq!(h.buffers_in_queue(), 0);
+    }
+
+    let mut h = gst_check::Harness::new("tttocea608");
+    h.set_src_caps_str("text/x-raw");
+    h.set_sink_caps_str("closedcaption/x-cea-608");
+
+    let mut n_caps = 0;
+    while h.events_in_queue() != 0 {
+        let event = h.pull_event().unwrap();
+        if let EventView::Caps(e) = event.view() {
+            let s = e.get_caps().get_structure(0).unwrap();
+            assert_eq!(s.get::<&str>("format").unwrap(), Some("raw"));
+            n_caps += 1;
+        }
+    }
+    assert_eq!(n_caps, 1);
+}
+
+/* Here we test accented characters: those in the basic set, extended
+ * characters overwriting their base letter, and letters without a
+ * glyph approximated with their base letter */
+#[test]
+fn test_accented_characters() {
+    init();
+
+    let cases: &[(&'static str, &[[u8; 2]])] = &[
+        (
+            "ñandú",
+            &[
+                [0xfe, 0x61], /* ñ a */
+                [0x6e, 0x64], /* n d */
+                [0xe0, 0x80], /* ú, nil */
+            ],
+        ),
+        (
+            "Émile",
+            &[
+                [0x45, 0x80], /* E, nil */
+                [0x92, 0xa1], /* É */
+                [0x6d, 0xe9], /* m i */
+                [0xec, 0xe5], /* l e */
+            ],
+        ),
+        (
+            "Müller",
+            &[
+                [0xcd, 0x75], /* M u */
+                [0x92, 0x25], /* ü */
+                [0xec, 0xec], /* l l */
+                [0xe5, 0xf2], /* e r */
+            ],
+        ),
+        (
+            "Dvořák",
+            &[
+                [0xc4, 0x76], /* D v */
+                [0xef, 0xf2], /* o r */
+                [0x2a, 0x6b], /* á k */
+            ],
+        ),
+        ("Ÿ", &[[0xd9, 0x80] /* Y, nil */]),
+        (
+            "çéñü",
+            &[
+                [0xfb, 0xdc], /* ç é */
+                [0xfe, 0x75], /* ñ u */
+                [0x92, 0x25], /* ü */
+            ],
+        ),
+    ];
+
+    for (text, text_cc_data) in cases {
+        let mut expected = vec![
+            [0x94, 0x20], /* resume_caption_loading */
+            [0x94, 0x20], /* control doubled */
+            [0x94, 0xae], /* erase_non_displayed_memory */
+            [0x94, 0xae], /* control doubled */
+            [0x94, 0x40], /* preamble, row 13 */
+            [0x94, 0x40], /* control doubled */
+        ];
+        expected.extend_from_slice(text_cc_data);
+        expected.push([0x94, 0x2f]); /* end_of_caption */
+        expected.push([0x94, 0x2f]); /* control doubled */
+
+        assert_pop_on_cc_data("", *text, &expected);
+    }
+}
+
+/* Here we test what happens to characters CEA-608 has no glyph for
+ * with the different fallbacks */
+#[test]
+fn test_fallback() {
+    init();
+
+    let cases: &[(&str, &'static str, &[[u8; 2]])] = &[
+        (
+            "space",
+            "A€B",
+            &[[0xc1, 0x20] /* A   */, [0xc2, 0x80] /* B, nil */],
+        ),
+        ("drop", "A€B", &[[0xc1, 0xc2] /* A B */]),
+        (
+            "transliterate",
+            "A–B…",
+            &[
+                [0xc1, 0xad], /* A - */
+                [0xc2, 0xae], /* B . */
+                [0xae, 0xae], /* . . */
+            ],
+        ),
+        (
+            "transliterate",
+            "„Hi‟",
+            &[[0xa2, 0xc8] /* " H */, [0xe9, 0xa2] /* i " */],
+        ),
+    ];
+
+    for (fallback, text, text_cc_data) in cases {
+        let mut expected = vec![
+            [0x94, 0x20], /* resume_caption_loading */
+            [0x94, 0x20], /* control doubled */
+            [0x94, 0xae], /* erase_non_displayed_memory */
+            [0x94, 0xae], /* control doubled */
+            [0x94, 0x40], /* preamble, row 13 */
+            [0x94, 0x40], /* control doubled */
+        ];
+        expected.extend_from_slice(text_cc_data);
+        expected.push([0x94, 0x2f]); /* end_of_caption */
+        expected.push([0x94, 0x2f]); /* control doubled */
+
+        assert_pop_on_cc_data(&format!("fallback={}", fallback), *text, &expected);
+    }
+}
+
+/* Checks a CDP and returns its sequence counter, time code and the
+ * field 1 byte pairs it carries */
+fn parse_cdp(data: &[u8]) -> (u16, [u8; 4], Vec<[u8; 2]>) {
+    assert_eq!(&data[..2], &[0x96, 0x69]);
+    assert_eq!(data[2] as usize, data.len());
+    assert_eq!(
+        data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)),
+        0
+    );
+
+    let sequence_counter = (data[5] as u16) << 8 | data[6] as u16;
+
+    assert_eq!(data[7], 0x71);
+    let time_code = [data[8], data[9], data[10], data[11]];
+
+    assert_eq!(data[12], 0x72);
+    let cc_count = (data[13] & 0x1f) as usize;
+    let cc_data = &data[14..14 + 3 * cc_count];
+
+    let pairs = cc_data
+        .chunks(3)
+        .filter(|triple| triple[0] == 0xfc)
+        .map(|triple| [triple[1], triple[2]])
+        .collect();
+
+    let footer = &data[14 + 3 * cc_count..];
+    assert_eq!(footer[0], 0x74);
+    assert_eq!((footer[1] as u16) << 8 | footer[2] as u16, sequence_counter);
+
+    (sequence_counter, time_code, pairs)
+}
+
+/* Here we test that CDPs decode back to the byte pairs of the caption,
+ * with increasing sequence counters and the time code of their frame */
+#[test]
+fn test_cdp_output() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=pop-on");
+    h.set_src_caps_str("text/x-raw");
+    h.set_sink_caps_str("closedcaption/x-cea-708, format=cdp");
+
+    while h.events_in_queue() != 0 {
+        let event = h.pull_event().unwrap();
+        if let EventView::Caps(e) = event.view() {
+            let s = e.get_caps().get_structure(0).unwrap();
+            assert_eq!(s.get_name(), "closedcaption/x-cea-708");
+            assert_eq!(s.get::<&str>("format").unwrap(), Some("cdp"));
+        }
+    }
+
+    let inbuf = new_timed_buffer(&"Hi", gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    let expected = [
+        [0x94, 0x20], /* resume_caption_loading */
+        [0x94, 0x20], /* control doubled */
+        [0x94, 0xae], /* erase_non_displayed_memory */
+        [0x94, 0xae], /* control doubled */
+        [0x94, 0x40], /* preamble */
+        [0x94, 0x40], /* control doubled */
+        [0xc8, 0xe9], /* H i */
+        [0x94, 0x2f], /* end_of_caption */
+        [0x94, 0x2f], /* control doubled */
+    ];
+
+    for (i, e) in expected.iter().enumerate() {
+        let outbuf = h.try_pull().unwrap();
+        let data = outbuf.map_readable().unwrap();
+
+        /* 7 byte header, 5 byte time code, 20 triples at 30 fps and 4 byte footer */
+        assert_eq!(data.len(), 7 + 5 + 2 + 3 * 20 + 4);
+
+        let (sequence_counter, time_code, pairs) = parse_cdp(&data);
+        assert_eq!(sequence_counter, i as u16);
+        /* The caption starts on frame 23, 00:00:00:23 */
+        let frame_no = 23 + i as u8;
+        let (seconds, frames) = (frame_no / 30, frame_no % 30);
+        assert_eq!(
+            time_code,
+            [0xc0, 0x80, seconds, (frames / 10) << 4 | frames % 10]
+        );
+        assert_eq!(pairs, vec![*e]);
+    }
+
+    assert_eq!(h.buffers_in_queue(), 0);
+}
+
+/* Here we test that in pop-on mode, gaps are forwarded latency frames
+ * behind, that the default latency follows max-rows, and that an
+ * explicit latency is honoured */
+#[test]
+fn test_latency() {
+    init();
+
+    for (properties, expected_gap) in &[
+        /* 74 frames of latency by default */
+        ("", 1_533_333_333u64),
+        /* Never lower than for 2 rows */
+        ("max-rows=1", 1_533_333_333u64),
+        /* 34 more frames for the third row */
+        ("max-rows=3", 400_000_000u64),
+        /* An explicit latency always wins */
+        ("max-rows=3 latency=78", 1_400_000_000u64),
+        ("latency=10", 3_666_666_667u64),
+    ] {
+        let mut h =
+            gst_check::Harness::new_parse(&format!("tttocea608 mode=pop-on {}", properties));
+        h.set_src_caps_str("text/x-raw");
+
+        while h.events_in_queue() != 0 {
+            let _event = h.pull_event().unwrap();
+        }
+
+        /* Frame 120, minus the latency */
+        let gap_event = gst::event::Gap::new(0.into(), 4 * gst::SECOND);
+        assert_eq!(h.push_event(gap_event), true);
+
+        assert_eq!(h.events_in_queue(), 1);
+        let event = h.pull_event().unwrap();
+        if let EventView::Gap(ev) = event.view() {
+            assert_eq!(ev.get(), (0.into(), (*expected_gap).into()));
+        } else {
+            panic!("Expected a gap event, got {:?}", event);
+        }
+    }
+}
+
+/* Here we test that erase_non_displayed_memory can be skipped, which
+ * lowers the default latency by two frames */
+#[test]
+fn test_erase_non_displayed() {
+    init();
+
+    assert_pop_on_cc_data(
+        "erase-non-displayed=false",
+        "Hi",
+        &[
+            [0x94, 0x20], /* resume_caption_loading */
+            [0x94, 0x20], /* control doubled */
+            [0x94, 0x40], /* preamble, row 13 */
+            [0x94, 0x40], /* control doubled */
+            [0xc8, 0xe9], /* H i */
+            [0x94, 0x2f], /* end_of_caption */
+            [0x94, 0x2f], /* control doubled */
+        ],
+    );
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=pop-on erase-non-displayed=false");
+    h.set_src_caps_str("text/x-raw");
+
+    while h.events_in_queue() != 0 {
+        let _event = h.pull_event().unwrap();
+    }
+
+    /* Frame 75, 72 frames of latency */
+    let gap_event = gst::event::Gap::new(0.into(), 2_500_000_000.into());
+    assert_eq!(h.push_event(gap_event), true);
+
+    assert_eq!(h.events_in_queue(), 1);
+    let event = h.pull_event().unwrap();
+    if let EventView::Gap(ev) = event.view() {
+        assert_eq!(ev.get(), (0.into(), 100_000_000.into()));
+    } else {
+        panic!("Expected a gap event, got {:?}", event);
+    }
+}
+
+/* Here we test that characters CEA-608 can't represent get replaced
+ * with the replacement character, or a space if it is invalid */
+#[test]
+fn test_replacement_char() {
+    init();
+
+    assert_pop_on_cc_data(
+        "replacement-char=?",
+        "A€B",
+        &[
+            [0x94, 0x20], /* resume_caption_loading */
+            [0x94, 0x20], /* control doubled */
+            [0x94, 0xae], /* erase_non_displayed_memory */
+            [0x94, 0xae], /* control doubled */
+            [0x94, 0x40], /* preamble, row 13 */
+            [0x94, 0x40], /* control doubled */
+            [0xc1, 0xbf], /* A ? */
+            [0xc2, 0x80], /* B, nil */
+            [0x94, 0x2f], /* end_of_caption */
+            [0x94, 0x2f], /* control doubled */
+        ],
+    );
+
+    let tttocea608 = gst::ElementFactory::make("tttocea608", None).unwrap();
+    tttocea608.set_property("replacement-char", &"€").unwrap();
+    assert_eq!(
+        tttocea608
+            .get_property("replacement-char")
+            .unwrap()
+            .get::<String>()
+            .unwrap(),
+        Some(" ".to_string())
+    );
+}
+
+/* Here we test that an element message names the first character
+ * that can't be represented, at most once per second */
+#[test]
+fn test_untranslatable_message() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=pop-on");
+    h.set_src_caps_str("text/x-raw");
+    let bus = gst::Bus::new();
+    h.get_element().unwrap().set_bus(Some(&bus));
+
+    while h.events_in_queue() != 0 {
+        let _event = h.pull_event().unwrap();
+    }
+
+    for (text, pts, expected) in &[
+        ("A€B€", gst::SECOND, Some(("€", 1, 2))),
+        ("€", 1_500_000_000.into(), None),
+        ("Hello", 3 * gst::SECOND, None),
+        ("✓", 4 * gst::SECOND, Some(("✓", 0, 1))),
+    ] {
+        let inbuf = new_timed_buffer(*text, *pts, 100 * gst::MSECOND);
+        assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+        let msg = bus.pop_filtered(&[gst::MessageType::Element]);
+
+        match expected {
+            Some((character, position, count)) => {
+                let msg = msg.unwrap();
+                let s = msg.get_structure().unwrap();
+                assert_eq!(s.get_name(), "tttocea608-untranslatable");
+                assert_eq!(s.get::<&str>("character").unwrap(), Some(*character));
+                assert_eq!(s.get_some::<u32>("position").unwrap(), *position);
+                assert_eq!(s.get_some::<u32>("count").unwrap(), *count);
+                assert_eq!(s.get::<&str>("action").unwrap(), Some("replaced"));
+            }
+            None => assert!(msg.is_none()),
+        }
+    }
+}
+
+/* Here we test that min-duration keeps short captions on screen, unless
+ * the next caption replaces them before that */
+#[test]
+fn test_min_duration() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=pop-on min-duration=1000000000");
+    h.set_src_caps_str("text/x-raw");
+
+    while h.events_in_queue() != 0 {
+        let _event = h.pull_event().unwrap();
+    }
+
+    /* A 100ms caption gets erased 1s after its display */
+    let inbuf = new_timed_buffer(&"Hello", gst::SECOND, 100 * gst::MSECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    /* This one replaces it 500ms after its display */
+    let inbuf = new_timed_buffer(&"World", 1_500_000_000.into(), 100 * gst::MSECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+    h.push_event(gst::event::Eos::new());
+
+    let mut end_of_caption_pts = vec![];
+    let mut erase_display_pts = vec![];
+
+    while h.buffers_in_queue() > 0 {
+        let outbuf = h.pull().unwrap();
+        let data = outbuf.map_readable().unwrap();
+
+        if *data == [0x94, 0x2f] {
+            end_of_caption_pts.push(outbuf.get_pts());
+        } else if *data == [0x94, 0x2c] {
+            erase_display_pts.push(outbuf.get_pts());
+        }
+    }
+
+    assert_eq!(
+        end_of_caption_pts,
+        vec![
+            gst::SECOND,
+            1_033_333_333.into(),
+            1_500_000_000.into(),
+            1_533_333_333.into()
+        ]
+    );
+    assert_eq!(
+        erase_display_pts,
+        vec![gst::ClockTime::from(2_500_000_000), 2_533_333_333.into()]
+    );
+}
+
+/* Here we test that captions without a duration last until the next
+ * caption, or for default-duration at EOS */
+#[test]
+fn test_no_duration() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=pop-on default-duration=2000000000");
+    h.set_src_caps_str("text/x-raw");
+
+    while h.events_in_queue() != 0 {
+        let _event = h.pull_event().unwrap();
+    }
+
+    let inbuf = new_timed_buffer(&"Hello", gst::SECOND, gst::CLOCK_TIME_NONE);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    /* Held until we know when it ends */
+    assert_eq!(h.buffers_in_queue(), 0);
+
+    let inbuf = new_timed_buffer(&"World", 3 * gst::SECOND, gst::CLOCK_TIME_NONE);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+    h.push_event(gst::event::Eos::new());
+
+    let mut end_of_caption_pts = vec![];
+    let mut erase_display_pts = vec![];
+
+    while h.buffers_in_queue() > 0 {
+        let outbuf = h.pull().unwrap();
+        let data = outbuf.map_readable().unwrap();
+
+        if *data == [0x94, 0x2f] {
+            end_of_caption_pts.push(outbuf.get_pts());
+        } else if *data == [0x94, 0x2c] {
+            erase_display_pts.push(outbuf.get_pts());
+        }
+    }
+
+    /* World replaces Hello, and gets erased 2 seconds later */
+    assert_eq!(
+        end_of_caption_pts,
+        vec![
+            gst::SECOND,
+            1_033_333_333.into(),
+            3 * gst::SECOND,
+            3_033_333_333.into()
+        ]
+    );
+    assert_eq!(
+        erase_display_pts,
+        vec![5 * gst::SECOND, 5_033_333_333.into()]
+    );
+}
+
+/* Here we test that empty and whitespace-only buffers, including those
+ * only made of markup, are ignored by default, and erase the display
+ * with clear-on-empty */
+#[test]
+fn test_empty_caption() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=pop-on");
+    h.set_src_caps_str("text/x-raw");
+
+    let inbuf = new_timed_buffer(&"", gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+    let inbuf = new_timed_buffer(&"\n\n", 2 * gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+    let inbuf = new_timed_buffer(&"<i> </i>\n", 3 * gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+    h.push_event(gst::event::Eos::new());
+
+    assert_eq!(h.buffers_in_queue(), 0);
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=pop-on clear-on-empty=true");
+    h.set_src_caps_str("text/x-raw");
+
+    let inbuf = new_timed_buffer(&"Hello", gst::SECOND, 10 * gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+    let inbuf = new_timed_buffer(&"\n\n", 3 * gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+    let inbuf = new_timed_buffer(&"", 5 * gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+    h.push_event(gst::event::Eos::new());
+
+    let mut erase_display_pts = vec![];
+
+    while h.buffers_in_queue() > 0 {
+        let outbuf = h.pull().unwrap();
+        let data = outbuf.map_readable().unwrap();
+
+        if *data == [0x94, 0x2c] {
+            erase_display_pts.push(outbuf.get_pts());
+        }
+    }
+
+    /* Hello gets erased early, not at its end */
+    assert_eq!(
+        erase_display_pts,
+        vec![
+            3 * gst::SECOND,
+            3_033_333_333.into(),
+            5 * gst::SECOND,
+            5_033_333_333.into()
+        ]
+    );
+}
+
+/* Here we test that with dynamic-latency, the latency follows the
+ * largest caption output so far */
+#[test]
+fn test_dynamic_latency() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=pop-on dynamic-latency=true");
+    h.set_src_caps_str("text/x-raw");
+    let bus = gst::Bus::new();
+    h.get_element().unwrap().set_bus(Some(&bus));
+
+    while h.events_in_queue() != 0 {
+        let _event = h.pull_event().unwrap();
+    }
+
+    /* 9 byte pairs, plus 2 for a potential erase_display_memory */
+    let inbuf = new_timed_buffer(&"Hi", gst::SECOND, 10 * gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    let msg = bus.pop_filtered(&[gst::MessageType::Latency]);
+    assert!(msg.is_some());
+
+    /* Gap before the caption */
+    let _event = h.pull_event().unwrap();
+
+    /* Frame 90 minus 11 frames of latency */
+    let gap_event = gst::event::Gap::new(2 * gst::SECOND, gst::SECOND);
+    assert_eq!(h.push_event(gap_event), true);
+
+    let event = h.pull_event().unwrap();
+    if let EventView::Gap(ev) = event.view() {
+        assert_eq!(ev.get(), (1_066_666_667.into(), 1_566_666_666.into()));
+    } else {
+        panic!("Expected a gap event, got {:?}", event);
+    }
+
+    /* A caption of the same size doesn't change the latency */
+    let inbuf = new_timed_buffer(&"Yo", 5 * gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    let msg = bus.pop_filtered(&[gst::MessageType::Latency]);
+    assert!(msg.is_none());
+}
+
+/* Here we test that after a flush, timestamps restart from the PTS of
+ * the next caption */
+#[test]
+fn test_flush() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=pop-on");
+    h.set_src_caps_str("text/x-raw");
+
+    let inbuf = new_timed_buffer(&"Hello", 10 * gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    while h.buffers_in_queue() > 0 {
+        let _outbuf = h.pull().unwrap();
+    }
+
+    assert_eq!(h.push_event(gst::event::FlushStart::new()), true);
+    assert_eq!(h.push_event(gst::event::FlushStop::new(true)), true);
+    assert_eq!(
+        h.push_event(gst::event::Segment::new(&gst::FormattedSegment::<
+            gst::ClockTime,
+        >::new())),
+        true
+    );
+
+    let inbuf = new_timed_buffer(&"Hi", gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    /* The caption starts on frame 23, and nothing erases the one we
+     * flushed */
+    let outbuf = h.pull().unwrap();
+    assert_eq!(outbuf.get_pts(), 766_666_667.into());
+    let data = outbuf.map_readable().unwrap();
+    assert_eq!(&*data, &[0x94, 0x20]);
+}
+
+/* Here we test that at 29.97 fps, captions still flip within a frame
+ * of their PTS after 10 minutes */
+#[test]
+fn test_ntsc_framerate_drift() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=pop-on");
+    h.set_src_caps_str("text/x-raw");
+    h.set_sink_caps_str("closedcaption/x-cea-608, format=raw, framerate=30000/1001");
+
+    while h.events_in_queue() != 0 {
+        let _event = h.pull_event().unwrap();
+    }
+
+    let frame_duration = gst::SECOND.mul_div_round(1001, 30000).unwrap();
+
+    for i in 0..300 {
+        let pts = 2 * i * gst::SECOND + gst::SECOND;
+        let inbuf = new_timed_buffer(&"Hi", pts, gst::SECOND);
+        assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+        let mut end_of_caption_pts = None;
+        while h.buffers_in_queue() > 0 {
+            let outbuf = h.pull().unwrap();
+            let data = outbuf.map_readable().unwrap();
+
+            if *data == [0x94, 0x2f] && end_of_caption_pts.is_none() {
+                end_of_caption_pts = Some(outbuf.get_pts());
+            }
+        }
+
+        let end_of_caption_pts = end_of_caption_pts.unwrap();
+        assert!(end_of_caption_pts <= pts);
+        assert!(pts - end_of_caption_pts < frame_duration);
+    }
+}
+
+/* Here we test that frame numbers are computed from the running time,
+ * and that the output is timestamped accordingly */
+#[test]
+fn test_segment() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=pop-on");
+    h.set_src_caps_str("text/x-raw");
+
+    let mut segment = gst::FormattedSegment::<gst::ClockTime>::new();
+    segment.set_start(10 * gst::SECOND);
+    segment.set_time(10 * gst::SECOND);
+    assert_eq!(h.push_event(gst::event::Segment::new(&segment)), true);
+
+    while h.events_in_queue() != 0 {
+        let event = h.pull_event().unwrap();
+        if let EventView::Segment(e) = event.view() {
+            let segment = e.get_segment().downcast_ref::<gst::ClockTime>().unwrap();
+            assert_eq!(segment.get_start(), 0.into());
+        }
+    }
+
+    /* Running time 1s */
+    let inbuf = new_timed_buffer(&"Hi", 11 * gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    let outbuf = h.pull().unwrap();
+    assert_eq!(outbuf.get_pts(), 766_666_667.into());
+    let data = outbuf.map_readable().unwrap();
+    assert_eq!(&*data, &[0x94, 0x20]);
+
+    /* Outside of the segment */
+    let inbuf = new_timed_buffer(&"Hi", 5 * gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+    assert_eq!(h.buffers_in_queue(), 8);
+}
+
+/* Here we test that the erase_display_memory output at EOS doesn't
+ * collapse onto the caption when the caption is shorter than the
+ * time needed to output it */
+#[test]
+fn test_short_caption_and_eos() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=pop-on");
+    h.set_src_caps_str("text/x-raw");
+
+    while h.events_in_queue() != 0 {
+        let _event = h.pull_event().unwrap();
+    }
+
+    let inbuf = new_timed_buffer(&"Hello", gst::SECOND, 10_000_000.into());
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    h.push_event(gst::event::Eos::new());
+
+    let mut outbufs = vec![];
+    while h.buffers_in_queue() > 0 {
+        outbufs.push(h.try_pull().unwrap());
+    }
+
+    for outbuf in outbufs.iter() {
+        assert_ne!(outbuf.get_duration(), 0.into());
+    }
+
+    for pair in outbufs.windows(2) {
+        assert_eq!(
+            pair[0].get_pts() + pair[0].get_duration() <= pair[1].get_pts(),
+            true
+        );
+    }
+
+    let n_buffers = outbufs.len();
+    assert_eq!(n_buffers >= 2, true);
+    for outbuf in &outbufs[n_buffers - 2..] {
+        let data = outbuf.map_readable().unwrap();
+        assert_eq!(&*data, &[0x94, 0x2c]);
+    }
+}
+
+/* Here we test that a caption held for lack of a duration ends with the
+ * next timed buffer, and that a flush discards it */
+#[test]
+fn test_no_duration_then_timed() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=pop-on");
+    h.set_src_caps_str("text/x-raw");
+
+    while h.events_in_queue() != 0 {
+        let _event = h.pull_event().unwrap();
+    }
+
+    let inbuf = new_timed_buffer(&"Hello", gst::SECOND, gst::CLOCK_TIME_NONE);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+    assert_eq!(h.buffers_in_queue(), 0);
+
+    let inbuf = new_timed_buffer(&"World", 3 * gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    let mut end_of_caption_pts = vec![];
+    while h.buffers_in_queue() > 0 {
+        let outbuf = h.pull().unwrap();
+        let data = outbuf.map_readable().unwrap();
+
+        if *data == [0x94, 0x2f] {
+            end_of_caption_pts.push(outbuf.get_pts());
+        }
+    }
+
+    assert_eq!(
+        end_of_caption_pts,
+        vec![
+            gst::SECOND,
+            1_033_333_333.into(),
+            3 * gst::SECOND,
+            3_033_333_333.into()
+        ]
+    );
+
+    let inbuf = new_timed_buffer(&"Held", 10 * gst::SECOND, gst::CLOCK_TIME_NONE);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    assert_eq!(h.push_event(gst::event::FlushStart::new()), true);
+    assert_eq!(h.push_event(gst::event::FlushStop::new(true)), true);
+    assert_eq!(
+        h.push_event(gst::event::Segment::new(&gst::FormattedSegment::<
+            gst::ClockTime,
+        >::new())),
+        true
+    );
+
+    /* Nothing left to drain */
+    h.push_event(gst::event::Eos::new());
+    while h.buffers_in_queue() > 0 {
+        let outbuf = h.pull().unwrap();
+        let data = outbuf.map_readable().unwrap();
+        assert_ne!(&*data, &[0x94, 0x2f]);
+    }
+}
+
+/* Here we test that markup is interpreted when the input caps say the
+ * text is pango markup, and encoded as is for plain utf8 text */
+#[test]
+fn test_markup_caps() {
+    init();
+
+    let cases = [
+        ("text/x-pango-markup", [0x94, 0xce], [0xc8, 0xe9]), /* italics preamble, H i */
+        (
+            "text/x-raw, format=pango-markup",
+            [0x94, 0xce],
+            [0xc8, 0xe9],
+        ),
+        ("text/x-raw, format=utf8", [0x94, 0x40], [0xbc, 0xe9]), /* preamble, < i */
+    ];
+
+    for (caps, preamble, first_chars) in cases.iter() {
+        let mut h = gst_check::Harness::new_parse("tttocea608 mode=pop-on");
+        h.set_src_caps_str(caps);
+
+        while h.events_in_queue() != 0 {
+            let _event = h.pull_event().unwrap();
+        }
+
+        let inbuf = new_timed_buffer(&"<i>Hi</i>", gst::SECOND, gst::SECOND);
+        assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+        /* resume_caption_loading and erase_non_displayed_memory */
+        for _ in 0..4 {
+            let _outbuf = h.try_pull().unwrap();
+        }
+
+        let outbuf = h.try_pull().unwrap();
+        assert_eq!(&*outbuf.map_readable().unwrap(), preamble, "{}", caps);
+        let outbuf = h.try_pull().unwrap();
+        assert_eq!(&*outbuf.map_readable().unwrap(), preamble, "{}", caps);
+        let outbuf = h.try_pull().unwrap();
+        assert_eq!(&*outbuf.map_readable().unwrap(), first_chars, "{}", caps);
+    }
+}
+
+/* Here we test that with synthesize-pts, a buffer without a PTS
+ * follows the previous one */
+#[test]
+fn test_synthesize_pts() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=pop-on synthesize-pts=true");
+    h.set_src_caps_str("text/x-raw");
+
+    while h.events_in_queue() != 0 {
+        let _event = h.pull_event().unwrap();
+    }
+
+    let inbuf = new_timed_buffer(&"Hello", gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    let inbuf = new_timed_buffer(&"World", gst::CLOCK_TIME_NONE, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    let mut end_of_caption_pts = vec![];
+    let mut last_pts = gst::ClockTime::from(0);
+    while h.buffers_in_queue() > 0 {
+        let outbuf = h.pull().unwrap();
+        assert_eq!(outbuf.get_pts() >= last_pts, true);
+        last_pts = outbuf.get_pts();
+
+        let data = outbuf.map_readable().unwrap();
+        if *data == [0x94, 0x2f] {
+            end_of_caption_pts.push(outbuf.get_pts());
+        }
+    }
+
+    assert_eq!(
+        end_of_caption_pts,
+        vec![
+            gst::SECOND,
+            1_033_333_333.into(),
+            2 * gst::SECOND,
+            2_033_333_333.into()
+        ]
+    );
+}
+
+/* Here we test that the framerate downstream lists first is picked,
+ * and that 30/1 is only preferred within a range */
+#[test]
+fn test_downstream_framerate() {
+    init();
+
+    for (sink_caps, framerate) in &[
+        (
+            "closedcaption/x-cea-608, format=raw, framerate=(fraction){ 25/1, 30/1 }",
+            gst::Fraction::new(25, 1),
+        ),
+        (
+            "closedcaption/x-cea-608, format=raw, framerate=(fraction)[ 20/1, 40/1 ]",
+            gst::Fraction::new(30, 1),
+        ),
+        (
+            "closedcaption/x-cea-608, framerate=(fraction)50/1",
+            gst::Fraction::new(50, 1),
+        ),
+    ] {
+        let mut h = gst_check::Harness::new_parse("tttocea608 mode=pop-on");
+        h.set_src_caps_str("text/x-raw");
+        h.set_sink_caps_str(sink_caps);
+
+        let mut n_caps = 0;
+        while h.events_in_queue() != 0 {
+            let event = h.pull_event().unwrap();
+            if let EventView::Caps(e) = event.view() {
+                let s = e.get_caps().get_structure(0).unwrap();
+                assert_eq!(
+                    s.get_some::<gst::Fraction>("framerate").unwrap(),
+                    *framerate
+                );
+                n_caps += 1;
+            }
+        }
+        assert_eq!(n_caps, 1);
+
+        let inbuf = new_timed_buffer(&"Hi", gst::SECOND, gst::SECOND);
+        assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+        /* The end_of_caption is output on the frame of the caption PTS */
+        let mut end_of_caption_pts = None;
+        while h.buffers_in_queue() > 0 {
+            let outbuf = h.pull().unwrap();
+            let data = outbuf.map_readable().unwrap();
+            if *data == [0x94, 0x2f] {
+                end_of_caption_pts.get_or_insert(outbuf.get_pts());
+            }
+        }
+        assert_eq!(end_of_caption_pts, Some(gst::SECOND));
+    }
+}
+
+/* Here we test that timestamps keep increasing when the framerate
+ * changes mid-stream */
+#[test]
+fn test_framerate_change() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=pop-on");
+    h.set_src_caps_str("text/x-raw");
+    h.set_sink_caps_str("closedcaption/x-cea-608, format=raw, framerate=30/1");
+
+    while h.events_in_queue() != 0 {
+        let _event = h.pull_event().unwrap();
+    }
+
+    let inbuf = new_timed_buffer(&"Hello", gst::SECOND, 2 * gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    h.set_sink_caps_str("closedcaption/x-cea-608, format=raw, framerate=25/1");
+    assert_eq!(
+        h.push_event(gst::event::Caps::new(&gst::Caps::new_simple(
+            "text/x-raw",
+            &[]
+        ))),
+        true
+    );
+
+    let inbuf = new_timed_buffer(&"World", 2 * gst::SECOND, 2 * gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+    h.push_event(gst::event::Eos::new());
+
+    let mut last_end = gst::ClockTime::from(0);
+    let mut end_of_caption_pts = vec![];
+    while h.buffers_in_queue() > 0 {
+        let outbuf = h.pull().unwrap();
+        assert_eq!(outbuf.get_pts() >= last_end, true);
+        last_end = outbuf.get_pts() + outbuf.get_duration();
+
+        let data = outbuf.map_readable().unwrap();
+        if *data == [0x94, 0x2f] {
+            end_of_caption_pts.push(outbuf.get_pts());
+        }
+    }
+
+    assert_eq!(
+        end_of_caption_pts,
+        vec![
+            gst::SECOND,
+            1_033_333_333.into(),
+            2 * gst::SECOND,
+            2_040_000_000.into()
+        ]
+    );
+}
+
+/* Here we test that after a flushing seek, no erase_display_memory is
+ * output for the flushed caption and timestamps follow the new segment */
+#[test]
+fn test_flush_seek() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=pop-on");
+    h.set_src_caps_str("text/x-raw");
+
+    let inbuf = new_timed_buffer(&"Hello", 10 * gst::SECOND, 5 * gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    while h.buffers_in_queue() > 0 {
+        let _outbuf = h.pull().unwrap();
+    }
+
+    assert_eq!(h.push_event(gst::event::FlushStart::new()), true);
+    assert_eq!(h.push_event(gst::event::FlushStop::new(true)), true);
+
+    let mut segment = gst::FormattedSegment::<gst::ClockTime>::new();
+    segment.set_start(20 * gst::SECOND);
+    segment.set_time(20 * gst::SECOND);
+    assert_eq!(h.push_event(gst::event::Segment::new(&segment)), true);
+
+    let inbuf = new_timed_buffer(&"Hi", 21 * gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+    h.push_event(gst::event::Eos::new());
+
+    let mut erase_display_pts = vec![];
+    let mut first_pts = gst::CLOCK_TIME_NONE;
+    while h.buffers_in_queue() > 0 {
+        let outbuf = h.pull().unwrap();
+        if first_pts == gst::CLOCK_TIME_NONE {
+            first_pts = outbuf.get_pts();
+        }
+
+        let data = outbuf.map_readable().unwrap();
+        if *data == [0x94, 0x2c] {
+            erase_display_pts.push(outbuf.get_pts());
+        }
+    }
+
+    /* Running time 1s, the caption starts on frame 23 */
+    assert_eq!(first_pts, 766_666_667.into());
+    /* Only "Hi" gets erased */
+    assert_eq!(
+        erase_display_pts,
+        vec![2 * gst::SECOND, 2_033_333_333.into()]
+    );
+}
+
+/* Here we test that with the video pads, the byte pairs for a frame are
+ * attached to the video buffer as a caption meta */
+#[test]
+fn test_caption_meta() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 name=enc mode=pop-on");
+    let bin = h.get_element().unwrap().downcast::<gst::Bin>().unwrap();
+    let element = bin.get_by_name("enc").unwrap();
+    let mut h_video =
+        gst_check::Harness::with_element(&element, Some("video_sink"), Some("video_src"));
+
+    h.set_src_caps_str("text/x-raw");
+    h_video.set_src_caps_str("video/x-raw, format=I420, width=320, height=240, framerate=30/1");
+
+    let inbuf = new_timed_buffer(&"Hi", gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    /* The frame of the first end_of_caption */
+    let inbuf = new_timed_buffer(vec![0u8; 16], gst::SECOND, 33_333_333.into());
+    assert_eq!(h_video.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    let outbuf = h_video.pull().unwrap();
+    assert_eq!(outbuf.get_pts(), gst::SECOND);
+
+    let meta = outbuf
+        .get_meta::<gst_video::VideoCaptionMeta>()
+        .expect("No caption meta");
+    assert_eq!(
+        meta.get_caption_type(),
+        gst_video::VideoCaptionType::Cea608Raw
+    );
+    assert_eq!(meta.get_data(), &[0x94, 0x2f]);
+
+    /* The regular output is still there */
+    assert_eq!(h.buffers_in_queue(), 9);
+}
+
+/* Here we test that when a new segment without a flush makes running
+ * time go back, the displayed caption gets erased and timestamps restart
+ * from the new segment */
+#[test]
+fn test_segment_loop() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=pop-on");
+    h.set_src_caps_str("text/x-raw");
+
+    while h.events_in_queue() != 0 {
+        let _event = h.pull_event().unwrap();
+    }
+
+    let inbuf = new_timed_buffer(&"Hello", 5 * gst::SECOND, 5 * gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    while h.buffers_in_queue() > 0 {
+        let _outbuf = h.pull().unwrap();
+    }
+
+    /* Looping back to the start */
+    assert_eq!(
+        h.push_event(gst::event::Segment::new(&gst::FormattedSegment::<
+            gst::ClockTime,
+        >::new())),
+        true
+    );
+
+    /* Erasing Hello */
+    assert_eq!(h.buffers_in_queue(), 2);
+    while h.buffers_in_queue() > 0 {
+        let outbuf = h.pull().unwrap();
+        let data = outbuf.map_readable().unwrap();
+        assert_eq!(&*data, &[0x94, 0x2c]);
+    }
+
+    let inbuf = new_timed_buffer(&"Hi", gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    let outbuf = h.pull().unwrap();
+    assert_eq!(outbuf.get_pts(), 766_666_667.into());
+    let data = outbuf.map_readable().unwrap();
+    assert_eq!(&*data, &[0x94, 0x20]);
+}
+
+/* Here we test that at 29.97 fps, output buffers and gap events cover
+ * the stream without holes or overlaps over 10 minutes */
+#[test]
+fn test_ntsc_framerate_gaps() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=pop-on");
+    h.set_src_caps_str("text/x-raw");
+    h.set_sink_caps_str("closedcaption/x-cea-608, format=raw, framerate=30000/1001");
+
+    while h.events_in_queue() != 0 {
+        let _event = h.pull_event().unwrap();
+    }
+
+    for i in 0..300 {
+        let pts = 2 * i * gst::SECOND + gst::SECOND;
+        let inbuf = new_timed_buffer(&"Hi", pts, gst::SECOND);
+        assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+    }
+
+    let mut intervals = vec![];
+    while h.buffers_in_queue() > 0 {
+        let outbuf = h.pull().unwrap();
+        intervals.push((outbuf.get_pts(), outbuf.get_pts() + outbuf.get_duration()));
+    }
+
+    while h.events_in_queue() > 0 {
+        let event = h.pull_event().unwrap();
+        if let EventView::Gap(e) = event.view() {
+            let (timestamp, duration) = e.get();
+            intervals.push((timestamp, timestamp + duration));
+        }
+    }
+
+    intervals.sort();
+
+    assert_eq!(intervals[0].0, 0.into());
+    for pair in intervals.windows(2) {
+        assert_eq!(pair[0].1, pair[1].0);
+    }
+
+    /* The last caption is displayed within a frame of 599 seconds, and
+     * its end_of_caption control code is doubled */
+    let frame_duration = gst::SECOND.mul_div_round(1001, 30000).unwrap();
+    let last_end = intervals.last().unwrap().1;
+    assert!(last_end > 599 * gst::SECOND);
+    assert!(last_end - 599 * gst::SECOND < 3 * frame_duration);
+}
+
+/* Here we test that in paint-on mode, a caption replacing a longer one
+ * that is still displayed overwrites it and deletes the rest of the row
+ * instead of erasing the display */
+#[test]
+fn test_paint_on_delete_to_end_of_row() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=paint-on");
+    h.set_src_caps_str("text/x-raw");
+
+    while h.events_in_queue() != 0 {
+        let _event = h.pull_event().unwrap();
+    }
+
+    let inbuf = new_timed_buffer(&"Hello World", gst::SECOND, 5 * gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    while h.buffers_in_queue() > 0 {
+        let _outbuf = h.pull().unwrap();
+    }
+
+    let inbuf = new_timed_buffer(&"Hi", 2 * gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    let expected: [[u8; 2]; 5] = [
+        [0x94, 0x40], /* preamble */
+        [0x94, 0x40], /* control doubled */
+        [0xc8, 0xe9], /* H i */
+        [0x94, 0xa4], /* delete_to_end_of_row */
+        [0x94, 0xa4], /* control doubled */
+    ];
+
+    for e in expected.iter() {
+        let outbuf = h.try_pull().unwrap();
+        let data = outbuf.map_readable().unwrap();
+        assert_eq!(e, &*data);
+    }
+
+    assert_eq!(h.buffers_in_queue(), 0);
+}
+
+/* Here we test that changing roll-up-rows while playing switches the
+ * roll-up code sent on the next carriage return */
+#[test]
+fn test_roll_up_rows_change() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 name=enc mode=roll-up2");
+    let bin = h.get_element().unwrap().downcast::<gst::Bin>().unwrap();
+    let element = bin.get_by_name("enc").unwrap();
+    h.set_src_caps_str("text/x-raw");
+
+    while h.events_in_queue() != 0 {
+        let _event = h.pull_event().unwrap();
+    }
+
+    let pull_cc_data = |h: &mut gst_check::Harness| {
+        let mut cc_data = vec![];
+        while h.buffers_in_queue() > 0 {
+            let outbuf = h.pull().unwrap();
+            let data = outbuf.map_readable().unwrap();
+            cc_data.push([data[0], data[1]]);
+        }
+        cc_data
+    };
+
+    let inbuf = new_timed_buffer(&"Hello", gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    let cc_data = pull_cc_data(&mut h);
+    assert!(cc_data.contains(&[0x94, 0x25])); /* roll_up_2 */
+
+    element.set_property("roll-up-rows", &4u32).unwrap();
+    assert_eq!(
+        element
+            .get_property("roll-up-window")
+            .unwrap()
+            .get_some::<u32>()
+            .unwrap(),
+        4
+    );
+
+    /* Too long to fit on the row after Hello */
+    let inbuf = new_timed_buffer(
+        &"abcdefghijklmnopqrstuvwxyzabcd",
+        2 * gst::SECOND,
+        gst::SECOND,
+    );
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    let cc_data = pull_cc_data(&mut h);
+    assert!(!cc_data.contains(&[0x94, 0x25])); /* roll_up_2 */
+    let roll_up = cc_data
+        .iter()
+        .position(|pair| *pair == [0x94, 0xa7]) /* roll_up_4 */
+        .unwrap();
+    assert_eq!(cc_data[roll_up + 2], [0x94, 0xad]); /* carriage_return */
+}
+
+/* Here we test that the stats property counts the output byte pairs,
+ * erase_display_memory sequences, dropped and replaced characters */
+#[test]
+fn test_stats() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 name=enc mode=pop-on");
+    let bin = h.get_element().unwrap().downcast::<gst::Bin>().unwrap();
+    let element = bin.get_by_name("enc").unwrap();
+    h.set_src_caps_str("text/x-raw");
+
+    while h.events_in_queue() != 0 {
+        let _event = h.pull_event().unwrap();
+    }
+
+    /* The snowman is replaced, X and Y are past the 32nd column */
+    let inbuf = new_timed_buffer(
+        &"☃bcdefghijklmnopqrstuvwxyzabcdefXY",
+        gst::SECOND,
+        gst::SECOND,
+    );
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+    h.push_event(gst::event::Eos::new());
+
+    let mut n_pairs = 0u64;
+    while h.buffers_in_queue() > 0 {
+        let _outbuf = h.pull().unwrap();
+        n_pairs += 1;
+    }
+
+    let stats = element
+        .get_property("stats")
+        .unwrap()
+        .get::<gst::Structure>()
+        .unwrap()
+        .unwrap();
+    assert_eq!(stats.get_some::<u64>("num-pairs").unwrap(), n_pairs);
+    assert_eq!(stats.get_some::<u64>("num-erase-display").unwrap(), 1);
+    assert_eq!(stats.get_some::<u64>("num-dropped").unwrap(), 2);
+    assert_eq!(stats.get_some::<u64>("num-replaced").unwrap(), 1);
+}
+
+/* Here we test that caption-rendered is emitted for each caption with
+ * its timing, row and text */
+#[test]
+fn test_caption_rendered() {
+    use std::sync::{Arc, Mutex};
+
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 name=enc mode=pop-on");
+    let bin = h.get_element().unwrap().downcast::<gst::Bin>().unwrap();
+    let element = bin.get_by_name("enc").unwrap();
+    h.set_src_caps_str("text/x-raw");
+
+    let rendered = Arc::new(Mutex::new(vec![]));
+    let rendered_clone = rendered.clone();
+    element
+        .connect("caption-rendered", false, move |args| {
+            rendered_clone.lock().unwrap().push((
+                args[1].get_some::<u64>().unwrap(),
+                args[2].get_some::<u64>().unwrap(),
+                args[3].get_some::<u32>().unwrap(),
+                args[4].get::<String>().unwrap().unwrap(),
+            ));
+            None
+        })
+        .unwrap();
+
+    let inbuf = new_timed_buffer(&"Hello", gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+    let inbuf = new_timed_buffer(&"World", 3 * gst::SECOND, 2 * gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    assert_eq!(
+        *rendered.lock().unwrap(),
+        vec![
+            (
+                gst::SECOND.unwrap(),
+                gst::SECOND.unwrap(),
+                13,
+                "Hello".to_string()
+            ),
+            (
+                3 * gst::SECOND.unwrap(),
+                2 * gst::SECOND.unwrap(),
+                13,
+                "World".to_string()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_charset() {
+    init();
+
+    /* Windows-1252 smart quotes, the curly double quotes are extended
+     * characters and get a substitute first */
+    assert_pop_on_cc_data(
+        "charset=cp1252",
+        &b"\x93Hi\x94"[..],
+        &[
+            [0x94, 0x20], /* resume_caption_loading */
+            [0x94, 0x20], /* control doubled */
+            [0x94, 0xae], /* erase_non_displayed_memory */
+            [0x94, 0xae], /* control doubled */
+            [0x94, 0x40], /* preamble, row 13 */
+            [0x94, 0x40], /* control doubled */
+            [0x20, 0x80], /* SPACE, nil */
+            [0x92, 0xae], /* “ */
+            [0xc8, 0xe9], /* H i */
+            [0x20, 0x80], /* SPACE, nil */
+            [0x92, 0x2f], /* ” */
+            [0x94, 0x2f], /* end_of_caption */
+            [0x94, 0x2f], /* control doubled */
+        ],
+    );
+
+    /* The right single quote is the basic apostrophe */
+    assert_pop_on_cc_data(
+        "charset=cp1252",
+        &b"It\x92s"[..],
+        &[
+            [0x94, 0x20], /* resume_caption_loading */
+            [0x94, 0x20], /* control doubled */
+            [0x94, 0xae], /* erase_non_displayed_memory */
+            [0x94, 0xae], /* control doubled */
+            [0x94, 0x40], /* preamble, row 13 */
+            [0x94, 0x40], /* control doubled */
+            [0x49, 0xf4], /* I t */
+            [0xa7, 0x73], /* ' s */
+            [0x94, 0x2f], /* end_of_caption */
+            [0x94, 0x2f], /* control doubled */
+        ],
+    );
+
+    /* 0xe9 is é in both Latin-1 and Windows-1252 */
+    assert_pop_on_cc_data(
+        "charset=latin1",
+        &b"\xe9t\xe9"[..],
+        &[
+            [0x94, 0x20], /* resume_caption_loading */
+            [0x94, 0x20], /* control doubled */
+            [0x94, 0xae], /* erase_non_displayed_memory */
+            [0x94, 0xae], /* control doubled */
+            [0x94, 0x40], /* preamble, row 13 */
+            [0x94, 0x40], /* control doubled */
+            [0xdc, 0xf4], /* é t */
+            [0xdc, 0x80], /* é, nil */
+            [0x94, 0x2f], /* end_of_caption */
+            [0x94, 0x2f], /* control doubled */
+        ],
+    );
+}
+
+#[test]
+fn test_double_control_codes() {
+    init();
+
+    assert_pop_on_cc_data(
+        "",
+        "Hi",
+        &[
+            [0x94, 0x20], /* resume_caption_loading */
+            [0x94, 0x20], /* control doubled */
+            [0x94, 0xae], /* erase_non_displayed_memory */
+            [0x94, 0xae], /* control doubled */
+            [0x94, 0x40], /* preamble, row 13 */
+            [0x94, 0x40], /* control doubled */
+            [0xc8, 0xe9], /* H i */
+            [0x94, 0x2f], /* end_of_caption */
+            [0x94, 0x2f], /* control doubled */
+        ],
+    );
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=pop-on double-control-codes=false");
+    h.set_src_caps_str("text/x-raw");
+
+    while h.events_in_queue() != 0 {
+        let _event = h.pull_event().unwrap();
+    }
+
+    let inbuf = new_timed_buffer(&"Hi", gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    /* The single end_of_caption is still output at the PTS of the caption */
+    let frame = |n: u64| (n * gst::SECOND).mul_div_round(1, 30).unwrap();
+    assert_eq!(
+        pull_cc_data(&mut h),
+        vec![
+            (frame(26), 0x94, 0x20), /* resume_caption_loading */
+            (frame(27), 0x94, 0xae), /* erase_non_displayed_memory */
+            (frame(28), 0x94, 0x40), /* preamble, row 13 */
+            (frame(29), 0xc8, 0xe9), /* H i */
+            (frame(30), 0x94, 0x2f), /* end_of_caption */
+        ]
+    );
+
+    /* The display is erased once, at the end of the caption */
+    h.push_event(gst::event::Eos::new());
+    assert_eq!(
+        pull_cc_data(&mut h),
+        vec![(frame(60), 0x94, 0x2c)] /* erase_display_memory */
+    );
+}
+
+/* Here we test that caption-stats reports what each caption took to
+ * encode, including the text dropped past the 32nd column */
+#[test]
+fn test_caption_stats() {
+    use std::sync::{Arc, Mutex};
+
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 name=enc mode=pop-on");
+    let bin = h.get_element().unwrap().downcast::<gst::Bin>().unwrap();
+    let element = bin.get_by_name("enc").unwrap();
+    h.set_src_caps_str("text/x-raw");
+
+    let stats = Arc::new(Mutex::new(vec![]));
+    let stats_clone = stats.clone();
+    element
+        .connect("caption-stats", false, move |args| {
+            let s = args[2].get::<gst::Structure>().unwrap().unwrap();
+            stats_clone.lock().unwrap().push((
+                args[1].get_some::<u64>().unwrap(),
+                s.get_some::<u64>("num-chars").unwrap(),
+                s.get_some::<u64>("num-dropped").unwrap(),
+                s.get_some::<u64>("num-replaced").unwrap(),
+                s.get_some::<u64>("num-rows").unwrap(),
+                s.get_some::<u64>("num-pairs").unwrap(),
+            ));
+            None
+        })
+        .unwrap();
+
+    let inbuf = new_timed_buffer(&"Hello\nWorld", gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+    let inbuf = new_timed_buffer(
+        &"abcdefghijklmnopqrstuvwxyzabcdefXY",
+        3 * gst::SECOND,
+        gst::SECOND,
+    );
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    /* The second caption's byte pairs include the erase_display_memory
+     * of the first one */
+    assert_eq!(
+        *stats.lock().unwrap(),
+        vec![
+            (gst::SECOND.unwrap(), 10, 0, 0, 2, 16),
+            ((3 * gst::SECOND).unwrap(), 32, 2, 0, 1, 26),
+        ]
+    );
+}
+
+/* Preambles, including the tab offset of an indented row, follow the
+ * same doubling as the other control codes */
+#[test]
+fn test_double_preambles() {
+    init();
+
+    assert_pop_on_cc_data(
+        "origin-column=1",
+        "Hello\nWorld",
+        &[
+            [0x94, 0x20], /* resume_caption_loading */
+            [0x94, 0x20], /* control doubled */
+            [0x94, 0xae], /* erase_non_displayed_memory */
+            [0x94, 0xae], /* control doubled */
+            [0x94, 0x40], /* preamble, row 13 */
+            [0x94, 0x40], /* control doubled */
+            [0x97, 0xa1], /* tab offset 1 */
+            [0x97, 0xa1], /* control doubled */
+            [0xc8, 0xe5], /* H e */
+            [0xec, 0xec], /* l l */
+            [0xef, 0x80], /* o, nil */
+            [0x94, 0xe0], /* preamble, row 14 */
+            [0x94, 0xe0], /* control doubled */
+            [0x97, 0xa1], /* tab offset 1 */
+            [0x97, 0xa1], /* control doubled */
+            [0x57, 0xef], /* W o */
+            [0xf2, 0xec], /* r l */
+            [0x64, 0x80], /* d, nil */
+            [0x94, 0x2f], /* end_of_caption */
+            [0x94, 0x2f], /* control doubled */
+        ],
+    );
+
+    assert_pop_on_cc_data(
+        "origin-column=1 double-control-codes=false",
+        "Hello\nWorld",
+        &[
+            [0x94, 0x20], /* resume_caption_loading */
+            [0x94, 0xae], /* erase_non_displayed_memory */
+            [0x94, 0x40], /* preamble, row 13 */
+            [0x97, 0xa1], /* tab offset 1 */
+            [0xc8, 0xe5], /* H e */
+            [0xec, 0xec], /* l l */
+            [0xef, 0x80], /* o, nil */
+            [0x94, 0xe0], /* preamble, row 14 */
+            [0x97, 0xa1], /* tab offset 1 */
+            [0x57, 0xef], /* W o */
+            [0xf2, 0xec], /* r l */
+            [0x64, 0x80], /* d, nil */
+            [0x94, 0x2f], /* end_of_caption */
+        ],
+    );
+}
+
+/* Roll-up captions stay displayed at the end of the stream unless
+ * clear-on-eos is set, then the display is erased after them */
+#[test]
+fn test_clear_on_eos() {
+    init();
+
+    let frame = |n: u64| (n * gst::SECOND).mul_div_round(1, 30).unwrap();
+
+    for clear_on_eos in &[false, true] {
+        let mut h = gst_check::Harness::new_parse(&format!(
+            "tttocea608 mode=roll-up2 clear-on-eos={}",
+            clear_on_eos
+        ));
+        h.set_src_caps_str("text/x-raw");
+
+        let inbuf = new_timed_buffer(&"Hello", gst::SECOND, gst::SECOND);
+        assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+        h.push_event(gst::event::Eos::new());
+
+        let erasures = pull_cc_data(&mut h)
+            .into_iter()
+            .filter(|(pts, _, _)| *pts >= 2 * gst::SECOND)
+            .collect::<Vec<_>>();
+
+        if *clear_on_eos {
+            assert_eq!(
+                erasures,
+                vec![
+                    (frame(60), 0x94, 0x2c), /* erase_display_memory */
+                    (frame(61), 0x94, 0x2c), /* control doubled */
+                ]
+            );
+        } else {
+            assert_eq!(erasures, vec![]);
+        }
+    }
+}
+
+/* Here we test that an XDS program name packet is interleaved on the
+ * second field of cc_data output, and repeated */
+#[test]
+fn test_xds_program_name() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=pop-on xds-program-name=Hi");
+    h.set_src_caps_str("text/x-raw");
+    h.set_sink_caps_str("closedcaption/x-cea-708, format=cc_data");
+
+    let inbuf = new_timed_buffer(&"Hi", gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    let mut field1 = vec![];
+    let mut field2 = vec![];
+    while h.buffers_in_queue() > 0 {
+        let outbuf = h.pull().unwrap();
+        let data = outbuf.map_readable().unwrap();
+        assert_eq!(data.len(), 6);
+        assert_eq!(data[0], 0xfc);
+        assert_eq!(data[3], 0xfd);
+        field1.push([data[1], data[2]]);
+        field2.push([data[4], data[5]]);
+    }
+
+    assert_eq!(field1.len(), 9);
+    assert_eq!(field1[6], [0xc8, 0xe9]); /* H i */
+
+    let packet = [
+        [0x01, 0x83], /* current class start, program name */
+        [0xc8, 0xe9], /* H i */
+        [0x8f, 0xbc], /* end, checksum */
+    ];
+    assert_eq!(&field2[..3], &packet[..]);
+    assert_eq!(&field2[3..6], &packet[..]);
+    assert_eq!(&field2[6..9], &packet[..]);
+
+    /* All the bytes of the packet, without parity, sum to 0 */
+    let sum = packet
+        .iter()
+        .flatten()
+        .fold(0u8, |sum, byte| sum.wrapping_add(byte & 0x7f));
+    assert_eq!(sum & 0x7f, 0);
+
+    /* The second field is taken by the captions themselves on CC3 */
+    let mut h =
+        gst_check::Harness::new_parse("tttocea608 mode=pop-on channel=3 xds-program-name=Hi");
+    h.set_src_caps_str("text/x-raw");
+    h.set_sink_caps_str("closedcaption/x-cea-708, format=cc_data");
+
+    let inbuf = new_timed_buffer(&"Hi", gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    while h.buffers_in_queue() > 0 {
+        let outbuf = h.pull().unwrap();
+        assert_eq!(outbuf.get_size(), 3);
+    }
+}
+
+/* Here we test that max-on-screen erases captions that would otherwise
+ * stay displayed during a long gap */
+#[test]
+fn test_max_on_screen() {
+    init();
+
+    let frame = |n: u64| (n * gst::SECOND).mul_div_round(1, 30).unwrap();
+
+    /* A long pop-on caption is cut short */
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=pop-on max-on-screen=2000000000");
+    h.set_src_caps_str("text/x-raw");
+
+    let inbuf = new_timed_buffer(&"Hello", gst::SECOND, 10 * gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+    let _ = pull_cc_data(&mut h);
+
+    h.push_event(gst::event::Eos::new());
+    assert_eq!(
+        pull_cc_data(&mut h),
+        vec![
+            (frame(90), 0x94, 0x2c), /* erase_display_memory */
+            (frame(91), 0x94, 0x2c), /* control doubled */
+        ]
+    );
+
+    /* Roll-up captions are erased once the gap covers their expiry,
+     * and the next one starts over on an empty window */
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=roll-up2 max-on-screen=2000000000");
+    h.set_src_caps_str("text/x-raw");
+
+    let inbuf = new_timed_buffer(&"Hello", gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+    let _ = pull_cc_data(&mut h);
+
+    let gap_event = gst::event::Gap::new(2 * gst::SECOND, 8 * gst::SECOND);
+    assert_eq!(h.push_event(gap_event), true);
+    assert_eq!(
+        pull_cc_data(&mut h),
+        vec![
+            (frame(90), 0x94, 0x2c), /* erase_display_memory */
+            (frame(91), 0x94, 0x2c), /* control doubled */
+        ]
+    );
+
+    let inbuf = new_timed_buffer(&"World", 11 * gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+    let cc_data = pull_cc_data(&mut h);
+    assert_eq!(cc_data[0], (frame(330), 0x94, 0x2c)); /* erase_display_memory */
+    assert_eq!(cc_data[2], (frame(332), 0x94, 0x25)); /* roll_up_2 */
+}
+
+/* Here we test that a caption overlapping the previous one replaces it
+ * without collapsing its byte pairs into 0 duration buffers, and that
+ * the previous caption's erasure is dropped */
+#[test]
+fn test_overlapping_captions() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=pop-on");
+    h.set_src_caps_str("text/x-raw");
+
+    let inbuf = new_timed_buffer(&"Hello", gst::SECOND, 2 * gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+    let inbuf = new_timed_buffer(&"World", 1_100_000_000.into(), gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+    h.push_event(gst::event::Eos::new());
+
+    let mut outbufs = vec![];
+    while h.buffers_in_queue() > 0 {
+        outbufs.push(h.pull().unwrap());
+    }
+
+    for outbuf in outbufs.iter() {
+        assert_ne!(outbuf.get_duration(), 0.into());
+    }
+
+    for pair in outbufs.windows(2) {
+        assert!(pair[0].get_pts() + pair[0].get_duration() <= pair[1].get_pts());
+    }
+
+    /* Only the second caption gets erased, at its end */
+    let frame = |n: u64| (n * gst::SECOND).mul_div_round(1, 30).unwrap();
+    let erasures = outbufs
+        .iter()
+        .filter(|outbuf| *outbuf.map_readable().unwrap() == [0x94, 0x2c])
+        .map(|outbuf| outbuf.get_pts())
+        .collect::<Vec<_>>();
+    assert_eq!(erasures, vec![frame(63), frame(64)]);
+}
+
+/* Here we test that in roll-up mode, a caption filling its row doesn't
+ * scroll the window up, the next caption does it at its PTS, leaving
+ * the third line on the base row */
+#[test]
+fn test_roll_up_scroll_timing() {
+    init();
+
+    let frame = |n: u64| (n * gst::SECOND).mul_div_round(1, 30).unwrap();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=roll-up2");
+    h.set_src_caps_str("text/x-raw");
+
+    let inbuf = new_timed_buffer("A".repeat(32), gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    let cc_data = pull_cc_data(&mut h);
+    assert_eq!(cc_data.len(), 22);
+    assert!(cc_data
+        .iter()
+        .all(|(_, cc1, cc2)| (*cc1, *cc2) != (0x94, 0xad)));
+    assert_eq!(cc_data[21], (frame(51), 0xc1, 0xc1)); /* A A */
+
+    for (i, (text, pair)) in [("B", [0xc2, 0xc2]), ("C", [0x43, 0x43])]
+        .iter()
+        .enumerate()
+    {
+        let frame_no = 90 + 60 * i as u64;
+
+        let inbuf = new_timed_buffer(
+            text.repeat(32),
+            (3 + 2 * i as u64) * gst::SECOND,
+            gst::SECOND,
+        );
+        assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+        let cc_data = pull_cc_data(&mut h);
+        assert_eq!(cc_data.len(), 22);
+        assert_eq!(
+            &cc_data[..7],
+            &[
+                (frame(frame_no), 0x94, 0x25),     /* roll_up_2 */
+                (frame(frame_no + 1), 0x94, 0x25), /* control doubled */
+                (frame(frame_no + 2), 0x94, 0xad), /* carriage_return */
+                (frame(frame_no + 3), 0x94, 0xad), /* control doubled */
+                (frame(frame_no + 4), 0x94, 0xe0), /* preamble, row 14 */
+                (frame(frame_no + 5), 0x94, 0xe0), /* control doubled */
+                (frame(frame_no + 6), pair[0], pair[1]),
+            ][..]
+        );
+    }
+}
+
+/* Here we test that a pop-on caption whose characters all get dropped,
+ * as its only line starts past the last column, is replaced with a gap
+ * over its duration instead of a flip of an empty caption */
+#[test]
+fn test_dropped_caption_gap() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=pop-on latency=10");
+    h.set_src_caps_str("text/x-raw");
+
+    while h.events_in_queue() != 0 {
+        let _event = h.pull_event().unwrap();
+    }
+
+    /* Ends on frame 60, 10 frames of latency */
+    let inbuf = new_timed_buffer(
+        format!("{}Hello", " ".repeat(40)),
+        0.into(),
+        2 * gst::SECOND,
+    );
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    assert_eq!(h.buffers_in_queue(), 0);
+    assert_eq!(h.events_in_queue(), 1);
+    let event = h.pull_event().unwrap();
+    if let EventView::Gap(ev) = event.view() {
+        assert_eq!(ev.get(), (0.into(), 1_666_666_667.into()));
+    } else {
+        panic!("Expected a gap event, got {:?}", event);
+    }
+}
+
+/* A dropped caption following a displayed one leaves it on screen
+ * and pushes a gap up to its pending erase_display_memory */
+#[test]
+fn test_dropped_caption_gap_pending_erase() {
+    init();
+
+    let frame = |n: u64| (n * gst::SECOND).mul_div_round(1, 30).unwrap();
+
+    let mut h = gst_check::Harness::new_parse("tttocea608 mode=pop-on latency=10");
+    h.set_src_caps_str("text/x-raw");
+
+    /* Flips on frame 32, erased on frame 62 */
+    let inbuf = new_timed_buffer(&"Hi", gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+    let _ = pull_cc_data(&mut h);
+
+    while h.events_in_queue() != 0 {
+        let _event = h.pull_event().unwrap();
+    }
+
+    /* Ends on frame 71 minus 10 frames of latency, past the frames of
+     * the erase_display_memory control codes */
+    let inbuf = new_timed_buffer(
+        format!("{}Hello", " ".repeat(40)),
+        1_500_000_000.into(),
+        866_666_667.into(),
+    );
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    assert_eq!(h.buffers_in_queue(), 0);
+    assert_eq!(h.events_in_queue(), 1);
+    let event = h.pull_event().unwrap();
+    if let EventView::Gap(ev) = event.view() {
+        assert_eq!(ev.get(), (frame(32), frame(60) - frame(32)));
+    } else {
+        panic!("Expected a gap event, got {:?}", event);
+    }
+
+    h.push_event(gst::event::Eos::new());
+    assert_eq!(
+        pull_cc_data(&mut h),
+        vec![
+            (frame(60), 0x94, 0x2c), /* erase_display_memory */
+            (frame(61), 0x94, 0x2c), /* control doubled */
+        ]
+    );
+}
@@ -0,0 +1,234 @@
+// Copyright (C) 2020 Mathieu Duponchelle <mathieu@centricular.com>
+//
+// This library is free software; you can redistribute it and/or
+// modify it under the terms of the GNU Library General Public
+// License as published by the Free Software Foundation; either
+// version 2 of the License, or (at your option) any later version.
+//
+// This library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+// Library General Public License for more details.
+//
+// You should have received a copy of the GNU Library General Public
+// License along with this library; if not, write to the
+// Free Software Foundation, Inc., 51 Franklin Street, Suite 500,
+// Boston, MA 02110-1335, USA.
+
+#[macro_use]
+extern crate pretty_assertions;
+
+fn init() {
+    use std::sync::Once;
+    static INIT: Once = Once::new();
+
+    INIT.call_once(|| {
+        gst::init().unwrap();
+        gstrsclosedcaption::plugin_register_static().unwrap();
+    });
+}
+
+fn new_timed_buffer<T: AsRef<[u8]> + Send + 'static>(
+    slice: T,
+    timestamp: gst::ClockTime,
+    duration: gst::ClockTime,
+) -> gst::buffer::Buffer {
+    let mut buf = gst::Buffer::from_slice(slice);
+    let buf_ref = buf.get_mut().unwrap();
+    buf_ref.set_pts(timestamp);
+    buf_ref.set_duration(duration);
+    buf
+}
+
+/* Reassembles the DTVCC packets carried by the cc_data triples, and
+ * returns the service number and data of their service blocks */
+fn service_blocks(data: &[u8]) -> Vec<(u8, Vec<u8>)> {
+    let mut packets: Vec<Vec<u8>> = vec![];
+
+    for triple in data.chunks(3) {
+        match triple[0] {
+            0xff => packets.push(vec![triple[1], triple[2]]),
+            0xfe => packets.last_mut().unwrap().extend_from_slice(&triple[1..]),
+            0xfa => (),
+            cc_type => panic!("Unexpected cc_type {:x}", cc_type),
+        }
+    }
+
+    let mut blocks = vec![];
+    for packet in packets {
+        let size = match packet[0] & 0x3f {
+            0 => 128,
+            size_code => size_code as usize * 2,
+        };
+        assert_eq!(packet.len(), size);
+
+        let mut data = &packet[1..];
+        while !data.is_empty() {
+            let mut service_number = data[0] >> 5;
+            let block_size = (data[0] & 0x1f) as usize;
+            data = &data[1..];
+
+            if service_number == 0 {
+                break;
+            } else if service_number == 7 {
+                service_number = data[0] & 0x3f;
+                data = &data[1..];
+            }
+
+            blocks.push((service_number, data[..block_size].to_vec()));
+            data = &data[block_size..];
+        }
+    }
+
+    blocks
+}
+
+#[test]
+fn test_one_caption() {
+    init();
+
+    let mut h = gst_check::Harness::new("tttocea708");
+    h.set_src_caps_str("text/x-raw");
+
+    let inbuf = new_timed_buffer(&"Hi", gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+    h.push_event(gst::event::Eos::new());
+
+    let expected: [(gst::ClockTime, &[u8]); 3] = [
+        (
+            966_666_667.into(),
+            &[
+                0x98, 0x18, 0xda, 0x32, 0x70, 0x1f, 0x09, /* define_window 0, hidden */
+                0x88, 0x01, /* clear_windows 0 */
+                0x92, 0x00, 0x00, /* set_pen_location 0, 0 */
+                0x48, 0x69, /* H i */
+            ],
+        ),
+        (gst::SECOND, &[0x89, 0x01]),     /* display_windows 0 */
+        (2 * gst::SECOND, &[0x8c, 0x01]), /* delete_windows 0 */
+    ];
+
+    for (i, e) in expected.iter().enumerate() {
+        let outbuf = h.try_pull().unwrap();
+
+        assert_eq!(
+            e.0,
+            outbuf.get_pts(),
+            "Unexpected PTS for {}th buffer",
+            i + 1
+        );
+
+        let data = outbuf.map_readable().unwrap();
+        /* 20 triples per frame at 30 fps */
+        assert_eq!(data.len(), 60);
+        assert_eq!(service_blocks(&*data), vec![(1, e.1.to_vec())]);
+    }
+
+    assert_eq!(h.buffers_in_queue(), 0);
+
+    let caps = h
+        .get_sinkpad()
+        .expect("harness has no sinkpad")
+        .get_current_caps()
+        .expect("pad has no caps");
+    assert_eq!(
+        caps,
+        gst::Caps::builder("closedcaption/x-cea-708")
+            .field("format", &"cc_data")
+            .field("framerate", &gst::Fraction::new(30, 1))
+            .build()
+    );
+}
+
+/* Here we test that a caption replacing a displayed one is loaded into
+ * the other window, and that services above 6 use the extended header */
+#[test]
+fn test_replace_caption() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse("tttocea708 service-number=10");
+    h.set_src_caps_str("text/x-raw");
+
+    let inbuf = new_timed_buffer(&"Hello", gst::SECOND, 5 * gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+    let inbuf = new_timed_buffer(&"World", 2 * gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+
+    let mut blocks = vec![];
+    while h.buffers_in_queue() > 0 {
+        let outbuf = h.pull().unwrap();
+        let data = outbuf.map_readable().unwrap();
+        blocks.push((outbuf.get_pts(), service_blocks(&*data)));
+    }
+
+    assert_eq!(blocks.len(), 4);
+    for (_, service_blocks) in blocks.iter() {
+        for (service_number, _) in service_blocks {
+            assert_eq!(*service_number, 10);
+        }
+    }
+
+    /* define_window 1 */
+    assert_eq!((blocks[2].1)[0].1[0], 0x99);
+
+    /* delete_windows 0 and display_windows 1 */
+    assert_eq!(blocks[3].0, 2 * gst::SECOND);
+    assert_eq!(blocks[3].1, vec![(10, vec![0x8c, 0x01, 0x89, 0x02])]);
+}
+
+/* Here we test that CDP output wraps each frame's cc_data in a packet
+ * with a time code and an incrementing sequence counter */
+#[test]
+fn test_cdp() {
+    init();
+
+    let mut h = gst_check::Harness::new("tttocea708");
+    h.set_src_caps_str("text/x-raw");
+    h.set_sink_caps_str("closedcaption/x-cea-708, format=cdp, framerate=30/1");
+
+    let inbuf = new_timed_buffer(&"Hi", gst::SECOND, gst::SECOND);
+    assert_eq!(h.push(inbuf), Ok(gst::FlowSuccess::Ok));
+    h.push_event(gst::event::Eos::new());
+
+    let expected: [(gst::ClockTime, [u8; 4], &[u8]); 3] = [
+        (
+            966_666_667.into(),
+            [0xc0, 0x80, 0x00, 0x29],
+            &[
+                0x98, 0x18, 0xda, 0x32, 0x70, 0x1f, 0x09, 0x88, 0x01, 0x92, 0x00, 0x00, 0x48, 0x69,
+            ],
+        ),
+        (gst::SECOND, [0xc0, 0x80, 0x01, 0x00], &[0x89, 0x01]),
+        (2 * gst::SECOND, [0xc0, 0x80, 0x02, 0x00], &[0x8c, 0x01]),
+    ];
+
+    for (i, e) in expected.iter().enumerate() {
+        let outbuf = h.try_pull().unwrap();
+
+        assert_eq!(
+            e.0,
+            outbuf.get_pts(),
+            "Unexpected PTS for {}th buffer",
+            i + 1
+        );
+
+        let data = outbuf.map_readable().unwrap();
+        let len = 7 + 5 + 2 + 3 * 20 + 4;
+        assert_eq!(data.len(), len);
+        assert_eq!(
+            data[..7],
+            [0x96, 0x69, len as u8, 0x5f, 0xc3, 0x00, i as u8]
+        );
+        assert_eq!(data[7], 0x71);
+        assert_eq!(data[8..12], e.1);
+        assert_eq!(data[12..14], [0x72, 0xf4]);
+        assert_eq!(service_blocks(&data[14..74]), vec![(1, e.2.to_vec())]);
+        assert_eq!(data[74..77], [0x74, 0x00, i as u8]);
+        assert_eq!(
+            data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)),
+            0
+        );
+    }
+
+    assert_eq!(h.buffers_in_queue(), 0);
+}